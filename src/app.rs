@@ -1,16 +1,17 @@
-use crate::config::{
-    setup_theme, ASSET_EXTENSION, REPO_OPTIONS, TEMP_PREFIX, VOLUME_LABEL, DEFAULT_REPO_INDEX,
-};
-use crate::copy::{copy_directory_with_progress, CopyProgress};
+use crate::archive::Component;
+use crate::benchmark::BenchmarkReport;
+use crate::cache::DownloadCache;
+use crate::capacity::{CAPACITY_CHECK_SUPPORTED, CAPACITY_CHECK_UNAVAILABLE};
+use crate::config::{setup_theme, APP_NAME, CHECK_CAPACITY, REPO_OPTIONS, DEFAULT_REPO_INDEX};
 use crate::drives::{get_removable_drives, DriveInfo};
 use crate::eject::eject_drive;
-use crate::extract::{extract_7z_with_progress, ExtractProgress};
-use crate::format::{format_drive_fat32, FormatProgress};
-use crate::github::{download_asset, find_release_asset, get_latest_release, DownloadProgress};
+use crate::github::Release;
+use crate::image::ImageTarget;
+use crate::source::list_releases;
+use crate::pipeline::{InstallMode, InstallOptions, InstallPipeline, InstallStage, PipelineError, PipelineEvent};
 use eframe::egui;
-use egui_thematic::{ThemeConfig, ThemeEditorState, render_theme_panel};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use egui_thematic::{ThemeConfig, ThemeEditorState, render_theme_panel};
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
@@ -21,9 +22,16 @@ enum AppState {
     AwaitingConfirmation,
     FetchingRelease,
     Downloading,
+    Verifying,
+    CheckingArchive,
+    CheckingCard,
+    CheckingCapacity,
     Formatting,
     Extracting,
     Copying,
+    VerifyingCard,
+    Compressing,
+    Benchmarking,
     Complete,
     Ejecting,
     Ejected,
@@ -31,6 +39,29 @@ enum AppState {
     Error,
 }
 
+impl From<InstallStage> for AppState {
+    fn from(stage: InstallStage) -> Self {
+        match stage {
+            InstallStage::FetchingRelease => AppState::FetchingRelease,
+            InstallStage::Downloading => AppState::Downloading,
+            InstallStage::Verifying => AppState::Verifying,
+            InstallStage::CheckingArchive => AppState::CheckingArchive,
+            InstallStage::CheckingCard => AppState::CheckingCard,
+            InstallStage::CheckingCapacity => AppState::CheckingCapacity,
+            InstallStage::Formatting => AppState::Formatting,
+            InstallStage::Extracting => AppState::Extracting,
+            InstallStage::Copying => AppState::Copying,
+            InstallStage::VerifyingCard => AppState::VerifyingCard,
+            InstallStage::Compressing => AppState::Compressing,
+            InstallStage::Benchmarking => AppState::Benchmarking,
+        }
+    }
+}
+
+/// Image sizes offered in the confirmation dialog, in GB as printed on cards
+const IMAGE_SIZES_GB: [u64; 5] = [2, 4, 8, 16, 32];
+const DEFAULT_IMAGE_SIZE_GB: u64 = 4;
+
 #[derive(Debug, Clone)]
 struct ProgressInfo {
     current: u64,
@@ -45,12 +76,39 @@ pub struct InstallerApp {
     // UI State
     drives: Vec<DriveInfo>,
     selected_drive_idx: Option<usize>,
+    // Disk image picked in the drive list, built instead of writing to the selected drive
+    image_target: Option<ImageTarget>,
     selected_repo_idx: usize,
 
+    // Releases of the selected repository for the version picker (None while loading)
+    releases: Option<Result<Vec<Release>, String>>,
+    // None installs the latest release
+    selected_tag: Option<String>,
+    // Release archive picked from disk or dropped on the window (overrides the version picker)
+    local_archive: Option<PathBuf>,
+    // Parts of the local archive or the downloaded release, and the ones the user unticked
+    components: Vec<Component>,
+    excluded_components: Vec<String>,
+    show_components: bool,
+    releases_tx: mpsc::UnboundedSender<(usize, Result<Vec<Release>, String>)>,
+    releases_rx: mpsc::UnboundedReceiver<(usize, Result<Vec<Release>, String>)>,
+
+    // Fresh install, update in place or verification of the selected card (or only
+    // downloading the release to list its components)
+    mode: InstallMode,
+    // Test the card's real capacity before a fresh install
+    check_capacity: bool,
+    // Results of the last benchmark run
+    benchmark: Option<BenchmarkReport>,
+
     // Progress tracking
     state: AppState,
-    progress: Arc<Mutex<ProgressInfo>>,
-    log_messages: Arc<Mutex<Vec<String>>>,
+    progress: ProgressInfo,
+    log_messages: Vec<String>,
+
+    // Events from the running install pipeline and the result of an eject
+    install_rx: Option<mpsc::UnboundedReceiver<PipelineEvent>>,
+    eject_rx: Option<mpsc::UnboundedReceiver<Result<(), String>>>,
 
     // Drive that was installed to (for eject)
     installed_drive: Option<DriveInfo>,
@@ -67,57 +125,13 @@ pub struct InstallerApp {
     show_theme_editor: bool,
     show_log: bool,
     last_system_dark_mode: bool,
-}
 
-/// Get available disk space for a given path (in bytes)
-fn get_available_disk_space(path: &std::path::Path) -> u64 {
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::ffi::OsStrExt;
-        use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
-
-        let path_wide: Vec<u16> = path.as_os_str()
-            .encode_wide()
-            .chain(Some(0))
-            .collect();
-
-        let mut free_bytes = 0u64;
-        unsafe {
-            if GetDiskFreeSpaceExW(
-                windows::core::PCWSTR(path_wide.as_ptr()),
-                None,
-                None,
-                Some(&mut free_bytes),
-            ).is_ok() {
-                return free_bytes;
-            }
-        }
-        crate::debug::log("WARNING: Failed to get disk space on Windows, assuming sufficient space");
-        u64::MAX // Assume sufficient space if we can't check
-    }
-
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
-    {
-        use std::os::unix::ffi::OsStrExt;
-        let path_cstr = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap_or_default();
-        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
-
-        unsafe {
-            if libc::statvfs(path_cstr.as_ptr(), &mut stat) == 0 {
-                // Available space = block size * available blocks
-                // Cast both to u64 to handle platforms where they're u32 (macOS, ARM32)
-                return (stat.f_bavail as u64) * (stat.f_bsize as u64);
-            }
-        }
-        crate::debug::log("WARNING: Failed to get disk space on Unix, assuming sufficient space");
-        u64::MAX // Assume sufficient space if we can't check
-    }
+    // Size of the download cache, shown on the clear cache button
+    cache_size: u64,
 
-    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
-    {
-        crate::debug::log("WARNING: Disk space check not supported on this platform");
-        u64::MAX // Assume sufficient space on unsupported platforms
-    }
+    // GitHub token window (the token itself lives in github::set_api_token)
+    show_token_editor: bool,
+    token_input: String,
 }
 
 impl InstallerApp {
@@ -151,20 +165,35 @@ impl InstallerApp {
         });
 
         let is_dark = cc.egui_ctx.style().visuals.dark_mode;
+        let (releases_tx, releases_rx) = mpsc::unbounded_channel();
         
         // Initial app creation to use helper method
         let mut app = Self {
             runtime,
             drives: Vec::new(),
             selected_drive_idx: None,
+            image_target: None,
             selected_repo_idx: DEFAULT_REPO_INDEX,
+            releases: None,
+            selected_tag: None,
+            local_archive: None,
+            components: Vec::new(),
+            excluded_components: Vec::new(),
+            show_components: false,
+            releases_tx,
+            releases_rx,
+            mode: InstallMode::Fresh,
+            check_capacity: CHECK_CAPACITY,
+            benchmark: None,
             state: AppState::Idle,
-            progress: Arc::new(Mutex::new(ProgressInfo {
+            progress: ProgressInfo {
                 current: 0,
                 total: 100,
                 message: String::new(),
-            })),
-            log_messages: Arc::new(Mutex::new(Vec::new())),
+            },
+            log_messages: Vec::new(),
+            install_rx: None,
+            eject_rx: None,
             installed_drive: None,
             cancel_token: None,
             drive_rx: rx,
@@ -173,6 +202,9 @@ impl InstallerApp {
             show_theme_editor: false,
             show_log: false,
             last_system_dark_mode: is_dark,
+            cache_size: DownloadCache::open_default().total_size(),
+            show_token_editor: false,
+            token_input: String::new(),
         };
 
         app.theme_state.current_config = app.get_theme_config(is_dark);
@@ -180,6 +212,7 @@ impl InstallerApp {
         // Initial sync load
         app.drives = get_removable_drives();
         app.ensure_selection_valid();
+        app.refresh_releases(cc.egui_ctx.clone());
         
         app
     }
@@ -210,749 +243,259 @@ impl InstallerApp {
         }
     }
 
-    fn log(&self, msg: &str) {
-        if let Ok(mut logs) = self.log_messages.lock() {
-            logs.push(msg.to_string());
-            // Keep only last 100 messages
-            if logs.len() > 100 {
-                logs.remove(0);
-            }
+    fn log(&mut self, msg: &str) {
+        self.log_messages.push(msg.to_string());
+        // Keep only last 100 messages
+        if self.log_messages.len() > 100 {
+            self.log_messages.remove(0);
         }
     }
 
-    fn cancel_installation(&mut self) {
-        if let Some(token) = &self.cancel_token {
-            self.log("Cancelling installation...");
-            token.cancel();
-            self.state = AppState::Cancelling;
+    /// Fetch the release list of the selected repository in the background
+    fn refresh_releases(&mut self, ctx: egui::Context) {
+        self.releases = None;
+        self.selected_tag = None;
+        if self.local_archive.is_none() {
+            self.clear_components();
         }
-    }
 
-    fn start_installation(&mut self, ctx: egui::Context) {
-        let Some(drive_idx) = self.selected_drive_idx else {
-            self.log("No drive selected");
-            return;
-        };
+        let repo_idx = self.selected_repo_idx;
+        let repo_url = REPO_OPTIONS[repo_idx].1;
+        let tx = self.releases_tx.clone();
+        self.runtime.spawn(async move {
+            let result = list_releases(repo_url).await;
+            if let Err(e) = &result {
+                crate::debug::log(&format!("Failed to list releases for {}: {}", repo_url, e));
+            }
+            let _ = tx.send((repo_idx, result));
+            ctx.request_repaint();
+        });
+    }
 
-        let Some(drive) = self.drives.get(drive_idx).cloned() else {
-            self.log("Invalid drive selection");
-            return;
-        };
+    fn pick_local_archive(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .set_title("Choose a release archive")
+            .add_filter("Release archive", &["zip", "7z"])
+            .pick_file();
+        if let Some(path) = picked {
+            self.set_local_archive(path);
+        }
+    }
 
-        // Store the drive for later ejection
-        self.installed_drive = Some(drive.clone());
+    fn pick_image_file(&mut self) {
+        let picked = rfd::FileDialog::new()
+            .set_title("Save the disk image as")
+            .add_filter("Disk image", &["img"])
+            .set_file_name(format!("{}.img", APP_NAME.to_lowercase()))
+            .save_file();
+        if let Some(path) = picked {
+            self.log(&format!("Building a disk image: {}", path.display()));
+            self.image_target = Some(ImageTarget::new(path, DEFAULT_IMAGE_SIZE_GB * 1_000_000_000, false));
+        }
+    }
 
-        self.state = AppState::FetchingRelease;
-        let (repo_name, repo_url) = REPO_OPTIONS[self.selected_repo_idx];
-        self.log(&format!(
-            "Starting installation to {} using {}",
-            drive.name, repo_name
-        ));
-
-        // Log installation start to debug log
-        crate::debug::log_section("Installation Started");
-        crate::debug::log(&format!("Drive: {} ({})", drive.name, drive.device_path));
-        crate::debug::log(&format!("Drive size: {} bytes", drive.size_bytes));
-        crate::debug::log(&format!("Mount path: {:?}", drive.mount_path));
-        crate::debug::log(&format!("Repository: {} ({})", repo_name, repo_url));
-
-        // Check if running as root on Linux
-        #[cfg(target_os = "linux")]
-        {
-            if unsafe { libc::geteuid() } == 0 {
-                crate::debug::log("WARNING: Running as root user");
-                if let Ok(sudo_user) = std::env::var("SUDO_USER") {
-                    crate::debug::log(&format!("Detected sudo execution by user: {}", sudo_user));
-                    self.log("Note: Running with sudo/root privileges");
-                } else if let Ok(pkexec_uid) = std::env::var("PKEXEC_UID") {
-                    crate::debug::log(&format!("Detected pkexec execution by UID: {}", pkexec_uid));
-                    self.log("Note: Running with elevated privileges (pkexec)");
-                } else {
-                    crate::debug::log("Running as actual root user (not via sudo/pkexec)");
-                    self.log("Note: Running as root user");
-                }
+    fn set_local_archive(&mut self, path: PathBuf) {
+        // Cheap check now; the full layout check runs in the pipeline before formatting
+        match crate::archive::detect_kind(&path) {
+            Ok(kind) => {
+                self.log(&format!("Using local {} archive: {}", kind.name(), path.display()));
+                self.components = match crate::archive::list_entries(&path) {
+                    Ok(entries) => crate::archive::components(&entries),
+                    Err(e) => {
+                        self.log(&format!("Error: {}", e));
+                        Vec::new()
+                    }
+                };
+                self.excluded_components.clear();
+                self.local_archive = Some(path);
             }
+            Err(e) => self.log(&format!("Error: {}", e)),
         }
+    }
 
-        let repo_url = repo_url.to_string();
-        let progress = self.progress.clone();
-        let log_messages = self.log_messages.clone();
-        let ctx_clone = ctx.clone();
-        let volume_label = VOLUME_LABEL.to_string();
-
-        // Create cancellation token
-        let cancel_token = CancellationToken::new();
-        self.cancel_token = Some(cancel_token.clone());
+    fn clear_local_archive(&mut self) {
+        self.local_archive = None;
+        self.clear_components();
+    }
 
-        // Channel for state updates
-        let (state_tx, mut state_rx) = mpsc::unbounded_channel::<AppState>();
+    fn clear_components(&mut self) {
+        self.components.clear();
+        self.excluded_components.clear();
+        self.show_components = false;
+    }
 
-        // Disable drive polling during installation
-        let _ = self.drive_poll_tx.send(false);
+    /// Show the component picker, downloading the selected release first to list its parts
+    fn choose_components(&mut self, ctx: egui::Context) {
+        if self.components.is_empty() && self.local_archive.is_none() {
+            self.mode = InstallMode::Download;
+            self.start_installation(ctx);
+        } else {
+            self.show_components = !self.show_components;
+        }
+    }
 
-        // Clone values for the async block
-        let state_tx_clone = state_tx.clone();
-        let drive_poll_tx_clone = self.drive_poll_tx.clone();
-        let cancel_token_clone = cancel_token.clone();
+    /// Name of what is about to be installed, for the dialogs
+    fn install_source_name(&self) -> String {
+        match &self.local_archive {
+            Some(_) => APP_NAME.to_string(),
+            None => REPO_OPTIONS[self.selected_repo_idx].0.to_string(),
+        }
+    }
 
-        // Spawn the installation task
-        self.runtime.spawn(async move {
-            let log = |msg: &str| {
-                if let Ok(mut logs) = log_messages.lock() {
-                    logs.push(msg.to_string());
-                }
-                // Also log to debug file/console
-                crate::debug::log(msg);
-                ctx_clone.request_repaint();
-            };
+    /// What the run is called in the dialogs ("Installation Complete")
+    fn run_label(&self) -> &'static str {
+        match self.image_target {
+            Some(_) if self.mode == InstallMode::Fresh => "Image build",
+            _ => self.mode.label(),
+        }
+    }
 
-            let set_progress = |current: u64, total: u64, message: &str| {
-                if let Ok(mut p) = progress.lock() {
-                    p.current = current;
-                    p.total = total;
-                    p.message = message.to_string();
-                }
-                ctx_clone.request_repaint();
-            };
+    /// Use the entered GitHub token (empty falls back to $GITHUB_TOKEN or the config one)
+    fn apply_token(&mut self, ctx: egui::Context) {
+        let token = Some(self.token_input.clone()).filter(|t| !t.trim().is_empty());
+        self.log(if token.is_some() { "Using the entered GitHub token" } else { "Cleared the entered GitHub token" });
+        crate::github::set_api_token(token);
+        self.show_token_editor = false;
+        // The list may have failed on the anonymous rate limit
+        self.refresh_releases(ctx);
+    }
 
-            // Step 1: Fetch release
-            log("Fetching latest release from GitHub...");
-            crate::debug::log_section("Fetching Release");
-            crate::debug::log(&format!("Repository URL: {}", repo_url));
-            set_progress(0, 100, "Fetching release info...");
-
-            let release = match get_latest_release(&repo_url).await {
-                Ok(r) => r,
-                Err(e) => {
-                    log(&format!("Error: {}", e));
-                    crate::debug::log(&format!("ERROR fetching release: {}", e));
-                    let _ = state_tx_clone.send(AppState::Error);
-                    let _ = drive_poll_tx_clone.send(true);
-                    return;
-                }
-            };
+    fn clear_cache(&mut self) {
+        match DownloadCache::open_default().clear() {
+            Ok(freed) => self.log(&format!("Cleared download cache ({:.1} MB freed)", freed as f64 / 1_048_576.0)),
+            Err(e) => self.log(&format!("Error: {}", e)),
+        }
+        self.cache_size = 0;
+    }
 
-            let asset = match find_release_asset(&release) {
-                Some(a) => a,
-                None => {
-                    log(&format!("Error: No {} file found in release", ASSET_EXTENSION));
-                    crate::debug::log(&format!("ERROR: No {} asset found in release", ASSET_EXTENSION));
-                    let _ = state_tx_clone.send(AppState::Error);
-                    let _ = drive_poll_tx_clone.send(true);
-                    return;
-                }
-            };
+    fn cancel_installation(&mut self) {
+        if let Some(token) = self.cancel_token.clone() {
+            self.log("Cancelling installation...");
+            token.cancel();
+            self.state = AppState::Cancelling;
+        }
+    }
 
-            log(&format!(
-                "Found release: {} ({})",
-                release.tag_name, asset.name
-            ));
-            crate::debug::log(&format!("Release: {}", release.tag_name));
-            crate::debug::log(&format!("Asset: {} ({} bytes)", asset.name, asset.size));
-
-            // Define temp/cache directory for later use
-            // On Linux/macOS, use cache dir to avoid temp space issues
-            // Linux: ~/.cache, macOS: ~/Library/Caches
-            #[cfg(target_os = "linux")]
-            let temp_dir = {
-                // If running as root via sudo or pkexec, try to use the actual user's cache directory
-                if unsafe { libc::geteuid() } == 0 {
-                    // First check for SUDO_USER (command-line sudo)
-                    if let Ok(sudo_user) = std::env::var("SUDO_USER") {
-                        let user_home = std::path::PathBuf::from(format!("/home/{}", sudo_user));
-                        if user_home.exists() {
-                            let user_cache = user_home.join(".cache");
-                            crate::debug::log(&format!("Using cache dir for sudo user {}: {:?}", sudo_user, user_cache));
-                            user_cache
-                        } else {
-                            crate::debug::log(&format!("User home not found at {:?}, using default", user_home));
-                            dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                        }
-                    }
-                    // Check for PKEXEC_UID (GUI elevation via pkexec)
-                    else if let Ok(pkexec_uid) = std::env::var("PKEXEC_UID") {
-                        if let Ok(uid) = pkexec_uid.parse::<u32>() {
-                            // Get username from UID using libc
-                            let pwd = unsafe { libc::getpwuid(uid) };
-                            if !pwd.is_null() {
-                                let username = unsafe {
-                                    std::ffi::CStr::from_ptr((*pwd).pw_name)
-                                        .to_string_lossy()
-                                        .to_string()
-                                };
-                                let user_home = std::path::PathBuf::from(format!("/home/{}", username));
-                                if user_home.exists() {
-                                    let user_cache = user_home.join(".cache");
-                                    crate::debug::log(&format!("Using cache dir for pkexec user {} (UID {}): {:?}", username, uid, user_cache));
-                                    user_cache
-                                } else {
-                                    crate::debug::log(&format!("User home not found at {:?}, using default", user_home));
-                                    dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                                }
-                            } else {
-                                crate::debug::log(&format!("Failed to get username for UID {}, using default", uid));
-                                dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                            }
-                        } else {
-                            crate::debug::log(&format!("Failed to parse PKEXEC_UID '{}', using default", pkexec_uid));
-                            dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                        }
-                    }
-                    else {
-                        crate::debug::log("Running as root, using root's cache dir");
-                        dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                    }
-                } else {
-                    dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                }
-            };
-            #[cfg(target_os = "macos")]
-            let temp_dir = dirs::cache_dir().unwrap_or_else(std::env::temp_dir);
-            #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-            let temp_dir = std::env::temp_dir();
-
-            crate::debug::log(&format!("Cache/temp directory: {:?}", temp_dir));
-
-            // Check available disk space before starting
-            // We need space for: download (asset.size) + extraction (~3x asset.size)
-            let required_space = asset.size * 4; // 4x for safety margin
-            let available_space = get_available_disk_space(&temp_dir);
-
-            crate::debug::log(&format!("Required disk space: {} MB", required_space / 1_048_576));
-            crate::debug::log(&format!("Available disk space: {} MB", available_space / 1_048_576));
-
-            if available_space < required_space {
-                let required_mb = required_space / 1_048_576;
-                let available_mb = available_space / 1_048_576;
-                let err_msg = format!(
-                    "Insufficient disk space. Need {} MB, but only {} MB available in cache directory. Please free up disk space and try again.",
-                    required_mb, available_mb
-                );
-                log(&err_msg);
-                crate::debug::log(&format!("ERROR: {}", err_msg));
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
+    fn start_installation(&mut self, ctx: egui::Context) {
+        let (repo_name, repo_url) = REPO_OPTIONS[self.selected_repo_idx];
+        let mut options = match self.image_target.clone() {
+            // Nothing to eject afterwards
+            Some(image) if self.mode == InstallMode::Fresh => {
+                self.installed_drive = None;
+                InstallOptions::for_image(image, repo_name, repo_url)
             }
-
-            log(&format!("Disk space check passed: {} MB available", available_space / 1_048_576));
-
-            // Step 2: Format drive (do this first so we fail fast if the card has issues)
-            let _ = state_tx_clone.send(AppState::Formatting);
-            log(&format!("Formatting {}...", drive.name));
-            crate::debug::log_section("Formatting Drive");
-            set_progress(0, 100, "Formatting drive...");
-
-            let (fmt_tx, mut fmt_rx) = mpsc::unbounded_channel::<FormatProgress>();
-            let progress_fmt = progress.clone();
-            let ctx_fmt = ctx_clone.clone();
-
-            // Spawn format progress handler
-            let fmt_handle = tokio::spawn(async move {
-                while let Some(prog) = fmt_rx.recv().await {
-                    if let Ok(mut p) = progress_fmt.lock() {
-                        match prog {
-                            FormatProgress::Started => {
-                                p.message = "Starting format...".to_string();
-                            }
-                            FormatProgress::Unmounting => {
-                                p.message = "Unmounting drive...".to_string();
-                            }
-                            #[cfg(not(target_os = "macos"))]
-                            FormatProgress::CleaningDisk => {
-                                p.message = "Cleaning disk...".to_string();
-                            }
-                            #[cfg(not(target_os = "macos"))]
-                            FormatProgress::CreatingPartition => {
-                                p.message = "Creating partition...".to_string();
-                            }
-                            FormatProgress::Formatting => {
-                                p.message = "Formatting to FAT32...".to_string();
-                            }
-                            FormatProgress::Progress { percent } => {
-                                p.current = percent as u64;
-                                p.total = 100;
-                                p.message = format!("Formatting... {}%", percent);
-                            }
-                            FormatProgress::Completed => {
-                                p.current = 100;
-                                p.total = 100;
-                                p.message = "Format complete".to_string();
-                            }
-                            FormatProgress::Cancelled => {
-                                p.message = "Format cancelled".to_string();
-                            }
-                            FormatProgress::Error(ref e) => {
-                                p.message = format!("Format error: {}", e);
-                            }
-                        }
-                    }
-                    ctx_fmt.request_repaint();
-                }
-            });
-
-            if let Err(e) = format_drive_fat32(&drive.device_path, &volume_label, fmt_tx, cancel_token_clone.clone()).await {
-                if e.contains("cancelled") {
-                    log("Format cancelled");
-                    let _ = state_tx_clone.send(AppState::Idle);
-                    let _ = drive_poll_tx_clone.send(true);
-                    return;
-                }
-                log(&format!("Format error: {}", e));
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
+            // Only fills the download cache
+            _ if self.mode == InstallMode::Download => {
+                self.installed_drive = None;
+                InstallOptions::new(DriveInfo::default(), repo_name, repo_url)
             }
-
-            let _ = fmt_handle.await;
-            log("Format complete");
-            crate::debug::log("Format complete");
-
-            // Get the destination path for extraction (platform-specific)
-            crate::debug::log("Getting mount path after format...");
-            let dest_path = match get_mount_path_after_format(&drive, &volume_label).await {
-                Ok(path) => path,
-                Err(e) => {
-                    log(&format!("Error getting mount path: {}", e));
-                    crate::debug::log(&format!("ERROR getting mount path: {}", e));
-                    let _ = state_tx_clone.send(AppState::Error);
-                    let _ = drive_poll_tx_clone.send(true);
+            _ => {
+                let Some(drive_idx) = self.selected_drive_idx else {
+                    self.log("No drive selected");
                     return;
-                }
-            };
-
-            log(&format!("Destination: {}", dest_path.display()));
-            crate::debug::log(&format!("Mount path: {:?}", dest_path));
-
-            // Create a log file on the SD card for debugging
-            let log_file_path = dest_path.join("install_log.txt");
-            let write_card_log = |msg: &str| {
-                use std::io::Write;
-                if let Ok(mut file) = std::fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(&log_file_path)
-                {
-                    let timestamp = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .map(|d| d.as_secs())
-                        .unwrap_or(0);
-                    let _ = writeln!(file, "[{}] {}", timestamp, msg);
-                }
-            };
-
-            write_card_log("Format complete, starting download...");
-
-            // Step 3: Download
-            let _ = state_tx_clone.send(AppState::Downloading);
-            let size_mb = asset.size as f64 / 1_048_576.0;
-            log(&format!("Downloading release ({:.1} MB)...", size_mb));
-            crate::debug::log_section("Downloading Release");
-
-            let download_path = temp_dir.join(&asset.name);
-            crate::debug::log(&format!("Download path: {:?}", download_path));
-
-            let (dl_tx, mut dl_rx) = mpsc::unbounded_channel::<DownloadProgress>();
-
-            let download_path_clone = download_path.clone();
-            let asset_clone = asset.clone();
-            let progress_clone = progress.clone();
-            let ctx_dl = ctx_clone.clone();
-
-            // Spawn download progress handler
-            let dl_handle = tokio::spawn(async move {
-                while let Some(prog) = dl_rx.recv().await {
-                    match prog {
-                        DownloadProgress::Started { total_bytes } => {
-                            if let Ok(mut p) = progress_clone.lock() {
-                                p.total = total_bytes;
-                                p.current = 0;
-                                p.message = "Downloading...".to_string();
-                            }
-                        }
-                        DownloadProgress::Progress { downloaded, total } => {
-                            if let Ok(mut p) = progress_clone.lock() {
-                                p.current = downloaded;
-                                p.total = total;
-                                let pct = (downloaded as f64 / total as f64 * 100.0) as u32;
-                                p.message = format!("Downloading... {}%", pct);
-                            }
-                        }
-                        DownloadProgress::Completed => {
-                            if let Ok(mut p) = progress_clone.lock() {
-                                p.message = "Download complete".to_string();
-                            }
-                        }
-                        DownloadProgress::Cancelled => {
-                            if let Ok(mut p) = progress_clone.lock() {
-                                p.message = "Download cancelled".to_string();
-                            }
-                        }
-                        DownloadProgress::Error(e) => {
-                            if let Ok(mut p) = progress_clone.lock() {
-                                p.message = format!("Download error: {}", e);
-                            }
-                        }
-                    }
-                    ctx_dl.request_repaint();
-                }
-            });
+                };
 
-            if let Err(e) = download_asset(&asset_clone, &download_path_clone, dl_tx, cancel_token_clone.clone()).await {
-                if e.contains("cancelled") {
-                    log("Download cancelled");
-                    let _ = tokio::fs::remove_file(&download_path_clone).await;
-                    crate::debug::log("Cleaned up partial download file");
-                    let _ = state_tx_clone.send(AppState::Idle);
-                    let _ = drive_poll_tx_clone.send(true);
+                let Some(drive) = self.drives.get(drive_idx).cloned() else {
+                    self.log("Invalid drive selection");
                     return;
-                }
-                log(&format!("Download error: {}", e));
-                let _ = tokio::fs::remove_file(&download_path_clone).await;
-                crate::debug::log("Cleaned up partial download file");
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
-            }
-
-            let _ = dl_handle.await;
-            log("Download complete");
-            crate::debug::log("Download complete");
-            write_card_log("Download complete, starting extraction...");
-
-            // Step 4: Extract to temp folder on local PC
-            // On Linux, use the same temp_dir we already determined
-            // On macOS, give cache_dir() another try (original behavior)
-            #[cfg(target_os = "linux")]
-            let extract_base_dir = temp_dir.clone();
-            #[cfg(target_os = "macos")]
-            let extract_base_dir = dirs::cache_dir().unwrap_or_else(|| temp_dir.clone());
-            #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-            let extract_base_dir = temp_dir.clone();
-
-            let _ = state_tx_clone.send(AppState::Extracting);
-            let temp_extract_dir = extract_base_dir.join(format!("{}_extract", TEMP_PREFIX));
-            log("Extracting files to local temp folder...");
-            crate::debug::log_section("Extracting Files");
-            crate::debug::log(&format!("Temp extract dir: {:?}", temp_extract_dir));
-            set_progress(0, 100, "Extracting files...");
-
-            // Clean up any previous extraction
-            let _ = std::fs::remove_dir_all(&temp_extract_dir);
-            if let Err(e) = std::fs::create_dir_all(&temp_extract_dir) {
-                let err_msg = format!("Failed to create temp extract dir: {}", e);
-                log(&err_msg);
-                crate::debug::log(&format!("ERROR: {}", err_msg));
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
-            }
-
-            let (ext_tx, mut ext_rx) = mpsc::unbounded_channel::<ExtractProgress>();
-            let progress_ext = progress.clone();
-            let ctx_ext = ctx_clone.clone();
-
-            // Spawn extract progress handler
-            let ext_handle = tokio::spawn(async move {
-                while let Some(prog) = ext_rx.recv().await {
-                    if let Ok(mut p) = progress_ext.lock() {
-                        match prog {
-                            ExtractProgress::Started => {
-                                p.message = "Starting extraction...".to_string();
-                            }
-                            ExtractProgress::Extracting => {
-                                p.message = "Extracting files...".to_string();
-                            }
-                            ExtractProgress::Progress { percent } => {
-                                p.current = percent as u64;
-                                p.total = 100;
-                                p.message = format!("Extracting... {}%", percent);
-                            }
-                            ExtractProgress::Completed => {
-                                p.current = 100;
-                                p.total = 100;
-                                p.message = "Extraction complete".to_string();
-                            }
-                            ExtractProgress::Cancelled => {
-                                p.message = "Extraction cancelled".to_string();
-                            }
-                            ExtractProgress::Error(e) => {
-                                p.message = format!("Extract error: {}", e);
-                            }
-                        }
-                    }
-                    ctx_ext.request_repaint();
-                }
-            });
+                };
 
-            write_card_log(&format!(
-                "Calling 7z extraction: {:?} -> {:?}",
-                download_path, temp_extract_dir
-            ));
-
-            if let Err(e) = extract_7z_with_progress(&download_path, &temp_extract_dir, ext_tx, cancel_token_clone.clone()).await {
-                if e.contains("cancelled") {
-                    write_card_log("Extraction cancelled");
-                    log("Extraction cancelled");
-                    let _ = std::fs::remove_dir_all(&temp_extract_dir);
-                    let _ = tokio::fs::remove_file(&download_path).await;
-                    crate::debug::log("Cleaned up download file after cancellation");
-                    let _ = state_tx_clone.send(AppState::Idle);
-                    let _ = drive_poll_tx_clone.send(true);
-                    return;
-                }
-                write_card_log(&format!("Extract error: {}", e));
-                log(&format!("Extract error: {}", e));
-                let _ = std::fs::remove_dir_all(&temp_extract_dir);
-                let _ = tokio::fs::remove_file(&download_path).await;
-                crate::debug::log("Cleaned up download file after error");
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
+                // Store the drive for later ejection
+                self.installed_drive = Some(drive.clone());
+                InstallOptions::new(drive, repo_name, repo_url)
             }
+        };
+        self.benchmark = None;
 
-            let _ = ext_handle.await;
-            log("Extraction complete");
-            write_card_log("Extraction complete");
-            crate::debug::log("Extraction complete");
-
-            // Step 5: Copy files to SD card
-            let _ = state_tx_clone.send(AppState::Copying);
-            log("Copying files to SD card...");
-            crate::debug::log_section("Copying Files");
-            set_progress(0, 100, "Copying files...");
-
-            let (copy_tx, mut copy_rx) = mpsc::unbounded_channel::<CopyProgress>();
-            let progress_copy = progress.clone();
-            let ctx_copy = ctx_clone.clone();
-
-            // Spawn copy progress handler
-            let copy_handle = tokio::spawn(async move {
-                while let Some(prog) = copy_rx.recv().await {
-                    if let Ok(mut p) = progress_copy.lock() {
-                        match prog {
-                            CopyProgress::Counting => {
-                                p.message = "Counting files...".to_string();
-                            }
-                            CopyProgress::Started { total_bytes, total_files } => {
-                                p.total = total_bytes;
-                                p.current = 0;
-                                p.message = format!("Copying {} files...", total_files);
-                            }
-                            CopyProgress::Progress { copied_bytes, total_bytes, current_file } => {
-                                p.current = copied_bytes;
-                                p.total = total_bytes;
-                                let pct = if total_bytes > 0 {
-                                    (copied_bytes as f64 / total_bytes as f64 * 100.0) as u32
-                                } else {
-                                    0
-                                };
-                                if current_file.is_empty() {
-                                    p.message = format!("Copying... {}%", pct);
-                                } else {
-                                    // Truncate filename if too long
-                                    let display_file = if current_file.len() > 40 {
-                                        format!("...{}", &current_file[current_file.len()-37..])
-                                    } else {
-                                        current_file
-                                    };
-                                    p.message = format!("{}% - {}", pct, display_file);
-                                }
-                            }
-                            CopyProgress::Completed => {
-                                p.current = p.total;
-                                p.message = "Copy complete".to_string();
-                            }
-                            CopyProgress::Cancelled => {
-                                p.message = "Copy cancelled".to_string();
-                            }
-                            CopyProgress::Error(e) => {
-                                p.message = format!("Copy error: {}", e);
-                            }
-                        }
-                    }
-                    ctx_copy.request_repaint();
-                }
-            });
+        self.state = AppState::FetchingRelease;
+        options.tag = self.selected_tag.clone();
+        options.local_archive = self.local_archive.clone();
+        options.excluded_components = self.excluded_components.clone();
+        options.mode = self.mode;
+        options.check_capacity = self.check_capacity && self.mode == InstallMode::Fresh && options.image.is_none();
 
-            write_card_log(&format!(
-                "Copying files: {:?} -> {:?}",
-                temp_extract_dir, dest_path
-            ));
-
-            if let Err(e) = copy_directory_with_progress(&temp_extract_dir, &dest_path, copy_tx, cancel_token_clone.clone()).await {
-                if e.contains("cancelled") {
-                    write_card_log("Copy cancelled");
-                    log("Copy cancelled");
-                    let _ = std::fs::remove_dir_all(&temp_extract_dir);
-                    let _ = tokio::fs::remove_file(&download_path).await;
-                    crate::debug::log("Cleaned up download file after cancellation");
-                    let _ = state_tx_clone.send(AppState::Idle);
-                    let _ = drive_poll_tx_clone.send(true);
-                    return;
-                }
-                write_card_log(&format!("Copy error: {}", e));
-                log(&format!("Copy error: {}", e));
-                let _ = std::fs::remove_dir_all(&temp_extract_dir);
-                let _ = tokio::fs::remove_file(&download_path).await;
-                crate::debug::log("Cleaned up download file after error");
-                let _ = state_tx_clone.send(AppState::Error);
-                let _ = drive_poll_tx_clone.send(true);
-                return;
-            }
+        // Create cancellation token
+        let cancel_token = CancellationToken::new();
+        self.cancel_token = Some(cancel_token.clone());
 
-            let _ = copy_handle.await;
-            log("Copy complete");
-            write_card_log("Copy complete");
-            crate::debug::log("Copy complete");
-
-            // Clean up temp extraction folder
-            let _ = std::fs::remove_dir_all(&temp_extract_dir);
-            crate::debug::log("Cleaned up temp extraction folder");
-
-            // Cleanup temp file
-            let _ = tokio::fs::remove_file(&download_path).await;
-            write_card_log("Cleaned up temp download file");
-            crate::debug::log("Cleaned up temp download file");
-
-            // Copy debug log to SD card
-            log("Writing debug log to SD card...");
-            crate::debug::log("Copying debug log to SD card...");
-            match crate::debug::copy_log_to(&dest_path) {
-                Ok(log_path) => {
-                    log(&format!("Debug log saved to: {}", log_path.display()));
-                    crate::debug::log(&format!("Debug log copied to: {:?}", log_path));
-                }
-                Err(e) => {
-                    log(&format!("Warning: Could not copy debug log: {}", e));
-                    crate::debug::log(&format!("Failed to copy debug log: {}", e));
-                }
-            }
+        // Disable drive polling during installation
+        let _ = self.drive_poll_tx.send(false);
 
-            log("Installation complete! You can now safely eject the SD card.");
-            write_card_log("Installation complete!");
-            crate::debug::log("Installation complete!");
-            let _ = state_tx_clone.send(AppState::Complete);
-            let _ = drive_poll_tx_clone.send(true);
-        });
+        // Pipeline events are drained in update(); wake the UI whenever one arrives
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let (ui_tx, ui_rx) = mpsc::unbounded_channel();
+        self.install_rx = Some(ui_rx);
 
-        // Spawn a task to update state from the channel
-        let progress = self.progress.clone();
-        let ctx_state = ctx.clone();
         self.runtime.spawn(async move {
-            while let Some(new_state) = state_rx.recv().await {
-                // We can't directly update self.state from here, but we can use the progress message
-                // to communicate state. The UI will poll the progress message.
-                if let Ok(mut p) = progress.lock() {
-                    p.message = match new_state {
-                        AppState::FetchingRelease => "Fetching release...".to_string(),
-                        AppState::Downloading => "Downloading...".to_string(),
-                        AppState::Formatting => "Formatting...".to_string(),
-                        AppState::Extracting => "Extracting...".to_string(),
-                        AppState::Copying => "Copying...".to_string(),
-                        AppState::Complete => "COMPLETE".to_string(),
-                        AppState::Error => "ERROR".to_string(),
-                        AppState::Idle => "CANCELLED".to_string(),
-                        _ => p.message.clone(),
-                    };
+            while let Some(event) = event_rx.recv().await {
+                if ui_tx.send(event).is_err() {
+                    break;
                 }
-                ctx_state.request_repaint();
+                ctx.request_repaint();
             }
         });
-    }
-}
 
-/// Get the mount path after formatting, handling platform differences
-#[cfg(target_os = "windows")]
-async fn get_mount_path_after_format(drive: &DriveInfo, _volume_label: &str) -> Result<PathBuf, String> {
-    // On Windows, the drive letter remains the same after formatting
-    // The mount_path should be set (e.g., "E:\")
-    drive.mount_path.clone().ok_or_else(|| {
-        format!("No mount path available for drive {}", drive.name)
-    })
-}
+        let pipeline = InstallPipeline::new(options, event_tx, cancel_token);
+        self.runtime.spawn(pipeline.run());
+    }
 
-#[cfg(target_os = "macos")]
-async fn get_mount_path_after_format(_drive: &DriveInfo, volume_label: &str) -> Result<PathBuf, String> {
-    // macOS automatically mounts at /Volumes/LABEL after diskutil eraseDisk
-    // Wait a moment for the mount to complete
-    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+    fn start_eject(&mut self, ctx: egui::Context) {
+        let Some(drive) = self.installed_drive.clone() else {
+            return;
+        };
+        self.state = AppState::Ejecting;
+        self.log("Ejecting SD card...");
 
-    let mount_path = PathBuf::from(format!("/Volumes/{}", volume_label));
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.eject_rx = Some(rx);
 
-    // Wait for the mount point to appear (up to 10 seconds)
-    for _ in 0..20 {
-        if mount_path.exists() {
-            return Ok(mount_path);
-        }
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        self.runtime.spawn(async move {
+            let result = tokio::task::spawn_blocking(move || eject_drive(&drive))
+                .await
+                .unwrap_or_else(|e| Err(format!("Eject task failed: {}", e)));
+            let _ = tx.send(result);
+            ctx.request_repaint();
+        });
     }
 
-    Err(format!("Mount point {} did not appear after formatting", mount_path.display()))
-}
-
-#[cfg(target_os = "linux")]
-async fn get_mount_path_after_format(drive: &DriveInfo, volume_label: &str) -> Result<PathBuf, String> {
-    use tokio::process::Command;
-
-    // Determine the partition path
-    let partition_path = if drive.device_path.contains("mmcblk") || drive.device_path.contains("nvme") {
-        format!("{}p1", drive.device_path)
-    } else {
-        format!("{}1", drive.device_path)
-    };
-
-    // Use udisksctl to mount - this registers with the udisks2 daemon so it won't
-    // auto-remount when we later unmount. The daemon chooses the mount point
-    // (typically /media/username/LABEL or /run/media/username/LABEL).
-    crate::debug::log(&format!("Mounting {} via udisksctl...", partition_path));
-    let output = Command::new("udisksctl")
-        .args(["mount", "-b", &partition_path])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to run udisksctl mount: {}", e))?;
-
-    if output.status.success() {
-        // Parse mount point from udisksctl output: "Mounted /dev/sdb1 at /media/user/LABEL"
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        crate::debug::log(&format!("udisksctl output: {}", stdout.trim()));
-
-        if let Some(mount_point) = stdout.split(" at ").nth(1) {
-            let mount_path = PathBuf::from(mount_point.trim().trim_end_matches('.'));
-            crate::debug::log(&format!("Mount point: {:?}", mount_path));
-            return Ok(mount_path);
+    fn handle_pipeline_event(&mut self, event: PipelineEvent) {
+        match event {
+            PipelineEvent::StageStarted(stage) => {
+                // Keep showing "Cancelling" until the pipeline actually stops
+                if self.state != AppState::Cancelling {
+                    self.state = stage.into();
+                }
+                self.progress.current = 0;
+                self.progress.total = 100;
+                self.progress.message = format!("{}...", stage.label());
+            }
+            PipelineEvent::Log(message) => self.log(&message),
+            PipelineEvent::Status(message) => self.progress.message = message,
+            PipelineEvent::Benchmark(report) => self.benchmark = Some(report),
+            PipelineEvent::Components(components) => {
+                self.components = components;
+                self.excluded_components.clear();
+                self.show_components = true;
+            }
+            PipelineEvent::Progress { current, total, message } => {
+                self.progress = ProgressInfo { current, total, message };
+            }
+            PipelineEvent::Finished(result) => {
+                self.state = match result {
+                    // The component picker opens instead of a dialog
+                    Ok(()) if self.mode == InstallMode::Download => AppState::Idle,
+                    Ok(()) => AppState::Complete,
+                    Err(PipelineError::Cancelled) => AppState::Idle,
+                    Err(PipelineError::Failed { .. }) => AppState::Error,
+                };
+                self.progress.message.clear();
+                self.cancel_token = None;
+                self.install_rx = None;
+                self.cache_size = DownloadCache::open_default().total_size();
+                let _ = self.drive_poll_tx.send(true);
+            }
         }
     }
-
-    // Fallback: use raw mount if udisksctl fails (e.g., no udisks2 daemon)
-    crate::debug::log("udisksctl mount failed, falling back to raw mount...");
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    crate::debug::log(&format!("udisksctl error: {}", stderr.trim()));
-
-    let cache_dir = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("/tmp"));
-    let mount_point = cache_dir.join(format!("{}_{}", TEMP_PREFIX, volume_label));
-
-    // Create the mount directory if it doesn't exist
-    let _ = std::fs::create_dir_all(&mount_point);
-
-    // Mount the partition
-    let output = Command::new("mount")
-        .args([&partition_path, mount_point.to_str().unwrap()])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to mount partition: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("Failed to mount partition: {}", stderr));
-    }
-
-    Ok(mount_point)
-}
-
-#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
-async fn get_mount_path_after_format(_drive: &DriveInfo, _volume_label: &str) -> Result<PathBuf, String> {
-    Err("Mounting not supported on this platform".to_string())
 }
 
 impl eframe::App for InstallerApp {
@@ -984,70 +527,139 @@ impl eframe::App for InstallerApp {
             }
         }
 
+        // GitHub token window
+        if !show_modal && self.show_token_editor {
+            let mut open = true;
+            let mut apply = false;
+            egui::Window::new("GitHub Token")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.label("Optional. Raises the GitHub API limit from 60 to 5000 requests an hour.");
+                    ui.label("Leave empty to use $GITHUB_TOKEN if it is set.");
+                    ui.add_space(8.0);
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.token_input)
+                            .password(true)
+                            .hint_text("github_pat_...")
+                            .desired_width(f32::INFINITY),
+                    );
+                    let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        apply = ui.button("Save").clicked() || entered;
+                        if ui.button("Clear").clicked() {
+                            self.token_input.clear();
+                            apply = true;
+                        }
+                    });
+                });
+            if apply {
+                self.apply_token(ctx.clone());
+            } else if !open {
+                self.show_token_editor = false;
+            }
+        }
+
+        // Component picker for the local archive or the downloaded release
+        if !show_modal && self.show_components {
+            let mut open = true;
+            egui::Window::new("Components")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.label("Untick the parts of the release you don't want on the card.");
+                    ui.add_space(8.0);
+                    egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                        for component in &self.components {
+                            component_checkbox(ui, component, &mut self.excluded_components);
+                            if component.children.is_empty() || self.excluded_components.contains(&component.item.name) {
+                                continue;
+                            }
+                            ui.indent(&component.item.name, |ui| {
+                                for child in &component.children {
+                                    component_checkbox(ui, child, &mut self.excluded_components);
+                                }
+                            });
+                        }
+                    });
+                    ui.add_space(8.0);
+                    let skipped = self.components.iter().flat_map(|c| std::iter::once(c).chain(&c.children));
+                    let skipped_bytes: u64 = skipped
+                        .filter(|c| self.excluded_components.contains(&c.item.name))
+                        .map(|c| c.item.bytes)
+                        .sum();
+                    let total_bytes: u64 = self.components.iter().map(|c| c.item.bytes).sum();
+                    ui.label(format!(
+                        "Installing {:.1} of {:.1} MB",
+                        (total_bytes - skipped_bytes) as f64 / 1_048_576.0,
+                        total_bytes as f64 / 1_048_576.0
+                    ));
+                });
+            if !open {
+                self.show_components = false;
+            }
+        }
+
         // Poll for drive updates
         while let Ok(drives) = self.drive_rx.try_recv() {
             self.drives = drives;
             self.ensure_selection_valid();
         }
 
-        // Check for state updates from async eject on Windows
-        if let Ok(mut progress) = self.progress.lock() {
-            if progress.message.starts_with("EJECT_") {
-                if progress.message == "EJECT_SUCCESS" {
-                    self.log("SD card safely ejected. You may now remove it.");
-                    self.state = AppState::Ejected;
-                } else if let Some(error_msg) = progress.message.strip_prefix("EJECT_ERROR: ") {
-                    self.log(&format!("Eject warning: {}. The card should still be safe to remove.", error_msg));
-                    self.state = AppState::Ejected;
-                }
-                progress.message.clear(); // Consume the message
+        // Release archive dropped onto the window
+        let dropped = ctx.input(|i| i.raw.dropped_files.iter().find_map(|f| f.path.clone()));
+        if let Some(path) = dropped {
+            if self.state == AppState::Idle {
+                self.set_local_archive(path);
             }
         }
 
-        // Check for state updates from main installation process
-        if let Ok(mut progress) = self.progress.lock() {
-            if progress.message == "COMPLETE" {
-                self.state = AppState::Complete;
-                self.cancel_token = None;
-                progress.message.clear();
-            } else if progress.message == "ERROR" {
-                self.state = AppState::Error;
-                self.cancel_token = None;
-                progress.message.clear();
-            } else if progress.message == "CANCELLED" {
-                self.state = AppState::Idle;
-                self.cancel_token = None;
-                progress.message.clear();
-            } else {
-                // Update state based on progress message
-                if progress.message.contains("Downloading") {
-                    self.state = AppState::Downloading;
-                } else if progress.message.contains("Formatting")
-                    || progress.message.contains("format")
-                    || progress.message.contains("Unmounting")
-                    || progress.message.contains("Cleaning")
-                    || progress.message.contains("partition")
-                {
-                    self.state = AppState::Formatting;
-                } else if progress.message.contains("Extracting") || progress.message.contains("Extract")
-                {
-                    self.state = AppState::Extracting;
-                } else if progress.message.contains("Copying") || progress.message.contains("Copy")
-                {
-                    self.state = AppState::Copying;
-                }
+        // Poll for release lists (ignore answers for a repository that is no longer selected)
+        while let Ok((repo_idx, result)) = self.releases_rx.try_recv() {
+            if repo_idx == self.selected_repo_idx {
+                self.releases = Some(result);
+            }
+        }
+
+        // Check for the result of an async eject
+        if let Some(Ok(result)) = self.eject_rx.as_mut().map(|rx| rx.try_recv()) {
+            match result {
+                Ok(()) => self.log("SD card safely ejected. You may now remove it."),
+                Err(e) => self.log(&format!("Eject warning: {}. The card should still be safe to remove.", e)),
             }
+            self.state = AppState::Ejected;
+            self.eject_rx = None;
         }
 
+        // Apply events from the install pipeline
+        let events: Vec<PipelineEvent> = match self.install_rx.as_mut() {
+            Some(rx) => std::iter::from_fn(|| rx.try_recv().ok()).collect(),
+            None => Vec::new(),
+        };
+        for event in events {
+            self.handle_pipeline_event(event);
+        }
 
         // Keep requesting repaints while busy so UI stays responsive
         let is_busy = matches!(
             self.state,
             AppState::FetchingRelease
                 | AppState::Downloading
+                | AppState::Verifying
+                | AppState::CheckingArchive
+                | AppState::CheckingCard
+                | AppState::CheckingCapacity
                 | AppState::Formatting
                 | AppState::Extracting
                 | AppState::Copying
+                | AppState::VerifyingCard
+                | AppState::Compressing
+                | AppState::Benchmarking
                 | AppState::Ejecting
                 | AppState::Cancelling
         );
@@ -1073,13 +685,13 @@ impl eframe::App for InstallerApp {
 
             let window_title = match self.state {
                 AppState::AwaitingConfirmation => {
-                    let selected_repo_name = REPO_OPTIONS[self.selected_repo_idx].0;
-                    format!("Confirm {} Installation", selected_repo_name)
+                    let selected_repo_name = self.install_source_name();
+                    format!("Confirm {} {}", selected_repo_name, self.run_label())
                 }
-                AppState::Complete => "Installation Complete".to_string(),
+                AppState::Complete => format!("{} Complete", self.run_label()),
                 AppState::Ejecting => "Ejecting...".to_string(),
                 AppState::Ejected => "Safe to Remove".to_string(),
-                AppState::Error => "Installation Error".to_string(),
+                AppState::Error => format!("{} Error", self.run_label()),
                 _ => String::new(),
             };
 
@@ -1095,17 +707,78 @@ impl eframe::App for InstallerApp {
                         match self.state {
                             AppState::AwaitingConfirmation => {
                                 ui.add_space(12.0);
-                                ui.colored_label(ui.visuals().warn_fg_color, "WARNING");
-                                ui.add_space(12.0);
-
-                                ui.label("This will DELETE ALL DATA on the selected drive:");
+                                match self.mode {
+                                    InstallMode::Update => {
+                                        ui.colored_label(ui.visuals().warn_fg_color, "UPDATE");
+                                        ui.add_space(12.0);
+                                        ui.label("This will replace the system files on the selected drive.");
+                                        ui.label("ROMs, saves, BIOS files and settings are kept:");
+                                    }
+                                    InstallMode::Repair => {
+                                        ui.colored_label(ui.visuals().warn_fg_color, "REPAIR");
+                                        ui.add_space(12.0);
+                                        ui.label("This will restore missing or damaged system files on the selected drive.");
+                                        ui.label("ROMs, saves, BIOS files and settings are kept:");
+                                    }
+                                    _ if self.image_target.is_some() => {
+                                        ui.colored_label(ui.visuals().warn_fg_color, "DISK IMAGE");
+                                        ui.add_space(12.0);
+                                        ui.label("This will build a ready-to-flash image (an existing file is replaced):");
+                                    }
+                                    _ => {
+                                        ui.colored_label(ui.visuals().warn_fg_color, "WARNING");
+                                        ui.add_space(12.0);
+                                        ui.label("This will DELETE ALL DATA on the selected drive:");
+                                    }
+                                }
                                 ui.add_space(8.0);
 
-                                if let Some(idx) = self.selected_drive_idx {
+                                if let Some(image) = &mut self.image_target {
+                                    ui.label(image.output_path().display().to_string());
+                                    ui.add_space(8.0);
+                                    ui.horizontal(|ui| {
+                                        let size_gb = image.size_bytes / 1_000_000_000;
+                                        egui::ComboBox::from_id_salt("image_size")
+                                            .selected_text(format!("{} GB", size_gb))
+                                            .show_ui(ui, |ui| {
+                                                for gb in IMAGE_SIZES_GB {
+                                                    if ui.selectable_label(gb == size_gb, format!("{} GB", gb)).clicked() {
+                                                        image.size_bytes = gb * 1_000_000_000;
+                                                    }
+                                                }
+                                            });
+                                        ui.checkbox(&mut image.compress, "Gzip the image");
+                                    });
+                                } else if let Some(idx) = self.selected_drive_idx {
                                     if let Some(drive) = self.drives.get(idx) {
                                         ui.label(drive.display_name());
                                     }
                                 }
+                                ui.add_space(8.0);
+                                match &self.local_archive {
+                                    Some(path) => ui.label(format!("Archive: {}", path.display())),
+                                    // The release comes from the card's manifest, if it has one
+                                    None if self.mode == InstallMode::Repair => {
+                                        ui.label("Version: the one installed on the card")
+                                    }
+                                    None => ui.label(format!(
+                                        "Version: {}",
+                                        self.selected_tag.as_deref().unwrap_or("latest release")
+                                    )),
+                                };
+
+                                if self.mode == InstallMode::Fresh && self.image_target.is_none() {
+                                    ui.add_space(8.0);
+                                    ui.add_enabled(
+                                        CAPACITY_CHECK_SUPPORTED,
+                                        egui::Checkbox::new(&mut self.check_capacity, "Check the card's real capacity first"),
+                                    )
+                                    .on_hover_text("Writes and reads back test data across the whole card to catch fake-capacity cards")
+                                    .on_disabled_hover_text(CAPACITY_CHECK_UNAVAILABLE);
+                                    if !CAPACITY_CHECK_SUPPORTED {
+                                        ui.small(CAPACITY_CHECK_UNAVAILABLE);
+                                    }
+                                }
 
                                 ui.add_space(12.0);
                                 ui.label("Are you sure you want to continue?");
@@ -1128,7 +801,13 @@ impl eframe::App for InstallerApp {
                                         egui::Vec2::ZERO,
                                         egui::Layout::left_to_right(egui::Align::Center),
                                         |ui| {
-                                            if ui.button("Yes, install").clicked() {
+                                            let confirm = match self.mode {
+                                                InstallMode::Update => "Yes, update",
+                                                InstallMode::Repair => "Yes, repair",
+                                                _ if self.image_target.is_some() => "Yes, build",
+                                                _ => "Yes, install",
+                                            };
+                                            if ui.button(confirm).clicked() {
                                                 self.start_installation(ctx.clone());
                                             }
                                         },
@@ -1139,8 +818,20 @@ impl eframe::App for InstallerApp {
                                 ui.add_space(12.0);
                                 ui.colored_label(egui::Color32::from_rgb(104, 157, 106), "SUCCESS");
                                 ui.add_space(12.0);
-                                let selected_repo_name = REPO_OPTIONS[self.selected_repo_idx].0;
-                                ui.label(format!("{} has been successfully installed.", selected_repo_name));
+                                let message = match self.mode {
+                                    InstallMode::Verify => "Every installed file on the card matches the release.".to_string(),
+                                    InstallMode::Repair => "The card has been repaired. See the log for what was fixed.".to_string(),
+                                    InstallMode::Benchmark => "The benchmark has finished.".to_string(),
+                                    _ => match &self.image_target {
+                                        Some(image) => format!("The image has been written to {}.", image.output_path().display()),
+                                        None => format!("{} has been successfully installed.", self.install_source_name()),
+                                    },
+                                };
+                                ui.label(message);
+                                if let (InstallMode::Benchmark, Some(report)) = (self.mode, &self.benchmark) {
+                                    ui.add_space(8.0);
+                                    benchmark_results(ui, report);
+                                }
                                 ui.add_space(15.0);
                                 ui.separator();
                                 ui.add_space(8.0);
@@ -1160,28 +851,8 @@ impl eframe::App for InstallerApp {
                                         egui::Vec2::ZERO,
                                         egui::Layout::left_to_right(egui::Align::Center),
                                         |ui| {
-                                            if ui.button("Safely Eject").clicked() {
-                                                if let Some(drive) = self.installed_drive.clone() {
-                                                    self.state = AppState::Ejecting;
-                                                    self.log("Ejecting SD card...");
-                    
-                                                    let progress = self.progress.clone();
-                                                    let ctx_clone = ctx.clone();
-                    
-                                                    self.runtime.spawn(async move {
-                                                        let result = tokio::task::spawn_blocking(move || {
-                                                            eject_drive(&drive)
-                                                        }).await.unwrap();
-                    
-                                                        if let Ok(mut progress) = progress.lock() {
-                                                            match result {
-                                                                Ok(()) => progress.message = "EJECT_SUCCESS".to_string(),
-                                                                Err(e) => progress.message = format!("EJECT_ERROR: {}", e),
-                                                            }
-                                                        }
-                                                        ctx_clone.request_repaint();
-                                                    });
-                                                }
+                                            if self.installed_drive.is_some() && ui.button("Safely Eject").clicked() {
+                                                self.start_eject(ctx.clone());
                                             }
                                         },
                                     );
@@ -1208,8 +879,15 @@ impl eframe::App for InstallerApp {
                                 ui.add_space(12.0);
                                 ui.colored_label(ui.visuals().error_fg_color, "FAILED");
                                 ui.add_space(12.0);
-                                let selected_repo_name = REPO_OPTIONS[self.selected_repo_idx].0;
-                                ui.label(format!("{} installation failed.", selected_repo_name));
+                                let message = match self.mode {
+                                    InstallMode::Verify => "The card did not pass verification.".to_string(),
+                                    InstallMode::Repair => "The card could not be repaired.".to_string(),
+                                    InstallMode::Benchmark => "The benchmark could not be completed.".to_string(),
+                                    InstallMode::Download => "The release could not be downloaded.".to_string(),
+                                    _ if self.image_target.is_some() => "The image could not be built.".to_string(),
+                                    _ => format!("{} installation failed.", self.install_source_name()),
+                                };
+                                ui.label(message);
                                 ui.add_space(8.0);
                                 ui.label("Check the log for details.");
                                 ui.add_space(15.0);
@@ -1294,9 +972,16 @@ impl eframe::App for InstallerApp {
                         self.state,
                         AppState::FetchingRelease
                             | AppState::Downloading
+                            | AppState::Verifying
+                            | AppState::CheckingArchive
+                            | AppState::CheckingCard
+                            | AppState::CheckingCapacity
                             | AppState::Formatting
                             | AppState::Extracting
                             | AppState::Copying
+                            | AppState::VerifyingCard
+                            | AppState::Compressing
+                            | AppState::Benchmarking
                             | AppState::Cancelling
                     );
 
@@ -1334,6 +1019,25 @@ impl eframe::App for InstallerApp {
                             if ui.button("🎨").on_hover_text("Toggle Theme Editor (Ctrl+T)").clicked() {
                                 self.show_theme_editor = !self.show_theme_editor;
                             }
+                            let clear_cache_hint = format!(
+                                "Clear download cache ({:.1} MB)",
+                                self.cache_size as f64 / 1_048_576.0
+                            );
+                            if ui
+                                .add_enabled(!show_progress && self.cache_size > 0, egui::Button::new("🗑"))
+                                .on_hover_text(&clear_cache_hint)
+                                .on_disabled_hover_text(clear_cache_hint)
+                                .clicked()
+                            {
+                                self.clear_cache();
+                            }
+                            if ui
+                                .add_enabled(!show_progress, egui::Button::new("🔑"))
+                                .on_hover_text("GitHub token")
+                                .clicked()
+                            {
+                                self.show_token_editor = !self.show_token_editor;
+                            }
                             if ui.button("📜").on_hover_text("Toggle Log Area").clicked() {
                                 self.show_log = !self.show_log;
                                 
@@ -1353,34 +1057,40 @@ impl eframe::App for InstallerApp {
                 ui.add_space(16.0);
                 ui.separator();
                 ui.add_space(16.0);
+                let mut pick_image = false;
                 ui.columns(2, |columns| {
                     columns[0].allocate_ui_with_layout(
                         egui::Vec2::ZERO,
                         egui::Layout::right_to_left(egui::Align::Center),
                         |ui| {
-                            // Drive selection
-                            let (selected_text, enabled) = if self.drives.is_empty() {
-                                ("No SD card".to_string(), false)
-                            } else {
-                                (
-                                    self.selected_drive_idx
-                                        .and_then(|idx| self.drives.get(idx))
-                                        .map(|d| d.display_name())
-                                        .unwrap_or_else(|| "Select Drive".to_string()),
-                                    !show_progress
-                                )
+                            // Drive selection, or a disk image file in place of a card
+                            let selected_text = match &self.image_target {
+                                Some(image) => format!("💾 {}", image.drive().name),
+                                None if self.drives.is_empty() => "No SD card".to_string(),
+                                None => self.selected_drive_idx
+                                    .and_then(|idx| self.drives.get(idx))
+                                    .map(|d| d.display_name())
+                                    .unwrap_or_else(|| "Select Drive".to_string()),
                             };
 
-                            ui.add_enabled_ui(enabled, |ui| {
+                            ui.add_enabled_ui(!show_progress, |ui| {
                                 egui::ComboBox::from_id_salt("drive_select")
                                     .selected_text(&selected_text)
                                     .show_ui(ui, |ui| {
                                         for (idx, drive) in self.drives.iter().enumerate() {
-                                            ui.selectable_value(
-                                                &mut self.selected_drive_idx,
-                                                Some(idx),
-                                                drive.display_name(),
-                                            );
+                                            let selected = self.image_target.is_none() && self.selected_drive_idx == Some(idx);
+                                            if ui.selectable_label(selected, drive.display_name()).clicked() {
+                                                self.selected_drive_idx = Some(idx);
+                                                self.image_target = None;
+                                            }
+                                        }
+                                        ui.separator();
+                                        if ui
+                                            .selectable_label(self.image_target.is_some(), "💾 Disk image file...")
+                                            .on_hover_text("Build a ready-to-flash .img instead of writing to a card")
+                                            .clicked()
+                                        {
+                                            pick_image = true;
                                         }
                                     });
                             });
@@ -1415,8 +1125,11 @@ impl eframe::App for InstallerApp {
                                         if ui.add(egui::Button::selectable(
                                             self.selected_repo_idx == idx,
                                             *name,
-                                        ).frame_when_inactive(true)).clicked() {
+                                        ).frame_when_inactive(true)).clicked()
+                                            && self.selected_repo_idx != idx
+                                        {
                                             self.selected_repo_idx = idx;
+                                            self.refresh_releases(ctx.clone());
                                         }
                                     });
                                 }
@@ -1424,16 +1137,106 @@ impl eframe::App for InstallerApp {
                         },
                     );
                 });
+                if pick_image {
+                    self.pick_image_file();
+                }
+
+                ui.add_space(8.0);
+
+                // Version selection, or the local archive that replaces it
+                let mut pick_archive = false;
+                let mut pick_components = false;
+                let selected_tag = self.selected_tag.clone();
+                ui.columns(2, |columns| {
+                    columns[0].allocate_ui_with_layout(
+                        egui::Vec2::ZERO,
+                        egui::Layout::right_to_left(egui::Align::Center),
+                        |ui| {
+                            if let Some(path) = &self.local_archive {
+                                let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+                                ui.label(format!("📦 {}", file_name)).on_hover_text(path.display().to_string());
+                                return;
+                            }
+
+                            let selected_text = match &self.releases {
+                                None => "Loading versions...".to_string(),
+                                Some(_) => self
+                                    .selected_tag
+                                    .clone()
+                                    .unwrap_or_else(|| "Latest release".to_string()),
+                            };
+
+                            ui.add_enabled_ui(!show_progress && self.releases.is_some(), |ui| {
+                                let response = egui::ComboBox::from_id_salt("version_select")
+                                    .selected_text(selected_text)
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut self.selected_tag, None, "Latest release");
+                                        if let Some(Ok(releases)) = &self.releases {
+                                            for release in releases {
+                                                ui.selectable_value(
+                                                    &mut self.selected_tag,
+                                                    Some(release.tag_name.clone()),
+                                                    release.summary(),
+                                                );
+                                            }
+                                        }
+                                    })
+                                    .response;
+
+                                if let Some(Err(e)) = &self.releases {
+                                    response.on_hover_text(format!("Could not load older versions: {}", e));
+                                }
+                            });
+                        },
+                    );
+
+                    columns[1].allocate_ui_with_layout(
+                        egui::Vec2::ZERO,
+                        egui::Layout::left_to_right(egui::Align::Center),
+                        |ui| {
+                            ui.add_enabled_ui(!show_progress, |ui| {
+                                if self.local_archive.is_some() {
+                                    if ui.button("✖").on_hover_text("Download a release instead").clicked() {
+                                        self.clear_local_archive();
+                                    }
+                                } else if ui
+                                    .button("📂")
+                                    .on_hover_text("Install from a local .zip/.7z archive (or drop one on the window)")
+                                    .clicked()
+                                {
+                                    pick_archive = true;
+                                }
+                                let hint = if self.components.is_empty() && self.local_archive.is_none() {
+                                    "Choose components (downloads the release first)"
+                                } else {
+                                    "Choose components"
+                                };
+                                if (self.local_archive.is_none() || !self.components.is_empty())
+                                    && ui.button("🧩").on_hover_text(hint).clicked()
+                                {
+                                    pick_components = true;
+                                }
+                            });
+                        },
+                    );
+                });
+                // The components listed belong to the release that was selected
+                if self.local_archive.is_none() && self.selected_tag != selected_tag {
+                    self.clear_components();
+                }
+                if pick_archive {
+                    self.pick_local_archive();
+                }
+                if pick_components {
+                    self.choose_components(ctx.clone());
+                }
 
                 ui.add_space(12.0);
 
                 // Progress bar
                 if show_progress {
                     
-                    let (current, total, message) = {
-                        let p = self.progress.lock().unwrap();
-                        (p.current, p.total, p.message.clone())
-                    };
+                    let ProgressInfo { current, total, message } = self.progress.clone();
 
                     ui.horizontal(|ui| {
                         ui.vertical_centered(|ui| {
@@ -1509,19 +1312,73 @@ impl eframe::App for InstallerApp {
                             self.state,
                             AppState::FetchingRelease
                                 | AppState::Downloading
+                                | AppState::Verifying
+                                | AppState::CheckingArchive
+                                | AppState::CheckingCard
+                                | AppState::CheckingCapacity
                                 | AppState::Formatting
                                 | AppState::Extracting
                                 | AppState::Copying
+                                | AppState::VerifyingCard
+                                | AppState::Compressing
+                                | AppState::Benchmarking
                                 | AppState::AwaitingConfirmation
                                 | AppState::Ejecting
                                 | AppState::Cancelling
                         );
                         
                         if !is_busy {
-                            ui.add_enabled_ui(!is_busy && self.selected_drive_idx.is_some() && !self.drives.is_empty(), |ui| {
-                                if ui.button("Install").clicked() {
+                            let has_target = self.image_target.is_some() || (self.selected_drive_idx.is_some() && !self.drives.is_empty());
+                            ui.add_enabled_ui(!is_busy && has_target, |ui| {
+                                let install = if self.image_target.is_some() { "Build Image" } else { "Install" };
+                                if ui.button(install).clicked() {
+                                    self.mode = InstallMode::Fresh;
+                                    self.state = AppState::AwaitingConfirmation;
+                                }
+                                // An image is only ever built from scratch
+                                let mounted = self.image_target.is_none()
+                                    && self
+                                        .selected_drive_idx
+                                        .and_then(|idx| self.drives.get(idx))
+                                        .is_some_and(|drive| drive.mount_path.is_some());
+                                if ui
+                                    .add_enabled(mounted, egui::Button::new("Update"))
+                                    .on_hover_text("Replace the system files without formatting (keeps ROMs and saves)")
+                                    .on_disabled_hover_text("The card has to be mounted to update it")
+                                    .clicked()
+                                {
+                                    self.mode = InstallMode::Update;
+                                    self.state = AppState::AwaitingConfirmation;
+                                }
+                                // Read-only, so no confirmation
+                                if ui
+                                    .add_enabled(mounted, egui::Button::new("Verify"))
+                                    .on_hover_text("Read the installed files back and compare them with the release")
+                                    .on_disabled_hover_text("The card has to be mounted to verify it")
+                                    .clicked()
+                                {
+                                    self.mode = InstallMode::Verify;
+                                    self.start_installation(ctx.clone());
+                                }
+                                if ui
+                                    .add_enabled(mounted, egui::Button::new("Repair"))
+                                    .on_hover_text("Restore missing or damaged system files (keeps ROMs and saves)")
+                                    .on_disabled_hover_text("The card has to be mounted to repair it")
+                                    .clicked()
+                                {
+                                    self.mode = InstallMode::Repair;
                                     self.state = AppState::AwaitingConfirmation;
                                 }
+                                // Only writes a test file that is removed again, so no confirmation
+                                if ui
+                                    .add_enabled(mounted, egui::Button::new("Benchmark"))
+                                    .on_hover_text("Measure how fast the card reads and writes")
+                                    .on_disabled_hover_text("The card has to be mounted to benchmark it")
+                                    .clicked()
+                                {
+                                    self.mode = InstallMode::Benchmark;
+                                    self.start_installation(ctx.clone());
+                                }
                             });
                         }
 
@@ -1530,15 +1387,20 @@ impl eframe::App for InstallerApp {
                             self.state,
                             AppState::FetchingRelease
                                 | AppState::Downloading
+                                | AppState::Verifying
+                                | AppState::CheckingArchive
+                                | AppState::CheckingCard
+                                | AppState::CheckingCapacity
                                 | AppState::Formatting
                                 | AppState::Extracting
                                 | AppState::Copying
+                                | AppState::VerifyingCard
+                                | AppState::Compressing
+                                | AppState::Benchmarking
                         ) && self.cancel_token.is_some();
 
-                        if can_cancel {
-                            if ui.button("Cancel").clicked() {
-                                self.cancel_installation();
-                            }
+                        if can_cancel && ui.button("Cancel").clicked() {
+                            self.cancel_installation();
                         }
                     });
                 });
@@ -1547,4 +1409,41 @@ impl eframe::App for InstallerApp {
             });
         });
     }
-}
\ No newline at end of file
+}
+/// The benchmark results and the card's rating
+fn benchmark_results(ui: &mut egui::Ui, report: &BenchmarkReport) {
+    egui::Grid::new("benchmark_results").num_columns(2).spacing([16.0, 4.0]).show(ui, |ui| {
+        ui.label("Sequential write");
+        ui.label(format!("{:.1} MB/s", report.sequential_write_mbps));
+        ui.end_row();
+        ui.label("Sequential read");
+        ui.label(format!("{:.1} MB/s", report.sequential_read_mbps));
+        ui.end_row();
+        ui.label("Random 4K write");
+        ui.label(format!("{:.0} IOPS", report.random_write_iops));
+        ui.end_row();
+        ui.label("Random 4K read");
+        ui.label(format!("{:.0} IOPS", report.random_read_iops));
+        ui.end_row();
+    });
+    ui.add_space(8.0);
+    ui.label(report.rating());
+}
+
+/// Tick box for one component; unticking a folder also drops its children from the list
+fn component_checkbox(ui: &mut egui::Ui, component: &Component, excluded: &mut Vec<String>) {
+    let name = &component.item.name;
+    let mut included = !excluded.contains(name);
+    let label = format!("{} ({:.1} MB)", name, component.item.bytes as f64 / 1_048_576.0);
+    let response = ui
+        .add_enabled(!component.required, egui::Checkbox::new(&mut included, label))
+        .on_disabled_hover_text("Required by the release");
+    if response.changed() {
+        if included {
+            excluded.retain(|c| c != name);
+        } else {
+            excluded.retain(|c| !c.starts_with(name.as_str()));
+            excluded.push(name.clone());
+        }
+    }
+}
@@ -0,0 +1,567 @@
+// Release archive inspection
+// Identifies .zip/.7z files by their magic bytes, lists their entries without
+// extracting, and checks that an archive looks like a release (and that every entry
+// can be written safely to a FAT32 card) before the card is wiped
+
+use crate::config::{APP_NAME, RELEASE_MARKER_PATHS};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const ZIP_EMPTY_MAGIC: &[u8] = b"PK\x05\x06";
+const SEVEN_ZIP_MAGIC: &[u8] = b"7z\xBC\xAF\x27\x1C";
+
+/// Longest file or folder name FAT32 can store, in UTF-16 code units
+const FAT_MAX_NAME_UNITS: usize = 255;
+
+/// Characters FAT32 names can't contain, besides control characters ('/' separates folders)
+const FAT_INVALID_CHARS: &[char] = &['"', '*', ':', '<', '>', '?', '\\', '|'];
+
+/// Device names Windows refuses as file names, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1",
+    "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    SevenZip,
+}
+
+impl ArchiveKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArchiveKind::Zip => "zip",
+            ArchiveKind::SevenZip => "7z",
+        }
+    }
+}
+
+/// One file or folder inside an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, always with '/' separators
+    pub path: String,
+    pub is_dir: bool,
+    /// Uncompressed size in bytes
+    pub size: u64,
+    pub is_symlink: bool,
+}
+
+/// A file or folder in an archive, with the files and bytes below it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveItem {
+    /// Path inside the archive; folders end in '/'
+    pub name: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// A part of a release that can be left out of the install: a top-level file or
+/// folder, and for folders the folders directly inside
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    pub item: ArchiveItem,
+    /// Is or contains one of RELEASE_MARKER_PATHS, so it can't be skipped
+    pub required: bool,
+    pub children: Vec<Component>,
+}
+
+/// File count and uncompressed size of an archive, read from its index
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveSummary {
+    pub total_files: u64,
+    pub total_bytes: u64,
+    /// Sorted by name, as in top_level_names
+    pub top_level: Vec<ArchiveItem>,
+}
+
+/// Why an archive entry can't be installed
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryProblem {
+    /// Absolute path, drive letter or ".." that would land outside the destination
+    EscapesDestination,
+    /// FAT32 has no symlinks, and one could point anywhere on the PC
+    Symlink,
+    InvalidCharacter(char),
+    TrailingDotOrSpace,
+    ReservedName(String),
+    /// Length of the offending name in UTF-16 code units
+    NameTooLong(usize),
+    /// Same path as this other entry apart from letter case (FAT32 ignores case)
+    CaseConflict(String),
+}
+
+impl std::fmt::Display for EntryProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EntryProblem::EscapesDestination => write!(f, "path leads outside the destination"),
+            EntryProblem::Symlink => write!(f, "symbolic links are not supported"),
+            EntryProblem::InvalidCharacter(c) if c.is_control() => {
+                write!(f, "contains control character U+{:04X}", *c as u32)
+            }
+            EntryProblem::InvalidCharacter(c) => write!(f, "contains '{}', which FAT32 can't store", c),
+            EntryProblem::TrailingDotOrSpace => write!(f, "name ends with a dot or space"),
+            EntryProblem::ReservedName(name) => write!(f, "'{}' is a reserved device name", name),
+            EntryProblem::NameTooLong(units) => {
+                write!(f, "name is {} characters long (FAT32 allows {})", units, FAT_MAX_NAME_UNITS)
+            }
+            EntryProblem::CaseConflict(other) => write!(f, "clashes with {} (FAT32 ignores case)", other),
+        }
+    }
+}
+
+/// An entry that failed check_entry_names
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryIssue {
+    pub path: String,
+    pub problem: EntryProblem,
+}
+
+impl std::fmt::Display for EntryIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// Work out the archive format from the first bytes of the file (the extension may lie)
+pub fn detect_kind(path: &Path) -> Result<ArchiveKind, String> {
+    let mut header = [0u8; 6];
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let read = file.read(&mut header).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let header = &header[..read];
+
+    if header.starts_with(ZIP_MAGIC) || header.starts_with(ZIP_EMPTY_MAGIC) {
+        Ok(ArchiveKind::Zip)
+    } else if header.starts_with(SEVEN_ZIP_MAGIC) {
+        Ok(ArchiveKind::SevenZip)
+    } else {
+        Err(format!("{} is not a .zip or .7z archive", path.display()))
+    }
+}
+
+/// List every entry of a .zip or .7z archive without extracting it
+pub fn list_entries(path: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    match detect_kind(path)? {
+        ArchiveKind::Zip => list_zip_entries(path),
+        ArchiveKind::SevenZip => list_7z_entries(path),
+    }
+}
+
+fn list_zip_entries(path: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut zip = zip::ZipArchive::new(std::io::BufReader::new(file))
+        .map_err(|e| format!("Archive is damaged or incomplete: {}", e))?;
+
+    (0..zip.len())
+        .map(|i| {
+            let entry = zip.by_index_raw(i).map_err(|e| format!("Archive is damaged: {}", e))?;
+            Ok(ArchiveEntry {
+                path: normalize_entry_path(entry.name()),
+                is_dir: entry.is_dir(),
+                size: entry.size(),
+                is_symlink: entry.is_symlink(),
+            })
+        })
+        .collect()
+}
+
+fn list_7z_entries(path: &Path) -> Result<Vec<ArchiveEntry>, String> {
+    let archive = sevenz_rust::Archive::open(path).map_err(|e| format!("Archive is damaged or incomplete: {}", e))?;
+    Ok(archive
+        .files
+        .iter()
+        .map(|entry| ArchiveEntry {
+            path: normalize_entry_path(entry.name()),
+            is_dir: entry.is_directory,
+            size: entry.size,
+            is_symlink: is_7z_symlink(entry),
+        })
+        .collect())
+}
+
+/// 7z keeps Unix permissions in the upper half of the Windows attributes when
+/// 0x8000 is set; Windows symlinks are reparse points (0x400)
+pub fn is_7z_symlink(entry: &sevenz_rust::SevenZArchiveEntry) -> bool {
+    const UNIX_EXTENSION: u32 = 0x8000;
+    const REPARSE_POINT: u32 = 0x400;
+    const S_IFMT: u32 = 0o170000;
+    const S_IFLNK: u32 = 0o120000;
+
+    let attributes = entry.windows_attributes;
+    entry.has_windows_attributes
+        && (attributes & REPARSE_POINT != 0
+            || (attributes & UNIX_EXTENSION != 0 && (attributes >> 16) & S_IFMT == S_IFLNK))
+}
+
+fn normalize_entry_path(name: &str) -> String {
+    name.replace('\\', "/").trim_start_matches("./").trim_end_matches('/').to_string()
+}
+
+/// Names at the top level of the archive, folders with a trailing '/', sorted
+pub fn top_level_names(entries: &[ArchiveEntry]) -> Vec<String> {
+    let mut names: Vec<String> = entries
+        .iter()
+        .filter(|e| !e.path.is_empty())
+        .map(|e| match e.path.split_once('/') {
+            Some((first, _)) => format!("{}/", first),
+            None if e.is_dir => format!("{}/", e.path),
+            None => e.path.clone(),
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Count files and uncompressed bytes, in total and per top-level item
+pub fn summarize(entries: &[ArchiveEntry]) -> ArchiveSummary {
+    let top_level = items_in(entries, "");
+    ArchiveSummary {
+        total_files: top_level.iter().map(|item| item.files).sum(),
+        total_bytes: top_level.iter().map(|item| item.bytes).sum(),
+        top_level,
+    }
+}
+
+/// Files and folders directly inside `folder` ("" for the top level, otherwise a
+/// path ending in '/'), sorted by name
+fn items_in(entries: &[ArchiveEntry], folder: &str) -> Vec<ArchiveItem> {
+    let mut items: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for entry in entries {
+        let Some(rest) = entry.path.strip_prefix(folder).filter(|rest| !rest.is_empty()) else {
+            continue;
+        };
+        let name = match rest.split_once('/') {
+            Some((first, _)) => format!("{}{}/", folder, first),
+            None if entry.is_dir => format!("{}/", entry.path),
+            None => entry.path.clone(),
+        };
+        let (files, bytes) = items.entry(name).or_default();
+        if !entry.is_dir {
+            *files += 1;
+            *bytes += entry.size;
+        }
+    }
+    items
+        .into_iter()
+        .map(|(name, (files, bytes))| ArchiveItem { name, files, bytes })
+        .collect()
+}
+
+/// The parts of a release a user can choose from: top-level files and folders, with
+/// the folders one level down as children
+pub fn components(entries: &[ArchiveEntry]) -> Vec<Component> {
+    let component = |item: ArchiveItem, children: Vec<Component>| Component {
+        required: RELEASE_MARKER_PATHS.iter().any(|marker| {
+            let (marker, path) = (marker.trim_end_matches('/'), item.name.trim_end_matches('/'));
+            is_inside(marker, path) || is_inside(path, marker)
+        }),
+        item,
+        children,
+    };
+
+    items_in(entries, "")
+        .into_iter()
+        .map(|item| {
+            let children = if item.name.ends_with('/') {
+                items_in(entries, &item.name)
+                    .into_iter()
+                    .filter(|child| child.name.ends_with('/'))
+                    .map(|child| component(child, Vec::new()))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            component(item, children)
+        })
+        .collect()
+}
+
+/// Whether `path` is `folder` or lies below it (both without a trailing '/')
+fn is_inside(path: &str, folder: &str) -> bool {
+    path == folder || path.strip_prefix(folder).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Whether an archive entry belongs to one of the `excluded` components
+/// (archive paths, folders with or without a trailing '/')
+pub fn is_excluded(name: &str, excluded: &[String]) -> bool {
+    let path = normalize_entry_path(name);
+    excluded
+        .iter()
+        .map(|component| normalize_entry_path(component))
+        .any(|component| !component.is_empty() && is_inside(&path, &component))
+}
+
+/// Check that every excluded component exists and none of them is required
+pub fn check_exclusions(entries: &[ArchiveEntry], excluded: &[String]) -> Result<(), String> {
+    for component in excluded {
+        if !entries.iter().any(|e| is_excluded(&e.path, std::slice::from_ref(component))) {
+            return Err(format!("There is no {} in this release", component));
+        }
+    }
+    match RELEASE_MARKER_PATHS.iter().find(|marker| is_excluded(marker, excluded)) {
+        Some(marker) => Err(format!("{} is required and can't be skipped", marker)),
+        None => Ok(()),
+    }
+}
+
+/// Space the entries take up on a FAT32 card with this cluster size: every file and
+/// folder occupies whole clusters (empty files take none)
+pub fn size_on_card(entries: &[ArchiveEntry], cluster_size: u64) -> u64 {
+    entries
+        .iter()
+        .filter(|e| !e.path.is_empty())
+        .map(|e| if e.is_dir { cluster_size } else { e.size.div_ceil(cluster_size) * cluster_size })
+        .sum()
+}
+
+/// Check that the archive has a release layout: at least one of RELEASE_MARKER_PATHS
+/// at its top level (so e.g. a zip of a folder, or a random download, is rejected)
+pub fn check_release_layout(entries: &[ArchiveEntry]) -> Result<(), String> {
+    if entries.iter().all(|e| e.is_dir) {
+        return Err("The archive is empty".to_string());
+    }
+    if RELEASE_MARKER_PATHS.is_empty() {
+        return Ok(());
+    }
+
+    let top_level = top_level_names(entries);
+    if RELEASE_MARKER_PATHS.iter().any(|marker| top_level.iter().any(|name| name == marker)) {
+        return Ok(());
+    }
+
+    let mut found = top_level.iter().take(8).cloned().collect::<Vec<_>>().join(", ");
+    if top_level.len() > 8 {
+        found.push_str(", ...");
+    }
+    Err(format!(
+        "This doesn't look like a {} release: expected {} at the top level of the archive, found: {}",
+        APP_NAME,
+        RELEASE_MARKER_PATHS.join(" or "),
+        found
+    ))
+}
+
+/// Archive entry name as a relative path, or None if it would land outside the
+/// destination (absolute paths, drive letters, "..")
+pub fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    let normalized = name.replace('\\', "/");
+    if normalized.starts_with('/') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for part in normalized.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            // "C:" would make Windows treat the rest as a path on that drive
+            _ if part.len() >= 2 && part.as_bytes()[1] == b':' && part.as_bytes()[0].is_ascii_alphabetic() => {
+                return None
+            }
+            _ => path.push(part),
+        }
+    }
+    Some(path)
+}
+
+/// Check every entry before anything is written: paths that escape the destination,
+/// symlinks, and names a FAT32 card can't store
+pub fn check_entry_names(entries: &[ArchiveEntry]) -> Vec<EntryIssue> {
+    let mut issues = Vec::new();
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    for entry in entries.iter().filter(|e| !e.path.is_empty()) {
+        let problem = if entry.is_symlink {
+            Some(EntryProblem::Symlink)
+        } else if safe_relative_path(&entry.path).is_none() {
+            Some(EntryProblem::EscapesDestination)
+        } else {
+            entry
+                .path
+                .split('/')
+                .filter(|part| !part.is_empty() && *part != ".")
+                .find_map(fat_name_problem)
+        };
+
+        let problem = problem.or_else(|| match seen.get(&entry.path.to_lowercase()) {
+            Some(other) if *other != entry.path => Some(EntryProblem::CaseConflict(other.to_string())),
+            Some(_) => None,
+            None => {
+                seen.insert(entry.path.to_lowercase(), &entry.path);
+                None
+            }
+        });
+
+        if let Some(problem) = problem {
+            issues.push(EntryIssue {
+                path: entry.path.clone(),
+                problem,
+            });
+        }
+    }
+    issues
+}
+
+/// Why FAT32 can't store one path component, if it can't
+fn fat_name_problem(name: &str) -> Option<EntryProblem> {
+    if let Some(c) = name.chars().find(|c| c.is_control() || FAT_INVALID_CHARS.contains(c)) {
+        return Some(EntryProblem::InvalidCharacter(c));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Some(EntryProblem::TrailingDotOrSpace);
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved)) {
+        return Some(EntryProblem::ReservedName(name.to_string()));
+    }
+    let units = name.encode_utf16().count();
+    if units > FAT_MAX_NAME_UNITS {
+        return Some(EntryProblem::NameTooLong(units));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_zip::write_zip;
+
+    #[test]
+    fn test_detect_and_list_zip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.7z"); // wrong extension on purpose
+        write_zip(
+            &path,
+            &[(".system/", b""), (".system/version.txt", b"v1"), ("MinUI.zip", b"minui"), ("Roms/GB/readme.txt", b"roms")],
+        );
+
+        assert_eq!(detect_kind(&path).unwrap(), ArchiveKind::Zip);
+        let entries = list_entries(&path).unwrap();
+        assert_eq!(top_level_names(&entries), vec![".system/", "MinUI.zip", "Roms/"]);
+        assert!(check_release_layout(&entries).is_ok());
+
+        std::fs::write(&path, b"<html>not an archive</html>").unwrap();
+        assert!(detect_kind(&path).is_err());
+    }
+
+    #[test]
+    fn test_summarize_and_size_on_card() {
+        let entry = |path: &str, is_dir: bool, size: u64| ArchiveEntry {
+            path: path.to_string(),
+            is_dir,
+            size,
+            is_symlink: false,
+        };
+        let entries = vec![
+            entry(".system", true, 0),
+            entry(".system/bin/tool", false, 5000),
+            entry(".system/version.txt", false, 10),
+            entry("MinUI.zip", false, 4096),
+            entry("Roms", true, 0),
+        ];
+
+        let summary = summarize(&entries);
+        assert_eq!((summary.total_files, summary.total_bytes), (3, 9106));
+        assert_eq!(
+            summary.top_level,
+            vec![
+                ArchiveItem { name: ".system/".to_string(), files: 2, bytes: 5010 },
+                ArchiveItem { name: "MinUI.zip".to_string(), files: 1, bytes: 4096 },
+                ArchiveItem { name: "Roms/".to_string(), files: 0, bytes: 0 },
+            ]
+        );
+        // 2 folders + 2 clusters + 1 cluster + 1 cluster
+        assert_eq!(size_on_card(&entries, 4096), 6 * 4096);
+    }
+
+    #[test]
+    fn test_components_and_exclusions() {
+        let entry = |path: &str, is_dir: bool| ArchiveEntry {
+            path: path.to_string(),
+            is_dir,
+            size: 10,
+            is_symlink: false,
+        };
+        let entries = vec![
+            entry(".system/res/font.ttf", false),
+            entry("Tools/tg5040/Clock.pak/launch.sh", false),
+            entry("Tools/rg35xx/Files.pak/launch.sh", false),
+            entry("Tools/readme.txt", false),
+            entry("Bios", true),
+            entry("MinUI.zip", false),
+        ];
+
+        let tree = components(&entries);
+        let names: Vec<(&str, bool)> = tree.iter().map(|c| (c.item.name.as_str(), c.required)).collect();
+        assert_eq!(
+            names,
+            vec![(".system/", true), ("Bios/", false), ("MinUI.zip", true), ("Tools/", false)]
+        );
+        let tools: Vec<&str> = tree[3].children.iter().map(|c| c.item.name.as_str()).collect();
+        assert_eq!(tools, vec!["Tools/rg35xx/", "Tools/tg5040/"]);
+        assert_eq!((tree[3].item.files, tree[3].children[1].item.files), (3, 1));
+
+        let excluded = vec!["Tools/rg35xx/".to_string(), "Bios".to_string()];
+        assert!(is_excluded("Tools/rg35xx/Files.pak/launch.sh", &excluded));
+        assert!(is_excluded("Bios/", &excluded));
+        assert!(!is_excluded("Tools/rg35xx-plus/x", &excluded));
+        assert!(!is_excluded("Tools/readme.txt", &excluded));
+        assert!(check_exclusions(&entries, &excluded).is_ok());
+        assert!(check_exclusions(&entries, &[".system/".to_string()]).is_err());
+        assert!(check_exclusions(&entries, &["Extras/".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_wrapped_release() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.zip");
+        write_zip(&path, &[("NextUI/.system/version.txt", b"v1"), ("NextUI/MinUI.zip", b"minui")]);
+
+        let err = check_release_layout(&list_entries(&path).unwrap()).unwrap_err();
+        assert!(err.contains("found: NextUI/"));
+    }
+
+    #[test]
+    fn test_check_entry_names() {
+        let entry = |path: &str| ArchiveEntry {
+            path: path.to_string(),
+            is_dir: false,
+            size: 1,
+            is_symlink: false,
+        };
+        let long = "a".repeat(256);
+        let entries = vec![
+            entry(".system/version.txt"),
+            entry("../../etc/cron.d/evil"),
+            entry("/etc/passwd"),
+            entry("C:/Windows/evil.dll"),
+            entry("Roms/what?.gb"),
+            entry("Roms/trailing."),
+            entry("Tools/con.txt"),
+            entry(&format!("Roms/{}", long)),
+            entry(".system/Version.txt"),
+            ArchiveEntry { is_symlink: true, ..entry("link") },
+        ];
+
+        let problems: Vec<EntryProblem> = check_entry_names(&entries).into_iter().map(|i| i.problem).collect();
+        assert_eq!(
+            problems,
+            vec![
+                EntryProblem::EscapesDestination,
+                EntryProblem::EscapesDestination,
+                EntryProblem::EscapesDestination,
+                EntryProblem::InvalidCharacter('?'),
+                EntryProblem::TrailingDotOrSpace,
+                EntryProblem::ReservedName("con.txt".to_string()),
+                EntryProblem::NameTooLong(256),
+                EntryProblem::CaseConflict(".system/version.txt".to_string()),
+                EntryProblem::Symlink,
+            ]
+        );
+
+        assert_eq!(safe_relative_path("./a\\b/c"), Some(PathBuf::from("a/b/c")));
+        assert_eq!(safe_relative_path("a/../../b"), None);
+    }
+}
@@ -0,0 +1,308 @@
+// Card speed benchmark
+// A slow card makes the whole device feel laggy. This times sequential and random 4K
+// reads and writes through a test file on the mounted card (removed again afterwards,
+// so nothing on the card is touched) and rates the results against the speed class
+// (U1/U3) and application performance class (A1/A2) logos printed on cards.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Test file in the root of the card
+pub const BENCHMARK_FILE_NAME: &str = "nextui_benchmark.tmp";
+
+/// Size of the test file; smaller if the card doesn't have twice this free
+const TEST_FILE_BYTES: u64 = 128 * 1024 * 1024;
+
+/// Below this the sequential results are mostly the card's write cache
+const MIN_TEST_FILE_BYTES: u64 = 16 * 1024 * 1024;
+
+const SEQUENTIAL_CHUNK_BYTES: usize = 1024 * 1024;
+const RANDOM_BLOCK_BYTES: usize = 4096;
+const RANDOM_WRITE_OPS: u64 = 1024;
+const RANDOM_READ_OPS: u64 = 2048;
+
+/// Unbuffered I/O on Windows needs buffers aligned to the sector size
+const BUFFER_ALIGN: usize = 4096;
+
+/// Speed classes and the sequential write speed (MB/s) they promise
+const SPEED_CLASSES: &[(&str, f64)] = &[("U3", 30.0), ("U1", 10.0)];
+
+/// Application performance classes and the random 4K read and write IOPS they
+/// promise; both also need 10 MB/s sequential writes
+const APP_CLASSES: &[(&str, f64, f64)] = &[("A2", 4000.0, 2000.0), ("A1", 1500.0, 500.0)];
+
+#[derive(Debug, Clone)]
+pub enum BenchmarkProgress {
+    Running { test: &'static str, done: u64, total: u64 },
+    Completed,
+    Cancelled,
+}
+
+/// Speeds as SD cards are rated: MB/s with 1 MB = 1,000,000 bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkReport {
+    pub test_bytes: u64,
+    pub sequential_write_mbps: f64,
+    pub sequential_read_mbps: f64,
+    pub random_write_iops: f64,
+    pub random_read_iops: f64,
+}
+
+impl BenchmarkReport {
+    /// The fastest speed class the card keeps up with, None if it's slower than U1
+    pub fn speed_class(&self) -> Option<&'static str> {
+        SPEED_CLASSES
+            .iter()
+            .find(|(_, write_mbps)| self.sequential_write_mbps >= *write_mbps)
+            .map(|(name, _)| *name)
+    }
+
+    /// The fastest application performance class the card keeps up with
+    pub fn app_class(&self) -> Option<&'static str> {
+        if self.sequential_write_mbps < 10.0 {
+            return None;
+        }
+        APP_CLASSES
+            .iter()
+            .find(|(_, read_iops, write_iops)| self.random_read_iops >= *read_iops && self.random_write_iops >= *write_iops)
+            .map(|(name, _, _)| *name)
+    }
+
+    /// One line rating the card, e.g. "Performs like a U3 A1 card"
+    pub fn rating(&self) -> String {
+        match (self.speed_class(), self.app_class()) {
+            (None, _) => "Slower than U1: expect long loading times and stutter".to_string(),
+            (Some(speed), Some(app)) => format!("Performs like a {} {} card", speed, app),
+            (Some(speed), None) => format!("Performs like a {} card, but random access is below A1", speed),
+        }
+    }
+
+    /// The results as log lines
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("Sequential write: {:.1} MB/s", self.sequential_write_mbps),
+            format!("Sequential read: {:.1} MB/s", self.sequential_read_mbps),
+            format!("Random 4K write: {:.0} IOPS", self.random_write_iops),
+            format!("Random 4K read: {:.0} IOPS", self.random_read_iops),
+            self.rating(),
+        ]
+    }
+}
+
+/// Time reads and writes of a test file on the card mounted at `card`
+pub async fn benchmark_card(
+    card: &Path,
+    progress_tx: mpsc::UnboundedSender<BenchmarkProgress>,
+    cancel_token: CancellationToken,
+) -> Result<BenchmarkReport, String> {
+    crate::debug::log_section("Card Benchmark");
+    let available = crate::pipeline::get_available_disk_space(card);
+    // Leave the card at least as much free space as the test file takes
+    let test_bytes = TEST_FILE_BYTES.min(available / 2) / SEQUENTIAL_CHUNK_BYTES as u64 * SEQUENTIAL_CHUNK_BYTES as u64;
+    crate::debug::log(&format!("Available space: {} MB, test file: {} MB", available / 1_048_576, test_bytes / 1_048_576));
+    if test_bytes < MIN_TEST_FILE_BYTES {
+        return Err(format!(
+            "Not enough free space on the card for the benchmark: needs {} MB, {} MB available",
+            MIN_TEST_FILE_BYTES * 2 / 1_048_576,
+            available / 1_048_576
+        ));
+    }
+
+    let path = card.join(BENCHMARK_FILE_NAME);
+    let (tx, token) = (progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        let result = open_test_file(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))
+            .and_then(|mut file| run_tests(&mut file, test_bytes, &tx, &token));
+        let _ = std::fs::remove_file(&path);
+        result
+    })
+    .await
+    .map_err(|e| format!("Benchmark task failed: {}", e))?;
+    finish(result, &progress_tx, &cancel_token)
+}
+
+/// Log the outcome and tell the UI
+fn finish(
+    result: Result<BenchmarkReport, String>,
+    progress_tx: &mpsc::UnboundedSender<BenchmarkProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<BenchmarkReport, String> {
+    match &result {
+        Ok(report) => {
+            crate::debug::log(&format!("Benchmark results ({} MB test file): {:?}", report.test_bytes / 1_048_576, report));
+            let _ = progress_tx.send(BenchmarkProgress::Completed);
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            let _ = progress_tx.send(BenchmarkProgress::Cancelled);
+        }
+        Err(_) => {}
+    }
+    result
+}
+
+/// Create the test file so that reads and writes bypass the OS cache where the
+/// platform allows it per file (on Linux the cache is dropped before each read test)
+fn open_test_file(path: &Path) -> std::io::Result<std::fs::File> {
+    let mut options = std::fs::OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        use windows::Win32::Storage::FileSystem::{FILE_FLAG_NO_BUFFERING, FILE_FLAG_WRITE_THROUGH};
+        options.custom_flags((FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH).0);
+    }
+    let file = options.open(path)?;
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+        unsafe {
+            libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1);
+        }
+    }
+    Ok(file)
+}
+
+/// Make the next reads come from the card, and tell the OS how they will be made
+fn prepare_reads(file: &std::fs::File, random: bool) -> std::io::Result<()> {
+    file.sync_all()?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        let advice = if random { libc::POSIX_FADV_RANDOM } else { libc::POSIX_FADV_SEQUENTIAL };
+        unsafe {
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice);
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = random;
+    Ok(())
+}
+
+/// Fill a `test_bytes` file sequentially, read it back, then write and read random
+/// 4K blocks inside it. Every write test includes flushing to the card
+fn run_tests(
+    file: &mut std::fs::File,
+    test_bytes: u64,
+    progress_tx: &mpsc::UnboundedSender<BenchmarkProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<BenchmarkReport, String> {
+    let io_error = |test: &str, e: std::io::Error| format!("{} failed: {}", test, e);
+    let mut storage = vec![0u8; SEQUENTIAL_CHUNK_BYTES + BUFFER_ALIGN];
+    let start = storage.as_ptr().align_offset(BUFFER_ALIGN);
+    let buffer = &mut storage[start..start + SEQUENTIAL_CHUNK_BYTES];
+    // Random data, so cards that compress or skip zeros can't take shortcuts
+    fastrand::fill(buffer);
+
+    let chunks = test_bytes / SEQUENTIAL_CHUNK_BYTES as u64;
+    let blocks = test_bytes / RANDOM_BLOCK_BYTES as u64;
+    let step = |test: &'static str, done: u64, total: u64| {
+        if cancel_token.is_cancelled() {
+            return Err("Benchmark cancelled".to_string());
+        }
+        let _ = progress_tx.send(BenchmarkProgress::Running { test, done, total });
+        Ok(())
+    };
+
+    let test = "Sequential write";
+    let started = Instant::now();
+    for chunk in 0..chunks {
+        step(test, chunk, chunks)?;
+        file.write_all(buffer).map_err(|e| io_error(test, e))?;
+    }
+    file.sync_all().map_err(|e| io_error(test, e))?;
+    let sequential_write_mbps = test_bytes as f64 / 1e6 / seconds(started);
+    crate::debug::log(&format!("{}: {:.1} MB/s", test, sequential_write_mbps));
+
+    let test = "Sequential read";
+    prepare_reads(file, false).map_err(|e| io_error(test, e))?;
+    file.seek(SeekFrom::Start(0)).map_err(|e| io_error(test, e))?;
+    let started = Instant::now();
+    for chunk in 0..chunks {
+        step(test, chunk, chunks)?;
+        file.read_exact(buffer).map_err(|e| io_error(test, e))?;
+    }
+    let sequential_read_mbps = test_bytes as f64 / 1e6 / seconds(started);
+    crate::debug::log(&format!("{}: {:.1} MB/s", test, sequential_read_mbps));
+
+    let block = &mut buffer[..RANDOM_BLOCK_BYTES];
+    let test = "Random 4K write";
+    let started = Instant::now();
+    for op in 0..RANDOM_WRITE_OPS {
+        step(test, op, RANDOM_WRITE_OPS)?;
+        let offset = fastrand::u64(0..blocks) * RANDOM_BLOCK_BYTES as u64;
+        file.seek(SeekFrom::Start(offset)).map_err(|e| io_error(test, e))?;
+        file.write_all(block).map_err(|e| io_error(test, e))?;
+    }
+    file.sync_all().map_err(|e| io_error(test, e))?;
+    let random_write_iops = RANDOM_WRITE_OPS as f64 / seconds(started);
+    crate::debug::log(&format!("{}: {:.0} IOPS", test, random_write_iops));
+
+    let test = "Random 4K read";
+    prepare_reads(file, true).map_err(|e| io_error(test, e))?;
+    let started = Instant::now();
+    for op in 0..RANDOM_READ_OPS {
+        step(test, op, RANDOM_READ_OPS)?;
+        let offset = fastrand::u64(0..blocks) * RANDOM_BLOCK_BYTES as u64;
+        file.seek(SeekFrom::Start(offset)).map_err(|e| io_error(test, e))?;
+        file.read_exact(block).map_err(|e| io_error(test, e))?;
+    }
+    let random_read_iops = RANDOM_READ_OPS as f64 / seconds(started);
+    crate::debug::log(&format!("{}: {:.0} IOPS", test, random_read_iops));
+
+    Ok(BenchmarkReport {
+        test_bytes,
+        sequential_write_mbps,
+        sequential_read_mbps,
+        random_write_iops,
+        random_read_iops,
+    })
+}
+
+fn seconds(started: Instant) -> f64 {
+    started.elapsed().as_secs_f64().max(1e-6)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmark() {
+        let report = |write_mbps, read_iops, write_iops| BenchmarkReport {
+            test_bytes: TEST_FILE_BYTES,
+            sequential_write_mbps: write_mbps,
+            sequential_read_mbps: 90.0,
+            random_write_iops: write_iops,
+            random_read_iops: read_iops,
+        };
+        let u3_a2 = report(45.0, 4500.0, 2100.0);
+        assert_eq!((u3_a2.speed_class(), u3_a2.app_class()), (Some("U3"), Some("A2")));
+        let u1_a1 = report(12.0, 2000.0, 1000.0);
+        assert_eq!((u1_a1.speed_class(), u1_a1.app_class()), (Some("U1"), Some("A1")));
+        assert_eq!(u1_a1.rating(), "Performs like a U1 A1 card");
+        let u3 = report(40.0, 3000.0, 200.0);
+        assert_eq!((u3.speed_class(), u3.app_class()), (Some("U3"), None));
+        // Application classes also need the minimum sequential speed
+        let slow = report(6.0, 5000.0, 3000.0);
+        assert_eq!((slow.speed_class(), slow.app_class()), (None, None));
+
+        // A real run on a small file, then one that is cancelled straight away
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(BENCHMARK_FILE_NAME);
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let token = CancellationToken::new();
+        let mut file = open_test_file(&path).unwrap();
+        let report = run_tests(&mut file, 4 * 1024 * 1024, &tx, &token).unwrap();
+        assert_eq!(report.test_bytes, 4 * 1024 * 1024);
+        assert!(report.sequential_write_mbps > 0.0 && report.random_read_iops > 0.0);
+        assert!(matches!(rx.try_recv(), Ok(BenchmarkProgress::Running { test: "Sequential write", .. })));
+
+        token.cancel();
+        assert_eq!(run_tests(&mut file, 4 * 1024 * 1024, &tx, &token).unwrap_err(), "Benchmark cancelled");
+    }
+}
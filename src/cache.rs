@@ -0,0 +1,298 @@
+// Persistent download cache
+// Release assets are kept under <cache dir>/<TEMP_PREFIX>/downloads/<repo>/<tag>/<asset>
+// so flashing several cards with the same release only downloads it once.
+// index.json records what each file is (repo, tag, asset, size, digest) and when it
+// was last used; the least recently used entries are evicted above CACHE_MAX_BYTES.
+
+use crate::config::{CACHE_MAX_BYTES, TEMP_PREFIX};
+use crate::github::Asset;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Identifies one cached asset
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub repo: String,
+    pub tag: String,
+    pub asset: String,
+    pub size: u64,
+    /// Published SHA-256, when the release provides one
+    pub sha256: Option<String>,
+}
+
+impl CacheKey {
+    pub fn new(repo_url: &str, tag: &str, asset: &Asset, sha256: Option<&str>) -> Self {
+        Self {
+            repo: repo_url.to_string(),
+            tag: tag.to_string(),
+            asset: asset.name.clone(),
+            size: asset.size,
+            sha256: sha256.map(String::from),
+        }
+    }
+
+    /// Path of the cached file, relative to the cache root
+    fn relative_path(&self) -> PathBuf {
+        PathBuf::from(sanitize(&self.repo))
+            .join(sanitize(&self.tag))
+            .join(sanitize(&self.asset))
+    }
+
+    /// Same repo/tag/asset; a different size or digest means the release was re-uploaded
+    fn same_file(&self, other: &CacheKey) -> bool {
+        self.repo == other.repo && self.tag == other.tag && self.asset == other.asset
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    key: CacheKey,
+    /// Seconds since the Unix epoch
+    last_used: u64,
+}
+
+pub struct DownloadCache {
+    root: PathBuf,
+    max_bytes: u64,
+    entries: Vec<CacheEntry>,
+}
+
+impl DownloadCache {
+    /// The cache in the user's cache directory
+    pub fn open_default() -> Self {
+        Self::open(default_cache_dir(), CACHE_MAX_BYTES)
+    }
+
+    pub fn open(root: PathBuf, max_bytes: u64) -> Self {
+        let entries = std::fs::read_to_string(root.join("index.json"))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        Self { root, max_bytes, entries }
+    }
+
+    /// Where `key` is (or will be) stored; the download is written straight here
+    pub fn path_for(&self, key: &CacheKey) -> PathBuf {
+        self.root.join(key.relative_path())
+    }
+
+    /// The cached file for `key`, if it is complete and matches the expected size/digest
+    pub fn lookup(&mut self, key: &CacheKey) -> Option<PathBuf> {
+        let index = self.entries.iter().position(|e| &e.key == key)?;
+        let path = self.path_for(key);
+        let complete = std::fs::metadata(&path).map(|m| m.len() == key.size).unwrap_or(false);
+        if !complete {
+            crate::debug::log(&format!("Cache entry for {} is missing or incomplete, dropping it", key.asset));
+            self.entries.remove(index);
+            self.save();
+            return None;
+        }
+
+        self.entries[index].last_used = now();
+        self.save();
+        Some(path)
+    }
+
+    /// Record a verified download and evict old entries to stay under the size limit
+    pub fn insert(&mut self, key: CacheKey) {
+        // A re-uploaded asset replaces the old entry for the same file
+        self.entries.retain(|e| !e.key.same_file(&key));
+        self.entries.push(CacheEntry { key: key.clone(), last_used: now() });
+        self.evict(&key);
+        self.save();
+    }
+
+    /// Forget `key` and delete its file (e.g. after a checksum mismatch)
+    pub fn remove(&mut self, key: &CacheKey) {
+        self.entries.retain(|e| &e.key != key);
+        let _ = std::fs::remove_file(self.path_for(key));
+        self.save();
+    }
+
+    /// Total size of the cached files in bytes
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.key.size).sum()
+    }
+
+    /// Delete everything in the cache, including partial downloads; returns the bytes freed
+    pub fn clear(&mut self) -> Result<u64, String> {
+        let freed = dir_size(&self.root);
+        self.entries.clear();
+        if self.root.exists() {
+            std::fs::remove_dir_all(&self.root).map_err(|e| format!("Failed to clear download cache: {}", e))?;
+        }
+        crate::debug::log(&format!("Cleared download cache ({} bytes)", freed));
+        Ok(freed)
+    }
+
+    /// Remove least recently used entries (never `keep`) until under max_bytes
+    fn evict(&mut self, keep: &CacheKey) {
+        self.entries.sort_by_key(|e| e.last_used);
+        while self.total_size() > self.max_bytes {
+            let Some(index) = self.entries.iter().position(|e| &e.key != keep) else {
+                break;
+            };
+            let entry = self.entries.remove(index);
+            crate::debug::log(&format!("Evicting {} {} from download cache", entry.key.tag, entry.key.asset));
+            let _ = std::fs::remove_file(self.path_for(&entry.key));
+        }
+    }
+
+    fn save(&self) {
+        let _ = std::fs::create_dir_all(&self.root);
+        if let Ok(text) = serde_json::to_string_pretty(&self.entries) {
+            let _ = std::fs::write(self.root.join("index.json"), text);
+        }
+    }
+}
+
+/// <cache dir>/<TEMP_PREFIX>/downloads
+pub fn default_cache_dir() -> PathBuf {
+    cache_base_dir().join(TEMP_PREFIX).join("downloads")
+}
+
+/// Directory for downloads and temp extraction
+/// On Linux/macOS, use cache dir to avoid temp space issues
+/// Linux: ~/.cache, macOS: ~/Library/Caches
+pub fn cache_base_dir() -> PathBuf {
+    #[cfg(target_os = "linux")]
+    {
+        // If running as root via sudo or pkexec, try to use the actual user's cache directory
+        if unsafe { libc::geteuid() } == 0 {
+            // First check for SUDO_USER (command-line sudo)
+            if let Ok(sudo_user) = std::env::var("SUDO_USER") {
+                let user_home = PathBuf::from(format!("/home/{}", sudo_user));
+                if user_home.exists() {
+                    let user_cache = user_home.join(".cache");
+                    crate::debug::log(&format!("Using cache dir for sudo user {}: {:?}", sudo_user, user_cache));
+                    return user_cache;
+                }
+                crate::debug::log(&format!("User home not found at {:?}, using default", user_home));
+            }
+            // Check for PKEXEC_UID (GUI elevation via pkexec)
+            else if let Ok(pkexec_uid) = std::env::var("PKEXEC_UID") {
+                if let Ok(uid) = pkexec_uid.parse::<u32>() {
+                    // Get username from UID using libc
+                    let pwd = unsafe { libc::getpwuid(uid) };
+                    if !pwd.is_null() {
+                        let username = unsafe {
+                            std::ffi::CStr::from_ptr((*pwd).pw_name)
+                                .to_string_lossy()
+                                .to_string()
+                        };
+                        let user_home = PathBuf::from(format!("/home/{}", username));
+                        if user_home.exists() {
+                            let user_cache = user_home.join(".cache");
+                            crate::debug::log(&format!("Using cache dir for pkexec user {} (UID {}): {:?}", username, uid, user_cache));
+                            return user_cache;
+                        }
+                        crate::debug::log(&format!("User home not found at {:?}, using default", user_home));
+                    } else {
+                        crate::debug::log(&format!("Failed to get username for UID {}, using default", uid));
+                    }
+                } else {
+                    crate::debug::log(&format!("Failed to parse PKEXEC_UID '{}', using default", pkexec_uid));
+                }
+            } else {
+                crate::debug::log("Running as root, using root's cache dir");
+            }
+        }
+        dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
+    }
+    #[cfg(target_os = "macos")]
+    {
+        dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        std::env::temp_dir()
+    }
+}
+
+/// Keep path components to characters every OS accepts
+fn sanitize(component: &str) -> String {
+    let cleaned: String = component
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    match cleaned.trim_matches('.') {
+        "" => "_".to_string(),
+        name => name.to_string(),
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(tag: &str, size: u64) -> CacheKey {
+        CacheKey {
+            repo: "LoveRetro/NextUI".to_string(),
+            tag: tag.to_string(),
+            asset: "NextUI.zip".to_string(),
+            size,
+            sha256: None,
+        }
+    }
+
+    fn store(cache: &mut DownloadCache, key: &CacheKey) {
+        let path = cache.path_for(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, vec![0u8; key.size as usize]).unwrap();
+        cache.insert(key.clone());
+    }
+
+    #[test]
+    fn test_lookup_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = DownloadCache::open(dir.path().to_path_buf(), 1000);
+        let v1 = key("v1", 100);
+        store(&mut cache, &v1);
+
+        let mut reopened = DownloadCache::open(dir.path().to_path_buf(), 1000);
+        assert_eq!(reopened.lookup(&v1), Some(reopened.path_for(&v1)));
+        // Same tag re-uploaded with a different size is a miss
+        assert_eq!(reopened.lookup(&key("v1", 99)), None);
+    }
+
+    #[test]
+    fn test_eviction_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = DownloadCache::open(dir.path().to_path_buf(), 250);
+        let (v1, v2, v3) = (key("v1", 100), key("v2", 100), key("v3", 100));
+        store(&mut cache, &v1);
+        store(&mut cache, &v2);
+        cache.entries.iter_mut().find(|e| e.key == v1).unwrap().last_used = 0;
+        store(&mut cache, &v3);
+
+        // v1 was least recently used
+        assert_eq!(cache.lookup(&v1), None);
+        assert!(!cache.path_for(&v1).exists());
+        assert!(cache.lookup(&v2).is_some() && cache.lookup(&v3).is_some());
+
+        assert!(cache.clear().unwrap() >= 200);
+        assert_eq!(cache.total_size(), 0);
+        assert!(!dir.path().join("LoveRetro_NextUI").exists());
+    }
+}
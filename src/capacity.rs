@@ -0,0 +1,330 @@
+// Fake capacity check
+// Counterfeit cards report more space than they have: writes past the real capacity
+// are dropped or wrap around onto the start of the card, so the filesystem looks
+// fine until saves get overwritten. Like f3probe, this writes tagged test blocks
+// across the whole reported capacity, reads them back, and works out how much of the
+// card really holds data. It overwrites the card, so it only runs before formatting.
+
+// Only the Linux build can reach the probe
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::io::{Read, Seek, SeekFrom, Write};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Whether cards can be checked on this platform (raw device access is needed)
+pub const CAPACITY_CHECK_SUPPORTED: bool = cfg!(target_os = "linux");
+
+/// Why the check can't be offered elsewhere, shown where it would be
+pub const CAPACITY_CHECK_UNAVAILABLE: &str =
+    "The capacity check needs raw access to the card, which is only implemented on Linux so far";
+
+/// Test blocks are spread across the card so that at least this many are written
+const MIN_BLOCKS: u64 = 256;
+
+/// Largest test block; the spacing of the blocks is a power of two, so a card that
+/// wraps at a power of two (as fakes do) puts its high blocks onto lower ones
+const MAX_BLOCK_BYTES: u64 = 1024 * 1024;
+
+const SECTOR_BYTES: usize = 512;
+
+/// Start of every sector of a test block
+const TAG_MAGIC: &[u8; 8] = b"NXCAPCHK";
+
+#[derive(Debug, Clone)]
+pub enum CapacityProgress {
+    Writing { done_blocks: u64, total_blocks: u64 },
+    Reading { done_blocks: u64, total_blocks: u64 },
+    Completed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapacityReport {
+    /// What the card claims to hold
+    pub reported_bytes: u64,
+    /// Where the first test block that didn't read back intact starts; everything
+    /// below it did. Equal to reported_bytes on a good card
+    pub usable_bytes: u64,
+    pub tested_blocks: u64,
+    pub bad_blocks: u64,
+}
+
+impl CapacityReport {
+    pub fn passed(&self) -> bool {
+        self.bad_blocks == 0
+    }
+}
+
+/// Write test blocks across the device at `device_path` and read them back
+#[cfg(target_os = "linux")]
+pub async fn check_card_capacity(
+    device_path: &str,
+    progress_tx: mpsc::UnboundedSender<CapacityProgress>,
+    cancel_token: CancellationToken,
+) -> Result<CapacityReport, String> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    crate::debug::log_section("Capacity Check");
+    crate::debug::log(&format!("Device path: {}", device_path));
+    crate::format::unmount_linux_device(device_path).await?;
+
+    // Exclusive access fails if anything still has the device or a partition mounted
+    let mut disk = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_EXCL)
+        .open(device_path)
+        .map_err(|e| format!("Failed to open {}: {}", device_path, e))?;
+    let size_bytes = disk
+        .seek(SeekFrom::End(0))
+        .map_err(|e| format!("Failed to get size of {}: {}", device_path, e))?;
+
+    let (tx, token) = (progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        // Test blocks have to come back from the card, not from the page cache
+        let mut drop_cache = |disk: &mut std::fs::File| {
+            disk.sync_all()?;
+            unsafe {
+                libc::posix_fadvise(disk.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+            }
+            Ok(())
+        };
+        probe(&mut disk, size_bytes, &mut drop_cache, &tx, &token)
+    })
+    .await
+    .map_err(|e| format!("Capacity check failed: {}", e))?;
+    finish(result, &progress_tx, &cancel_token)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn check_card_capacity(
+    _device_path: &str,
+    _progress_tx: mpsc::UnboundedSender<CapacityProgress>,
+    _cancel_token: CancellationToken,
+) -> Result<CapacityReport, String> {
+    Err("The capacity check is only available on Linux".to_string())
+}
+
+/// Log the outcome and tell the UI
+fn finish(
+    result: Result<CapacityReport, String>,
+    progress_tx: &mpsc::UnboundedSender<CapacityProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<CapacityReport, String> {
+    match &result {
+        Ok(report) => {
+            crate::debug::log(&format!(
+                "{} of {} test blocks bad; {} of {} bytes usable",
+                report.bad_blocks, report.tested_blocks, report.usable_bytes, report.reported_bytes
+            ));
+            let _ = progress_tx.send(CapacityProgress::Completed);
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            let _ = progress_tx.send(CapacityProgress::Cancelled);
+        }
+        Err(_) => {}
+    }
+    result
+}
+
+/// Write every test block of a `size_bytes` disk, then read them all back
+/// `drop_cache` runs in between, so the reads can't be served from memory
+fn probe<D: Read + Write + Seek>(
+    disk: &mut D,
+    size_bytes: u64,
+    drop_cache: &mut dyn FnMut(&mut D) -> std::io::Result<()>,
+    progress_tx: &mpsc::UnboundedSender<CapacityProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<CapacityReport, String> {
+    let (block_bytes, offsets) = block_offsets(size_bytes)?;
+    let total_blocks = offsets.len() as u64;
+    crate::debug::log(&format!("Testing {} blocks of {} bytes across {} bytes", total_blocks, block_bytes, size_bytes));
+    // A new run never mistakes blocks left by an earlier one for its own
+    let run = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    let cancelled = || Err("Capacity check cancelled".to_string());
+
+    // Writes past the real capacity may fail outright; such blocks are bad
+    let mut bad = vec![false; offsets.len()];
+    let mut block = vec![0u8; block_bytes as usize];
+    for (i, &offset) in offsets.iter().enumerate() {
+        if cancel_token.is_cancelled() {
+            return cancelled();
+        }
+        fill_block(&mut block, run, offset);
+        if let Err(e) = disk.seek(SeekFrom::Start(offset)).and_then(|_| disk.write_all(&block)) {
+            crate::debug::log(&format!("Write failed at {}: {}", offset, e));
+            bad[i] = true;
+        }
+        let _ = progress_tx.send(CapacityProgress::Writing { done_blocks: i as u64 + 1, total_blocks });
+    }
+    if let Err(e) = disk.flush().and_then(|_| drop_cache(disk)) {
+        crate::debug::log(&format!("Flushing test blocks failed: {}", e));
+    }
+
+    // The block whose data each block reads back, None if it's no block's
+    let mut found: Vec<Option<usize>> = vec![None; offsets.len()];
+    let mut expected = vec![0u8; block_bytes as usize];
+    for (i, &offset) in offsets.iter().enumerate() {
+        if cancel_token.is_cancelled() {
+            return cancelled();
+        }
+        let _ = progress_tx.send(CapacityProgress::Reading { done_blocks: i as u64, total_blocks });
+        if let Err(e) = disk.seek(SeekFrom::Start(offset)).and_then(|_| disk.read_exact(&mut block)) {
+            crate::debug::log(&format!("Read failed at {}: {}", offset, e));
+            continue;
+        }
+        let Some(j) = tagged_offset(&block, run).and_then(|other| offsets.binary_search(&other).ok()) else {
+            continue;
+        };
+        fill_block(&mut expected, run, offsets[j]);
+        if block == expected {
+            found[i] = Some(j);
+            if j != i {
+                crate::debug::log(&format!("Block at {} reads back the one written at {}", offset, offsets[j]));
+            }
+        }
+    }
+    let _ = progress_tx.send(CapacityProgress::Reading { done_blocks: total_blocks, total_blocks });
+
+    // Blocks that read back the same data share one place on the card; only the
+    // lowest of them really exists, the others wrapped around onto it
+    let mut claimed = std::collections::HashSet::new();
+    for (i, found) in found.iter().enumerate() {
+        match found {
+            Some(j) if claimed.insert(*j) => {}
+            _ => bad[i] = true,
+        }
+    }
+
+    let first_bad = bad.iter().position(|&b| b);
+    Ok(CapacityReport {
+        reported_bytes: size_bytes,
+        usable_bytes: first_bad.map(|i| offsets[i]).unwrap_or(size_bytes),
+        tested_blocks: total_blocks,
+        bad_blocks: bad.iter().filter(|&&b| b).count() as u64,
+    })
+}
+
+/// Block size and the ascending offsets of the test blocks: every multiple of a power
+/// of two spacing, plus the last block of the disk
+fn block_offsets(size_bytes: u64) -> Result<(u64, Vec<u64>), String> {
+    let spacing = (size_bytes / MIN_BLOCKS).checked_ilog2().map(|bits| 1u64 << bits).unwrap_or(0);
+    if spacing < SECTOR_BYTES as u64 {
+        return Err(format!("The card is too small to check ({} bytes)", size_bytes));
+    }
+    let block_bytes = spacing.min(MAX_BLOCK_BYTES);
+    let mut offsets: Vec<u64> = (0..).map(|i| i * spacing).take_while(|&o| o + block_bytes <= size_bytes).collect();
+    let last = (size_bytes - block_bytes) / SECTOR_BYTES as u64 * SECTOR_BYTES as u64;
+    if offsets.last() != Some(&last) {
+        offsets.push(last);
+    }
+    Ok((block_bytes, offsets))
+}
+
+/// Test data for the block at `offset`: every sector starts with the magic, the run
+/// and the offset, followed by noise seeded from them
+fn fill_block(block: &mut [u8], run: u64, offset: u64) {
+    for (i, sector) in block.chunks_mut(SECTOR_BYTES).enumerate() {
+        sector[..8].copy_from_slice(TAG_MAGIC);
+        sector[8..16].copy_from_slice(&run.to_le_bytes());
+        sector[16..24].copy_from_slice(&offset.to_le_bytes());
+        // xorshift64; the seed must not be zero
+        let mut state = (run ^ offset.rotate_left(17) ^ (i as u64 + 1).wrapping_mul(0x9E3779B97F4A7C15)) | 1;
+        for word in sector[24..].chunks_mut(8) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            word.copy_from_slice(&state.to_le_bytes()[..word.len()]);
+        }
+    }
+}
+
+/// The offset a block read back claims to be from, if it was written by this run
+fn tagged_offset(block: &[u8], run: u64) -> Option<u64> {
+    let tag = block.get(..24)?;
+    if &tag[..8] != TAG_MAGIC || tag[8..16] != run.to_le_bytes() {
+        return None;
+    }
+    Some(u64::from_le_bytes(tag[16..24].try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A counterfeit card: reports `reported` bytes, but addresses wrap around at the
+    /// size of its memory
+    struct FakeCard {
+        memory: Vec<u8>,
+        reported: u64,
+        position: u64,
+    }
+
+    impl Read for FakeCard {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let at = (self.position % self.memory.len() as u64) as usize;
+            let len = buf.len().min(self.memory.len() - at);
+            buf[..len].copy_from_slice(&self.memory[at..at + len]);
+            self.position += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Write for FakeCard {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let at = (self.position % self.memory.len() as u64) as usize;
+            let len = buf.len().min(self.memory.len() - at);
+            self.memory[at..at + len].copy_from_slice(&buf[..len]);
+            self.position += len as u64;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for FakeCard {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset,
+                SeekFrom::End(delta) => self.reported.saturating_add_signed(delta),
+                SeekFrom::Current(delta) => self.position.saturating_add_signed(delta),
+            };
+            Ok(self.position)
+        }
+    }
+
+    fn check(card: &mut FakeCard) -> CapacityReport {
+        let (tx, _rx) = mpsc::unbounded_channel();
+        probe(card, card.reported, &mut |_| Ok(()), &tx, &CancellationToken::new()).unwrap()
+    }
+
+    #[test]
+    fn test_capacity_check() {
+        let mib = 1024 * 1024;
+        let mut good = FakeCard { memory: vec![0; 64 * mib], reported: 64 * mib as u64, position: 0 };
+        let report = check(&mut good);
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(report.usable_bytes, 64 * mib as u64);
+        assert_eq!(report.tested_blocks, 256);
+
+        // Claims 64MB, holds 16MB
+        let mut fake = FakeCard { memory: vec![0; 16 * mib], reported: 64 * mib as u64, position: 0 };
+        let report = check(&mut fake);
+        assert!(!report.passed());
+        assert_eq!(report.usable_bytes, 16 * mib as u64);
+        assert_eq!(report.bad_blocks, 192);
+
+        // An odd size still gets its last sector tested
+        let (block_bytes, offsets) = block_offsets(100 * mib as u64 + 512).unwrap();
+        assert_eq!(block_bytes, 256 * 1024);
+        assert_eq!(*offsets.last().unwrap(), 100 * mib as u64 + 512 - block_bytes);
+        assert!(block_offsets(4096).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// SHA-256 verification of downloaded release assets
+// The expected digest is taken from, in order of preference:
+//   1. the `digest` field GitHub publishes for each asset ("sha256:<hex>")
+//   2. a sibling asset named "<asset>.sha256"
+//   3. a SHA256SUMS file attached to the same release
+
+use crate::github::{fetch_text, Asset, Release};
+use crate::source::OnRetry;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Names of checksum list files we look for in a release
+const SUMS_FILE_NAMES: &[&str] = &["SHA256SUMS", "SHA256SUMS.txt", "sha256sums.txt"];
+
+#[derive(Debug)]
+pub enum VerifyProgress {
+    Started { total_bytes: u64 },
+    Progress { checked: u64, total: u64 },
+    Completed,
+    Cancelled,
+}
+
+/// A digest to check a download against, and where it came from (for the log)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectedDigest {
+    pub sha256: String,
+    pub source: String,
+}
+
+/// Find the published SHA-256 for `asset`, or None if the release doesn't provide one
+/// Checksum files are fetched with retries, reported to `on_retry`
+pub async fn find_expected_digest(
+    release: &Release,
+    asset: &Asset,
+    cancel_token: &CancellationToken,
+    on_retry: OnRetry<'_>,
+) -> Result<Option<ExpectedDigest>, String> {
+    if let Some(sha256) = asset.digest.as_deref().and_then(parse_digest_field) {
+        return Ok(Some(ExpectedDigest {
+            sha256,
+            source: "GitHub asset digest".to_string(),
+        }));
+    }
+
+    let sidecar_name = format!("{}.sha256", asset.name);
+    if let Some(sidecar) = release.assets.iter().find(|a| a.name == sidecar_name) {
+        let text = fetch_text(&sidecar.browser_download_url, cancel_token, on_retry).await?;
+        let sha256 = parse_sidecar(&text)
+            .ok_or_else(|| format!("{} does not contain a SHA-256 digest", sidecar.name))?;
+        return Ok(Some(ExpectedDigest { sha256, source: sidecar.name.clone() }));
+    }
+
+    if let Some(sums) = release.assets.iter().find(|a| SUMS_FILE_NAMES.contains(&a.name.as_str())) {
+        let text = fetch_text(&sums.browser_download_url, cancel_token, on_retry).await?;
+        let sha256 = parse_sums_file(&text, &asset.name)
+            .ok_or_else(|| format!("{} has no entry for {}", sums.name, asset.name))?;
+        return Ok(Some(ExpectedDigest { sha256, source: sums.name.clone() }));
+    }
+
+    Ok(None)
+}
+
+/// Hash `path` and compare it with `expected` (lowercase hex)
+pub async fn verify_file(
+    path: &Path,
+    expected: &str,
+    progress_tx: mpsc::UnboundedSender<VerifyProgress>,
+    cancel_token: CancellationToken,
+) -> Result<(), String> {
+    let actual = hash_file(path, progress_tx.clone(), cancel_token).await?;
+
+    if actual != expected {
+        return Err(format!(
+            "Checksum mismatch for {}: expected {}, got {}. The download is corrupted or incomplete; the SD card was not modified.",
+            path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            expected,
+            actual
+        ));
+    }
+
+    let _ = progress_tx.send(VerifyProgress::Completed);
+    Ok(())
+}
+
+/// SHA-256 of `path` as lowercase hex
+pub async fn hash_file(
+    path: &Path,
+    progress_tx: mpsc::UnboundedSender<VerifyProgress>,
+    cancel_token: CancellationToken,
+) -> Result<String, String> {
+    let path_buf: PathBuf = path.to_path_buf();
+    tokio::task::spawn_blocking(move || sha256_file(&path_buf, &progress_tx, &cancel_token))
+        .await
+        .map_err(|e| format!("Checksum task failed: {}", e))?
+}
+
+/// SHA-256 of a file as lowercase hex (blocking)
+fn sha256_file(
+    path: &Path,
+    progress_tx: &mpsc::UnboundedSender<VerifyProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<String, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open download for verification: {}", e))?;
+    let total = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let _ = progress_tx.send(VerifyProgress::Started { total_bytes: total });
+
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut checked = 0u64;
+
+    loop {
+        if cancel_token.is_cancelled() {
+            let _ = progress_tx.send(VerifyProgress::Cancelled);
+            return Err("Verification cancelled".to_string());
+        }
+
+        let read = file.read(&mut buffer).map_err(|e| format!("Failed to read download: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        checked += read as u64;
+        let _ = progress_tx.send(VerifyProgress::Progress { checked, total });
+    }
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+/// Lowercase hex, the form digests are compared and stored in
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Accept exactly 64 hex characters, returned lowercase
+fn normalize_sha256(value: &str) -> Option<String> {
+    let value = value.trim();
+    (value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())).then(|| value.to_ascii_lowercase())
+}
+
+/// GitHub's asset digest field: "sha256:<hex>"
+fn parse_digest_field(digest: &str) -> Option<String> {
+    let (algorithm, value) = digest.split_once(':')?;
+    if !algorithm.eq_ignore_ascii_case("sha256") {
+        return None;
+    }
+    normalize_sha256(value)
+}
+
+/// A "<asset>.sha256" file: the digest, optionally followed by the file name
+fn parse_sidecar(text: &str) -> Option<String> {
+    text.split_whitespace().next().and_then(normalize_sha256)
+}
+
+/// A sha256sum-style list: "<hex>  <name>" or "<hex> *<name>" per line
+fn parse_sums_file(text: &str, asset_name: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let (digest, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start();
+        let name = name.strip_prefix('*').unwrap_or(name);
+        let name = name.strip_prefix("./").unwrap_or(name);
+        if name == asset_name {
+            normalize_sha256(digest)
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_parse_digest_sources() {
+        assert_eq!(parse_digest_field(&format!("sha256:{}", ABC_SHA256.to_uppercase())).as_deref(), Some(ABC_SHA256));
+        assert_eq!(parse_digest_field("sha1:abc"), None);
+
+        assert_eq!(parse_sidecar(&format!("{}  NextUI.zip\n", ABC_SHA256)).as_deref(), Some(ABC_SHA256));
+        assert_eq!(parse_sidecar("not a digest"), None);
+
+        let sums = format!("{}  other.zip\n{} *NextUI.zip\n", "0".repeat(64), ABC_SHA256);
+        assert_eq!(parse_sums_file(&sums, "NextUI.zip").as_deref(), Some(ABC_SHA256));
+        assert_eq!(parse_sums_file(&sums, "missing.zip"), None);
+    }
+
+    #[tokio::test]
+    async fn test_verify_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("NextUI.zip");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert!(verify_file(&path, ABC_SHA256, tx.clone(), CancellationToken::new()).await.is_ok());
+
+        let err = verify_file(&path, &"0".repeat(64), tx, CancellationToken::new()).await.unwrap_err();
+        assert!(err.contains("Checksum mismatch"));
+    }
+}
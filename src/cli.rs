@@ -0,0 +1,917 @@
+// Headless command-line mode
+// Runs the same install pipeline as the GUI and prints progress to the terminal,
+// for build servers and SSH sessions where there is no display
+
+use crate::cache::{default_cache_dir, DownloadCache};
+use crate::capacity::{CAPACITY_CHECK_SUPPORTED, CAPACITY_CHECK_UNAVAILABLE};
+use crate::config::{APP_NAME, DEFAULT_REPO_INDEX, REPO_OPTIONS};
+use crate::drives::{get_removable_drives, DriveInfo};
+use crate::eject::eject_drive;
+use crate::github::find_release_asset;
+use crate::image::ImageTarget;
+use crate::source::list_releases;
+use crate::pipeline::{InstallMode, InstallOptions, InstallPipeline, InstallStage, PipelineError, PipelineEvent};
+use std::io::{IsTerminal, Write};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+// Process exit codes
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NO_PERMISSION: i32 = 77;
+pub const EXIT_CANCELLED: i32 = 130;
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Help,
+    Version,
+    ListDevices,
+    Releases { channel: Option<String> },
+    ClearCache,
+    Components { archive: String },
+    Verify { device: String },
+    Benchmark { device: String },
+    Install(InstallArgs),
+    Image(ImageArgs),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct InstallArgs {
+    device: String,
+    channel: Option<String>,
+    tag: Option<String>,
+    archive: Option<String>,
+    yes: bool,
+    no_cache: bool,
+    extract_to_card: bool,
+    no_verify: bool,
+    check_capacity: bool,
+    skip: Vec<String>,
+    eject: bool,
+    /// Fresh install, or update or repair of the mounted card
+    mode: InstallMode,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct ImageArgs {
+    output: String,
+    size_bytes: u64,
+    channel: Option<String>,
+    tag: Option<String>,
+    archive: Option<String>,
+    no_cache: bool,
+    skip: Vec<String>,
+    compress: bool,
+    /// Overwrite an existing image
+    yes: bool,
+}
+
+/// Command-line arguments, minus the program name and anything macOS adds on its own
+pub fn args() -> Vec<String> {
+    std::env::args()
+        .skip(1)
+        // Finder used to pass a process serial number when launching an .app bundle
+        .filter(|a| !a.starts_with("-psn_"))
+        .collect()
+}
+
+/// Run the CLI and return the process exit code
+pub fn run(args: Vec<String>) -> i32 {
+    #[cfg(windows)]
+    attach_parent_console();
+
+    let verbose = args.iter().any(|a| a == "--verbose" || a == "-v");
+    crate::debug::set_console_echo(verbose);
+
+    let args: Vec<String> = args
+        .into_iter()
+        .filter(|a| a != "--verbose" && a != "-v")
+        .collect();
+
+    let command = match parse_args(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!();
+            eprintln!("{}", usage());
+            return EXIT_USAGE;
+        }
+    };
+
+    match command {
+        Command::Help => {
+            println!("{}", usage());
+            EXIT_SUCCESS
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            EXIT_SUCCESS
+        }
+        Command::ListDevices => list_devices(),
+        Command::Releases { channel } => releases(channel.as_deref()),
+        Command::ClearCache => clear_cache(),
+        Command::Components { archive } => components(&archive),
+        Command::Verify { device } => check_card(&device, InstallMode::Verify),
+        Command::Benchmark { device } => check_card(&device, InstallMode::Benchmark),
+        Command::Install(install_args) => install(install_args),
+        Command::Image(image_args) => image(image_args),
+    }
+}
+
+fn usage() -> String {
+    let channels: Vec<&str> = REPO_OPTIONS.iter().map(|(name, _)| *name).collect();
+    format!(
+        "{app} SD card installer (command-line mode)
+
+Usage:
+  {bin} install --device <DEVICE> [--channel <NAME>] [--tag <TAG>] [--yes] [--no-eject] [--no-cache] [--extract-to-card] [--no-verify] [--check-capacity] [--skip <PATH>]...
+  {bin} install --device <DEVICE> --archive <FILE> [--yes] [--no-eject] [--extract-to-card] [--no-verify] [--check-capacity] [--skip <PATH>]...
+  {bin} update --device <DEVICE> [--channel <NAME>] [--tag <TAG> | --archive <FILE>] [--yes] [--no-eject] [--no-cache] [--no-verify] [--skip <PATH>]...
+  {bin} repair --device <DEVICE> [--channel <NAME>] [--tag <TAG> | --archive <FILE>] [--yes] [--no-eject] [--no-cache] [--no-verify]
+  {bin} verify --device <DEVICE>
+  {bin} benchmark --device <DEVICE>
+  {bin} image --output <FILE> --size <SIZE> [--channel <NAME>] [--tag <TAG> | --archive <FILE>] [--no-cache] [--compress] [--yes] [--skip <PATH>]...
+  {bin} components <FILE>
+  {bin} list-devices
+  {bin} releases [--channel <NAME>]
+  {bin} clear-cache
+  {bin} --help | --version
+
+Options:
+  -d, --device <DEVICE>   Removable drive to install to (e.g. /dev/sdb, disk4, E:)
+  -c, --channel <NAME>    Release channel: {channels} (default: {default})
+  -t, --tag <TAG>         Install this release instead of the latest (see releases)
+  -a, --archive <FILE>    Install a local .zip/.7z release archive (no download)
+  -y, --yes               Do not ask for confirmation before writing to the drive
+      --no-eject          Leave the card mounted when the install finishes
+      --no-cache          Don't use or fill the download cache
+      --extract-to-card   Extract straight onto the card instead of via a local temp folder
+      --no-verify         Don't read the installed files back from the card afterwards
+      --check-capacity    Test that the card really holds what it reports before formatting it (Linux)
+  -s, --skip <PATH>       Leave out a part of the release (see components; repeatable)
+  -o, --output <FILE>     Disk image to create instead of writing to a drive
+      --size <SIZE>       Image size, e.g. 4GB or 512MiB (GB and MB are decimal, as on card labels)
+      --compress          Gzip the image into <FILE>.gz
+  -v, --verbose           Echo the debug log to the terminal
+
+Environment:
+  GITHUB_TOKEN, GH_TOKEN  GitHub token for API requests (raises the rate limit)
+
+Run without arguments to open the graphical installer.",
+        app = APP_NAME,
+        bin = env!("CARGO_PKG_NAME"),
+        channels = channels.join(", "),
+        default = REPO_OPTIONS[DEFAULT_REPO_INDEX].0,
+    )
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let Some(first) = args.first() else {
+        return Ok(Command::Help);
+    };
+
+    match first.as_str() {
+        "-h" | "--help" | "help" => Ok(Command::Help),
+        "-V" | "--version" | "version" => Ok(Command::Version),
+        "list-devices" => {
+            if let Some(extra) = args.get(1) {
+                return Err(format!("Unexpected argument '{}'", extra));
+            }
+            Ok(Command::ListDevices)
+        }
+        "clear-cache" => {
+            if let Some(extra) = args.get(1) {
+                return Err(format!("Unexpected argument '{}'", extra));
+            }
+            Ok(Command::ClearCache)
+        }
+        "verify" | "benchmark" => {
+            let mut device = None;
+            let mut iter = args[1..].iter();
+            while let Some(arg) = iter.next() {
+                match arg.split_once('=') {
+                    Some(("--device", value)) => device = Some(value.to_string()),
+                    _ if arg == "-d" || arg == "--device" => {
+                        device = Some(iter.next().cloned().ok_or("Missing value for --device")?)
+                    }
+                    _ => return Err(format!("Unknown option '{}'", arg)),
+                }
+            }
+            let device = device.ok_or_else(|| format!("{} requires --device (see list-devices)", first))?;
+            match first.as_str() {
+                "verify" => Ok(Command::Verify { device }),
+                _ => Ok(Command::Benchmark { device }),
+            }
+        }
+        "components" => match &args[1..] {
+            [archive] if !archive.starts_with('-') => Ok(Command::Components { archive: archive.clone() }),
+            [] => Err("components requires an archive file".to_string()),
+            [_, extra, ..] => Err(format!("Unexpected argument '{}'", extra)),
+            [other] => Err(format!("Unknown option '{}'", other)),
+        },
+        "releases" => {
+            let mut channel = None;
+            let mut iter = args[1..].iter();
+            while let Some(arg) = iter.next() {
+                match arg.split_once('=') {
+                    Some(("--channel", value)) => channel = Some(value.to_string()),
+                    _ if arg == "-c" || arg == "--channel" => {
+                        channel = Some(iter.next().cloned().ok_or("Missing value for --channel")?)
+                    }
+                    _ => return Err(format!("Unknown option '{}'", arg)),
+                }
+            }
+            Ok(Command::Releases { channel })
+        }
+        "image" => {
+            let mut image_args = ImageArgs::default();
+            let mut size = None;
+            let mut iter = args[1..].iter();
+
+            while let Some(arg) = iter.next() {
+                let (flag, inline_value) = match arg.split_once('=') {
+                    Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                    _ => (arg.as_str(), None),
+                };
+                let mut value = |name: &str| -> Result<String, String> {
+                    inline_value
+                        .clone()
+                        .or_else(|| iter.next().cloned())
+                        .ok_or_else(|| format!("Missing value for {}", name))
+                };
+
+                match flag {
+                    "-o" | "--output" => image_args.output = value("--output")?,
+                    "--size" => size = Some(parse_size(&value("--size")?)?),
+                    "-c" | "--channel" => image_args.channel = Some(value("--channel")?),
+                    "-t" | "--tag" => image_args.tag = Some(value("--tag")?),
+                    "-a" | "--archive" => image_args.archive = Some(value("--archive")?),
+                    "--no-cache" => image_args.no_cache = true,
+                    "--compress" => image_args.compress = true,
+                    "-y" | "--yes" => image_args.yes = true,
+                    "-s" | "--skip" => image_args.skip.push(value("--skip")?),
+                    _ => return Err(format!("Unknown option '{}'", arg)),
+                }
+            }
+
+            if image_args.output.is_empty() {
+                return Err("image requires --output".to_string());
+            }
+            image_args.size_bytes = size.ok_or("image requires --size")?;
+            if image_args.archive.is_some() && image_args.tag.is_some() {
+                return Err("--archive and --tag can't be used together".to_string());
+            }
+            Ok(Command::Image(image_args))
+        }
+        command @ ("install" | "update" | "repair") => {
+            let mode = match command {
+                "update" => InstallMode::Update,
+                "repair" => InstallMode::Repair,
+                _ => InstallMode::Fresh,
+            };
+            let mut install_args = InstallArgs { eject: true, mode, ..Default::default() };
+            let mut iter = args[1..].iter();
+
+            while let Some(arg) = iter.next() {
+                // Accept both "--flag value" and "--flag=value"
+                let (flag, inline_value) = match arg.split_once('=') {
+                    Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                    _ => (arg.as_str(), None),
+                };
+                let mut value = |name: &str| -> Result<String, String> {
+                    inline_value
+                        .clone()
+                        .or_else(|| iter.next().cloned())
+                        .ok_or_else(|| format!("Missing value for {}", name))
+                };
+
+                match flag {
+                    "-d" | "--device" => install_args.device = value("--device")?,
+                    "-c" | "--channel" => install_args.channel = Some(value("--channel")?),
+                    "-t" | "--tag" => install_args.tag = Some(value("--tag")?),
+                    "-a" | "--archive" => install_args.archive = Some(value("--archive")?),
+                    "-y" | "--yes" => install_args.yes = true,
+                    "--no-eject" => install_args.eject = false,
+                    "--no-cache" => install_args.no_cache = true,
+                    "--extract-to-card" => install_args.extract_to_card = true,
+                    "--no-verify" => install_args.no_verify = true,
+                    "--check-capacity" => install_args.check_capacity = true,
+                    "-s" | "--skip" => install_args.skip.push(value("--skip")?),
+                    _ => return Err(format!("Unknown option '{}'", arg)),
+                }
+            }
+
+            if install_args.device.is_empty() {
+                return Err(format!("{} requires --device (see list-devices)", command));
+            }
+            if install_args.mode.in_place() && install_args.extract_to_card {
+                return Err(format!("--extract-to-card can't be used with {}", command));
+            }
+            // The check overwrites the card
+            if install_args.mode.in_place() && install_args.check_capacity {
+                return Err(format!("--check-capacity can't be used with {}", command));
+            }
+            // A repair restores exactly what was installed (see the manifest)
+            if install_args.mode == InstallMode::Repair && !install_args.skip.is_empty() {
+                return Err("--skip can't be used with repair".to_string());
+            }
+            if install_args.archive.is_some() && install_args.tag.is_some() {
+                return Err("--archive and --tag can't be used together".to_string());
+            }
+            Ok(Command::Install(install_args))
+        }
+        other => Err(format!("Unknown command '{}'", other)),
+    }
+}
+
+/// Parse a size like "4GB", "3.5G", "512MiB" or a plain byte count
+/// GB and MB are decimal like the sizes printed on cards, GiB and MiB are binary
+fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size '{}' (e.g. 4GB or 512MiB)", value);
+    let split = value.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "KIB" => 1 << 10,
+        "M" | "MB" => 1_000_000,
+        "MIB" => 1 << 20,
+        "G" | "GB" => 1_000_000_000,
+        "GIB" => 1 << 30,
+        "T" | "TB" => 1_000_000_000_000,
+        "TIB" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    Ok((number * multiplier as f64) as u64)
+}
+
+fn list_devices() -> i32 {
+    let drives = get_removable_drives();
+    if drives.is_empty() {
+        println!("No removable drives found.");
+        return EXIT_SUCCESS;
+    }
+
+    for drive in &drives {
+        println!("{:<16} {}", drive.device_path, drive.display_name());
+    }
+    EXIT_SUCCESS
+}
+
+fn releases(channel: Option<&str>) -> i32 {
+    let (repo_name, repo_url) = match find_channel(channel) {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: failed to create Tokio runtime: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    let releases = match runtime.block_on(list_releases(repo_url)) {
+        Ok(releases) => releases,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    if releases.is_empty() {
+        println!("No installable releases found for {} ({}).", repo_name, repo_url);
+        return EXIT_SUCCESS;
+    }
+
+    println!("{} releases ({}), newest first:", repo_name, repo_url);
+    for release in &releases {
+        let size_mb = find_release_asset(release)
+            .map(|asset| asset.size as f64 / 1_048_576.0)
+            .unwrap_or(0.0);
+        let marker = if release.prerelease { "pre-release" } else { "" };
+        println!(
+            "  {:<24} {:<12} {:>8.1} MB  {}",
+            release.tag_name,
+            release.published_date(),
+            size_mb,
+            marker
+        );
+    }
+    EXIT_SUCCESS
+}
+
+fn clear_cache() -> i32 {
+    match DownloadCache::open_default().clear() {
+        Ok(freed) => {
+            println!(
+                "Cleared download cache ({:.1} MB freed): {}",
+                freed as f64 / 1_048_576.0,
+                default_cache_dir().display()
+            );
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Print the parts of a release archive that --skip accepts
+fn components(archive: &str) -> i32 {
+    let entries = match crate::archive::list_entries(std::path::Path::new(archive)) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+
+    println!("Components of {} (required ones can't be skipped):", archive);
+    for component in crate::archive::components(&entries) {
+        for (depth, part) in std::iter::once((0, &component)).chain(component.children.iter().map(|c| (1, c))) {
+            let name = format!("{}{}", "  ".repeat(depth), part.item.name);
+            println!(
+                "  {:<32} {:>6} files {:>8.1} MB  {}",
+                name,
+                part.item.files,
+                part.item.bytes as f64 / 1_048_576.0,
+                if part.required { "required" } else { "" }
+            );
+        }
+    }
+    EXIT_SUCCESS
+}
+
+/// Match --device against the removable drives we would offer in the GUI
+/// Only those are allowed, so a typo can't wipe a system disk
+fn find_drive(drives: &[DriveInfo], device: &str) -> Option<DriveInfo> {
+    let wanted = device.trim().trim_end_matches(['\\', '/']);
+    let wanted_name = wanted.strip_prefix("/dev/").unwrap_or(wanted);
+
+    drives
+        .iter()
+        .find(|d| {
+            d.device_path.eq_ignore_ascii_case(wanted) || d.name.eq_ignore_ascii_case(wanted_name)
+        })
+        .cloned()
+}
+
+fn find_channel(channel: Option<&str>) -> Result<(&'static str, &'static str), String> {
+    let Some(channel) = channel else {
+        return Ok(REPO_OPTIONS[DEFAULT_REPO_INDEX]);
+    };
+
+    REPO_OPTIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(channel))
+        .copied()
+        .ok_or_else(|| {
+            let names: Vec<&str> = REPO_OPTIONS.iter().map(|(name, _)| *name).collect();
+            format!("Unknown channel '{}'. Available: {}", channel, names.join(", "))
+        })
+}
+
+fn confirm_erase(drive: &DriveInfo) -> bool {
+    println!("WARNING: This will DELETE ALL DATA on {}", drive.display_name());
+    print!("Type 'yes' to continue: ");
+    let _ = std::io::stdout().flush();
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim().eq_ignore_ascii_case("yes")
+}
+
+fn confirm_in_place(mode: InstallMode, drive: &DriveInfo, mount_path: &str) -> bool {
+    let action = if mode == InstallMode::Repair { "restore missing or damaged" } else { "replace the" };
+    println!("This will {} {} system files on {} ({})", action, APP_NAME, drive.display_name(), mount_path);
+    println!("Files under {} are kept.", crate::config::PRESERVE_PATHS.join(", "));
+    print!("Type 'yes' to continue: ");
+    let _ = std::io::stdout().flush();
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim().eq_ignore_ascii_case("yes")
+}
+
+fn install(args: InstallArgs) -> i32 {
+    // Updates and repairs only write through the mounted filesystem, formatting needs
+    // raw disk access
+    #[cfg(not(windows))]
+    if !args.mode.in_place() && unsafe { libc::geteuid() } != 0 {
+        eprintln!("Error: writing to disks requires root. Re-run with sudo:");
+        eprintln!("  sudo {} install --device {}", env!("CARGO_PKG_NAME"), args.device);
+        return EXIT_NO_PERMISSION;
+    }
+
+    let (repo_name, repo_url) = match find_channel(args.channel.as_deref()) {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    if args.check_capacity && !CAPACITY_CHECK_SUPPORTED {
+        eprintln!("Error: {}", CAPACITY_CHECK_UNAVAILABLE);
+        return EXIT_USAGE;
+    }
+
+    // Catch a wrong path before asking to erase anything
+    if let Some(archive) = &args.archive {
+        if let Err(e) = crate::archive::detect_kind(std::path::Path::new(archive)) {
+            eprintln!("Error: {}", e);
+            return EXIT_USAGE;
+        }
+    }
+
+    let Some(drive) = find_drive_or_list(&args.device) else {
+        return EXIT_USAGE;
+    };
+
+    let mount_path = drive.mount_path.as_ref().map(|p| p.display().to_string());
+    if args.mode.in_place() && mount_path.is_none() {
+        eprintln!(
+            "Error: {} is not mounted; mount the card to {} it",
+            drive.device_path,
+            args.mode.label().to_lowercase()
+        );
+        return EXIT_USAGE;
+    }
+
+    if !args.yes {
+        if !std::io::stdin().is_terminal() {
+            let action = if args.mode.in_place() { "write to" } else { "erase" };
+            eprintln!("Error: refusing to {} {} without --yes (stdin is not a terminal)", action, drive.device_path);
+            return EXIT_USAGE;
+        }
+        let confirmed = match &mount_path {
+            Some(mount_path) if args.mode.in_place() => confirm_in_place(args.mode, &drive, mount_path),
+            _ => confirm_erase(&drive),
+        };
+        if !confirmed {
+            println!("Aborted.");
+            return EXIT_CANCELLED;
+        }
+    }
+
+    let mut options = InstallOptions::new(drive.clone(), repo_name, repo_url);
+    options.tag = args.tag.clone();
+    options.use_cache = !args.no_cache;
+    options.local_archive = args.archive.as_ref().map(std::path::PathBuf::from);
+    if args.extract_to_card {
+        options.extract_to_card = true;
+    }
+    options.excluded_components = args.skip.clone();
+    options.mode = args.mode;
+    if args.no_verify {
+        options.verify_after_install = false;
+    }
+    if args.check_capacity {
+        options.check_capacity = true;
+    }
+
+    match run_pipeline(options) {
+        Ok(()) => {
+            if args.eject {
+                println!("Ejecting {}...", drive.device_path);
+                match eject_drive(&drive) {
+                    Ok(()) => println!("SD card ejected. You may now remove it."),
+                    Err(e) => println!("Eject warning: {}. The card should still be safe to remove.", e),
+                }
+            }
+            EXIT_SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+/// Build a disk image of a release instead of writing to a card
+fn image(args: ImageArgs) -> i32 {
+    let (repo_name, repo_url) = match find_channel(args.channel.as_deref()) {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    if let Some(archive) = &args.archive {
+        if let Err(e) = crate::archive::detect_kind(std::path::Path::new(archive)) {
+            eprintln!("Error: {}", e);
+            return EXIT_USAGE;
+        }
+    }
+
+    let target = ImageTarget::new(std::path::PathBuf::from(&args.output), args.size_bytes, args.compress);
+    // Only ever replace plain files, so --output can't point at a disk
+    for path in [&target.path, &target.output_path()] {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            continue;
+        };
+        if !metadata.is_file() {
+            eprintln!("Error: {} exists and is not a regular file", path.display());
+            return EXIT_USAGE;
+        }
+        if !args.yes {
+            eprintln!("Error: {} already exists; pass --yes to replace it", path.display());
+            return EXIT_USAGE;
+        }
+    }
+
+    let mut options = InstallOptions::for_image(target, repo_name, repo_url);
+    options.tag = args.tag.clone();
+    options.use_cache = !args.no_cache;
+    options.local_archive = args.archive.as_ref().map(std::path::PathBuf::from);
+    options.excluded_components = args.skip.clone();
+    match run_pipeline(options) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(code) => code,
+    }
+}
+
+/// Verify or benchmark a mounted card; neither needs a release
+fn check_card(device: &str, mode: InstallMode) -> i32 {
+    let Some(drive) = find_drive_or_list(device) else {
+        return EXIT_USAGE;
+    };
+    let (repo_name, repo_url) = REPO_OPTIONS[DEFAULT_REPO_INDEX];
+    let mut options = InstallOptions::new(drive, repo_name, repo_url);
+    options.mode = mode;
+    match run_pipeline(options) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(code) => code,
+    }
+}
+
+/// Look up --device, listing the drives that can be used if it isn't one of them
+fn find_drive_or_list(device: &str) -> Option<DriveInfo> {
+    let drives = get_removable_drives();
+    let drive = find_drive(&drives, device);
+    if drive.is_none() {
+        eprintln!("Error: '{}' is not a removable drive. Available drives:", device);
+        for drive in &drives {
+            eprintln!("  {:<16} {}", drive.device_path, drive.display_name());
+        }
+    }
+    drive
+}
+
+/// Run the pipeline with terminal progress; on failure, returns the exit code after
+/// printing the error
+fn run_pipeline(options: InstallOptions) -> Result<(), i32> {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: failed to create Tokio runtime: {}", e);
+            return Err(EXIT_FAILURE);
+        }
+    };
+
+    let action = options.label();
+    let mut printer = ProgressPrinter::new();
+    let result = runtime.block_on(async {
+        let cancel_token = CancellationToken::new();
+
+        // Ctrl+C cancels the install the same way the GUI's Cancel button does
+        let ctrl_c_token = cancel_token.clone();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                eprintln!();
+                eprintln!("Cancelling...");
+                ctrl_c_token.cancel();
+            }
+        });
+
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel();
+        let pipeline = tokio::spawn(InstallPipeline::new(options, event_tx, cancel_token).run());
+
+        while let Some(event) = event_rx.recv().await {
+            printer.handle(event);
+        }
+        pipeline
+            .await
+            .unwrap_or_else(|e| Err(PipelineError::Failed {
+                stage: InstallStage::FetchingRelease,
+                message: format!("Install task failed: {}", e),
+            }))
+    });
+    printer.finish_line();
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(PipelineError::Cancelled) => {
+            eprintln!("{} cancelled.", action);
+            Err(EXIT_CANCELLED)
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("{} failed. Debug log: {}", action, crate::debug::get_log_path().display());
+            Err(EXIT_FAILURE)
+        }
+    }
+}
+
+/// Release builds use the Windows GUI subsystem, so attach to the console we were started from
+#[cfg(windows)]
+fn attach_parent_console() {
+    use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    unsafe {
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+/// Prints pipeline events to the terminal
+/// On a TTY the progress line is redrawn in place; otherwise (CI logs) a line is
+/// printed every 10% so logs stay readable
+struct ProgressPrinter {
+    interactive: bool,
+    line_open: bool,
+    last_percent: Option<u64>,
+}
+
+impl ProgressPrinter {
+    fn new() -> Self {
+        Self {
+            interactive: std::io::stdout().is_terminal(),
+            line_open: false,
+            last_percent: None,
+        }
+    }
+
+    fn handle(&mut self, event: PipelineEvent) {
+        match event {
+            PipelineEvent::StageStarted(stage) => {
+                self.finish_line();
+                self.last_percent = None;
+                println!("==> {}", stage.label());
+            }
+            PipelineEvent::Log(message) => {
+                self.finish_line();
+                println!("{}", message);
+            }
+            PipelineEvent::Status(message) => {
+                if self.interactive {
+                    print!("\r\x1b[K  {}", message);
+                    let _ = std::io::stdout().flush();
+                    self.line_open = true;
+                }
+            }
+            PipelineEvent::Progress { current, total, message } => self.progress(current, total, &message),
+            // Already printed as log lines
+            PipelineEvent::Benchmark(_) => {}
+            // Only sent in the window's download mode (see the components command)
+            PipelineEvent::Components(_) => {}
+            // The caller reports the result once the pipeline has returned
+            PipelineEvent::Finished(_) => self.finish_line(),
+        }
+    }
+
+    fn progress(&mut self, current: u64, total: u64, message: &str) {
+        let percent = (current.min(total) * 100).checked_div(total).unwrap_or(0);
+
+        if self.interactive {
+            const BAR_WIDTH: usize = 24;
+            let filled = (percent as usize * BAR_WIDTH) / 100;
+            print!(
+                "\r\x1b[K  [{}{}] {:>3}% {}",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                percent,
+                message
+            );
+            let _ = std::io::stdout().flush();
+            self.line_open = true;
+        } else {
+            let step = percent / 10;
+            if self.last_percent.map(|last| last / 10) != Some(step) {
+                println!("  {:>3}% {}", percent, message);
+            }
+        }
+        self.last_percent = Some(percent);
+    }
+
+    /// End an in-place progress line so the next output starts on a fresh line
+    fn finish_line(&mut self) {
+        if self.line_open {
+            println!();
+            self.line_open = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_install() {
+        assert_eq!(
+            parse_args(&args(&[
+                "install", "--device", "/dev/sdb", "--channel=Nightly", "--tag", "v6.2.0", "-y", "--no-cache",
+                "--extract-to-card", "--check-capacity", "--skip", "Tools/", "-s", "Bios/",
+            ]))
+            .unwrap(),
+            Command::Install(InstallArgs {
+                device: "/dev/sdb".to_string(),
+                channel: Some("Nightly".to_string()),
+                tag: Some("v6.2.0".to_string()),
+                archive: None,
+                yes: true,
+                no_cache: true,
+                extract_to_card: true,
+                no_verify: false,
+                check_capacity: true,
+                skip: vec!["Tools/".to_string(), "Bios/".to_string()],
+                eject: true,
+                mode: InstallMode::Fresh,
+            })
+        );
+        assert_eq!(
+            parse_args(&args(&["update", "-d", "E:", "--no-eject"])).unwrap(),
+            Command::Install(InstallArgs { device: "E:".to_string(), mode: InstallMode::Update, ..Default::default() })
+        );
+        assert_eq!(
+            parse_args(&args(&["repair", "-d", "E:", "--archive", "a.zip"])).unwrap(),
+            Command::Install(InstallArgs {
+                device: "E:".to_string(),
+                archive: Some("a.zip".to_string()),
+                mode: InstallMode::Repair,
+                eject: true,
+                ..Default::default()
+            })
+        );
+        assert!(parse_args(&args(&["repair", "-d", "E:", "--skip", "Tools/"])).is_err());
+        assert!(parse_args(&args(&["update", "-d", "E:", "--extract-to-card"])).is_err());
+        assert!(parse_args(&args(&["repair", "-d", "E:", "--check-capacity"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["verify", "--device=E:"])).unwrap(),
+            Command::Verify { device: "E:".to_string() }
+        );
+        assert!(parse_args(&args(&["verify"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["benchmark", "-d", "/dev/sdb"])).unwrap(),
+            Command::Benchmark { device: "/dev/sdb".to_string() }
+        );
+        assert!(parse_args(&args(&["install", "--yes"])).is_err());
+        assert!(parse_args(&args(&["install", "--device"])).is_err());
+        assert!(parse_args(&args(&["instal"])).is_err());
+        assert!(parse_args(&args(&["install", "-d", "sdb", "--archive", "a.zip", "--tag", "v1"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["components", "release.zip"])).unwrap(),
+            Command::Components { archive: "release.zip".to_string() }
+        );
+        assert_eq!(
+            parse_args(&args(&["releases", "-c", "Nightly"])).unwrap(),
+            Command::Releases { channel: Some("Nightly".to_string()) }
+        );
+    }
+
+    #[test]
+    fn test_parse_image() {
+        assert_eq!(
+            parse_args(&args(&["image", "-o", "nextui.img", "--size=4GB", "--archive", "a.zip", "--compress"])).unwrap(),
+            Command::Image(ImageArgs {
+                output: "nextui.img".to_string(),
+                size_bytes: 4_000_000_000,
+                archive: Some("a.zip".to_string()),
+                compress: true,
+                ..Default::default()
+            })
+        );
+        assert!(parse_args(&args(&["image", "--size", "4GB"])).is_err());
+        assert!(parse_args(&args(&["image", "-o", "nextui.img"])).is_err());
+        assert_eq!(parse_size("512MiB"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_size("1.5G"), Ok(1_500_000_000));
+        assert_eq!(parse_size("1000000"), Ok(1_000_000));
+        assert!(parse_size("4 gallons").is_err());
+        assert!(parse_size("GB").is_err());
+    }
+
+    #[test]
+    fn test_find_drive() {
+        let drives = vec![DriveInfo {
+            name: "sdb".to_string(),
+            device_path: "/dev/sdb".to_string(),
+            mount_path: None,
+            label: String::new(),
+            size_bytes: 0,
+        }];
+        assert!(find_drive(&drives, "/dev/sdb").is_some());
+        assert!(find_drive(&drives, "sdb").is_some());
+        assert!(find_drive(&drives, "/dev/sda").is_none());
+    }
+}
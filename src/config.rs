@@ -6,7 +6,7 @@
 // QUICK START - To rebrand this installer, change these values:
 //   1. APP_NAME        - Your OS name (e.g., "NextUI", "Onion", "MinUI")
 //   2. VOLUME_LABEL    - SD card label, max 11 chars uppercase (e.g., "NEXTUI")
-//   3. REPO_OPTIONS    - Your GitHub repositories
+//   3. REPO_OPTIONS    - Where your releases are published
 //
 // ALSO UPDATE THESE EXTERNAL FILES:
 //   - Cargo.toml: name, description, authors fields
@@ -48,7 +48,11 @@ pub const TEMP_PREFIX: &str = env!("CARGO_PKG_NAME");
 // ----------------------------------------------------------------------------
 // REPOSITORY OPTIONS
 // ----------------------------------------------------------------------------
-// Each entry is (Display Name, GitHub repo in "owner/repo" format)
+// Each entry is (Display Name, release source). The source can be:
+//   "owner/repo"                                       GitHub
+//   "github:https://ghe.example.com/api/v3/owner/repo" GitHub Enterprise / custom API base
+//   "gitea:https://git.example.com/owner/repo"         Gitea or Forgejo (also "forgejo:")
+//   "index:https://example.com/releases.json"          Static JSON index (see README)
 
 pub const REPO_OPTIONS: &[(&str, &str)] = &[
     ("Stable", "LoveRetro/NextUI"),
@@ -62,15 +66,71 @@ pub const DEFAULT_REPO_INDEX: usize = 0;
 /// The installer will download the first asset matching this extension
 pub const ASSET_EXTENSION: &str = ".zip";
 
+/// Files or folders (folders end in '/') that mark an archive as a release
+/// At least one must be at the top level of the archive, or the install is refused
+/// before the card is touched. Leave empty to skip the check.
+pub const RELEASE_MARKER_PATHS: &[&str] = &["MinUI.zip", ".system/", ".tmp_update/"];
+
+/// Refuse to install releases that publish no SHA-256 checksum
+/// When false, such releases are installed with a warning in the log
+pub const REQUIRE_CHECKSUM: bool = false;
+
+/// Size limit for the download cache in bytes
+/// Downloaded releases are kept so the next install of the same release skips the
+/// download; the least recently used ones are deleted above this size
+pub const CACHE_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+/// How many recent releases to offer in the version pickers (GitHub allows up to 100)
+pub const RELEASE_LIST_LIMIT: usize = 30;
+
+/// GitHub token used for API requests when none is set in the UI or in the
+/// GITHUB_TOKEN / GH_TOKEN environment variables. Raises the API limit from 60 to
+/// 5000 requests an hour. Anyone with the binary can read it, so only ever use a
+/// read-only token with no scopes here. Leave empty for anonymous access.
+pub const GITHUB_TOKEN: &str = "";
+
+/// How many times a release lookup or download is tried before giving up
+/// Only network trouble (timeouts, dropped connections, 5xx) is retried, with
+/// growing waits in between; interrupted downloads resume where they stopped
+pub const NETWORK_RETRY_ATTEMPTS: u32 = 5;
+
+/// Extract the release straight onto the card instead of into a local temp folder
+/// that is then copied over. Halves the disk I/O and needs no free space for the
+/// extracted files, but a damaged archive is only noticed once the card is half written
+pub const EXTRACT_TO_CARD: bool = false;
+
+/// Read every installed file back from the card once the install is done and compare
+/// it with the release. Catches cards that silently lose data, at the cost of reading
+/// the whole release once more
+pub const VERIFY_AFTER_INSTALL: bool = true;
+
+/// Check that the card really holds what it reports before formatting it (see
+/// capacity.rs). Catches counterfeit cards, at the cost of writing and reading a few
+/// hundred MB; can also be turned on per install
+pub const CHECK_CAPACITY: bool = false;
+
+/// Files and folders (folders end in '/') that belong to the user
+/// Updating a card never overwrites existing files here; anything the release adds
+/// under them (e.g. empty ROM folders) is only created if it is missing
+pub const PRESERVE_PATHS: &[&str] = &[
+    "Roms/",
+    "Saves/",
+    "Bios/",
+    "Cheats/",
+    "Collections/",
+    "Screenshots/",
+    ".userdata/",
+];
+
 // ----------------------------------------------------------------------------
 // WINDOW SETTINGS
 // ----------------------------------------------------------------------------
 
 /// Default window size (width, height)
-pub const WINDOW_SIZE: (f32, f32) = (475.0, 275.0);
+pub const WINDOW_SIZE: (f32, f32) = (475.0, 305.0);
 
 /// Minimum window size (width, height)
-pub const WINDOW_MIN_SIZE: (f32, f32) = (475.0, 275.0);
+pub const WINDOW_MIN_SIZE: (f32, f32) = (475.0, 305.0);
 
 // ----------------------------------------------------------------------------
 // ICON CONFIGURATION
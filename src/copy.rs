@@ -1,3 +1,4 @@
+use crate::fat32::Volume;
 use std::path::{Path, PathBuf};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
@@ -138,3 +139,72 @@ pub async fn copy_directory_with_progress(
 
     Ok(())
 }
+
+/// Copy all files from source into a FAT32 volume that isn't mounted, with the same
+/// progress reporting, and hand the volume back
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub async fn copy_directory_to_volume(
+    source_dir: &Path,
+    mut volume: Volume<std::fs::File>,
+    progress_tx: mpsc::UnboundedSender<CopyProgress>,
+    cancel_token: CancellationToken,
+) -> Result<Volume<std::fs::File>, String> {
+    crate::debug::log_section("Copy Files");
+    crate::debug::log(&format!("Source: {:?}", source_dir));
+    crate::debug::log("Destination: FAT32 volume");
+
+    let _ = progress_tx.send(CopyProgress::Counting);
+    let files = collect_files(source_dir)
+        .map_err(|e| format!("Failed to scan source directory: {}", e))?;
+    let total_files = files.len() as u64;
+    let total_bytes = calculate_total_size(&files);
+    crate::debug::log(&format!("Found {} files, {} bytes total", total_files, total_bytes));
+    let _ = progress_tx.send(CopyProgress::Started { total_bytes, total_files });
+
+    let source_dir = source_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut copied_bytes: u64 = 0;
+        for file_path in &files {
+            if cancel_token.is_cancelled() {
+                crate::debug::log("Copy cancelled by user");
+                let _ = progress_tx.send(CopyProgress::Cancelled);
+                return Err("Copy cancelled".to_string());
+            }
+
+            let relative_path = file_path.strip_prefix(&source_dir)
+                .map_err(|e| format!("Failed to get relative path: {}", e))?;
+            // The volume takes '/'-separated paths on every platform
+            let path = relative_path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let _ = progress_tx.send(CopyProgress::Progress {
+                copied_bytes,
+                total_bytes,
+                current_file: path.clone(),
+            });
+
+            if let Some((parent, _)) = path.rsplit_once('/') {
+                volume.create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create directory {}: {}", parent, e))?;
+            }
+            let copy_error = |e: std::io::Error| format!("Failed to copy {:?}: {}", file_path, e);
+            let mut source = std::fs::File::open(file_path).map_err(copy_error)?;
+            let mut dest = volume.create_file(&path).map_err(copy_error)?;
+            copied_bytes += std::io::copy(&mut source, &mut dest).map_err(copy_error)?;
+            dest.close().map_err(copy_error)?;
+        }
+
+        let _ = progress_tx.send(CopyProgress::Progress {
+            copied_bytes: total_bytes,
+            total_bytes,
+            current_file: String::new(),
+        });
+        crate::debug::log("Copy completed successfully");
+        let _ = progress_tx.send(CopyProgress::Completed);
+        Ok(volume)
+    })
+    .await
+    .map_err(|e| format!("Copy task failed: {}", e))?
+}
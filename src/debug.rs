@@ -4,12 +4,16 @@
 use crate::config::{APP_NAME, TEMP_PREFIX};
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
 lazy_static::lazy_static! {
     static ref DEBUG_LOG: Mutex<DebugLog> = Mutex::new(DebugLog::new());
 }
 
+/// Whether log lines are also echoed to stdout (the CLI turns this off unless --verbose)
+static CONSOLE_ECHO: AtomicBool = AtomicBool::new(true);
+
 pub struct DebugLog {
     path: PathBuf,
     enabled: bool,
@@ -36,7 +40,9 @@ impl DebugLog {
             })
             .unwrap_or_else(|_| std::env::temp_dir().join(&log_filename));
 
-        println!("[DEBUG] Initializing log at: {:?}", path);
+        if CONSOLE_ECHO.load(Ordering::Relaxed) {
+            println!("[DEBUG] Initializing log at: {:?}", path);
+        }
 
         // Try to create the file to verify write permissions
         // If it fails (e.g. running as different user in restricted dir), fall back to temp
@@ -44,7 +50,9 @@ impl DebugLog {
             path
         } else {
             let temp_path = std::env::temp_dir().join(&log_filename);
-            println!("[DEBUG] Failed to write to preferred path, falling back to: {:?}", temp_path);
+            if CONSOLE_ECHO.load(Ordering::Relaxed) {
+                println!("[DEBUG] Failed to write to preferred path, falling back to: {:?}", temp_path);
+            }
             temp_path
         };
 
@@ -55,7 +63,7 @@ impl DebugLog {
             let _ = writeln!(f, "Timestamp: {:?}", std::time::SystemTime::now());
             let _ = writeln!(f, "Platform: {}", std::env::consts::OS);
             let _ = writeln!(f, "Arch: {}", std::env::consts::ARCH);
-            let _ = writeln!(f, "");
+            let _ = writeln!(f);
         }
 
         Self {
@@ -65,10 +73,17 @@ impl DebugLog {
     }
 }
 
+/// Enable or disable echoing log lines to stdout
+pub fn set_console_echo(enabled: bool) {
+    CONSOLE_ECHO.store(enabled, Ordering::Relaxed);
+}
+
 /// Log a debug message
 pub fn log(message: &str) {
     // Also print to stdout for VS Code debug console visibility
-    println!("[DEBUG] {}", message);
+    if CONSOLE_ECHO.load(Ordering::Relaxed) {
+        println!("[DEBUG] {}", message);
+    }
 
     if let Ok(debug_log) = DEBUG_LOG.lock() {
         if debug_log.enabled {
@@ -102,10 +117,13 @@ pub fn get_log_path() -> PathBuf {
     }
 }
 
+/// Name of the debug log on the SD card
+pub const CARD_LOG_NAME: &str = "installer_debug.txt";
+
 /// Copy the debug log to a destination directory (e.g., SD card)
 pub fn copy_log_to(dest_dir: &std::path::Path) -> Result<PathBuf, String> {
     let log_path = get_log_path();
-    let dest_path = dest_dir.join("installer_debug.txt");
+    let dest_path = dest_dir.join(CARD_LOG_NAME);
 
     // Add final log entry before copying
     log("Copying debug log to SD card...");
@@ -116,3 +134,9 @@ pub fn copy_log_to(dest_dir: &std::path::Path) -> Result<PathBuf, String> {
 
     Ok(dest_path)
 }
+
+/// Read the whole debug log, to write it to a card that isn't mounted
+pub fn read_log() -> Result<Vec<u8>, String> {
+    log("Writing debug log to SD card...");
+    std::fs::read(get_log_path()).map_err(|e| format!("Failed to read debug log: {}", e))
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DriveInfo {
     /// Display name (e.g., "E:" on Windows, "sdb" on Linux, "disk2" on macOS)
     pub name: String,
@@ -1,388 +1,729 @@
-use crate::config::TEMP_PREFIX;
-use std::path::Path;
-use std::process::Stdio;
-use tokio::io::AsyncReadExt;
-use tokio::process::Command;
+// Release archive extraction
+// .zip and .7z archives are decompressed in-process (zip crate / sevenz-rust) with
+// byte-level and per-file progress. The format is taken from the archive's magic
+// bytes, not its file name. When updating an existing install, files that are already
+// identical (size + CRC32) or that belong to the user are left alone, and files that
+// change are written next to the old one and renamed over it, so an interrupted update
+// never leaves a half-written file. Files go to a folder, or straight into a FAT32
+// volume that isn't mounted (see fat32::Volume).
+
+use crate::archive::{detect_kind, is_7z_symlink, is_excluded, safe_relative_path, ArchiveKind};
+use crate::checksum::to_hex;
+use crate::fat32::Volume;
+use crate::manifest::InstalledFile;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
-#[cfg(target_os = "windows")]
-#[allow(unused_imports)]
-use std::os::windows::process::CommandExt;
+/// Send a progress update at most once per this many extracted bytes
+const PROGRESS_INTERVAL_BYTES: u64 = 1024 * 1024;
 
-// Embed platform-specific 7z binaries
-#[cfg(target_os = "windows")]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Windows/7zr.exe");
+#[derive(Debug, Clone)]
+pub enum ExtractProgress {
+    /// Totals from the archive index
+    Started { total_bytes: u64, total_files: u64 },
+    /// `current_file` is the archive path of the entry being written
+    Progress {
+        extracted_bytes: u64,
+        total_bytes: u64,
+        files_done: u64,
+        total_files: u64,
+        current_file: String,
+    },
+    Completed,
+    Cancelled,
+    Error(String),
+}
 
-#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Linux-x86_64/7zzs");
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtractError {
+    Cancelled,
+    /// The archive can't be opened or its index is damaged
+    Archive(String),
+    /// One entry couldn't be decompressed (corrupt data, unsupported method)
+    Entry { name: String, message: String },
+    /// Writing the extracted files failed (disk full, permissions)
+    Write { path: PathBuf, message: String },
+}
 
-#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Linux-aarch64/7zzs");
+/// What to extract and how to treat files that already exist
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Components to leave out (archive paths, see archive::is_excluded)
+    pub excluded: Vec<String>,
+    /// Updating an existing install: identical files are not rewritten and existing
+    /// files under `preserve` are never overwritten
+    pub update: bool,
+    /// User data paths (folders end in '/'), only used when updating
+    pub preserve: Vec<String>,
+    /// When not empty, only these archive paths are extracted (repairing a card)
+    pub only: Vec<String>,
+    /// Files the previous release put on the card (from its manifest), only used when
+    /// updating: those this archive no longer has are reported as stale
+    pub installed: Vec<String>,
+}
 
-#[cfg(all(target_os = "linux", target_arch = "x86"))]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Linux-i686/7zzs");
+impl ExtractOptions {
+    /// Whether an entry is left in the archive
+    fn skips(&self, name: &str) -> bool {
+        is_excluded(name, &self.excluded) || (!self.only.is_empty() && !is_excluded(name, &self.only))
+    }
+}
 
-#[cfg(all(target_os = "linux", target_arch = "arm"))]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Linux-armv7/7zzs");
+/// Archive paths of the files extracted, by what happened to them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractReport {
+    /// Written where there was no file before
+    pub added: Vec<String>,
+    /// Written over a file with different contents
+    pub replaced: Vec<String>,
+    /// Already identical on the destination (update only)
+    pub unchanged: Vec<String>,
+    /// Existing user files left alone (update only)
+    pub preserved: Vec<String>,
+    /// Installed by the previous release but not part of this one; left on the card
+    /// (update only)
+    pub stale: Vec<String>,
+    /// Every file from the archive that is now on the destination (added, replaced
+    /// and unchanged), with its SHA-256
+    pub files: Vec<InstalledFile>,
+}
 
-#[cfg(target_os = "macos")]
-const SEVEN_ZIP_EXE: &[u8] = include_bytes!("../assets/Mac/7zz");
+/// Where extracted files are written
+enum Destination {
+    Dir(PathBuf),
+    Volume(Volume<std::fs::File>),
+}
 
-#[cfg(target_os = "windows")]
-const CREATE_NO_WINDOW: u32 = 0x08000000;
+/// What the writer needs to know about an entry besides its data
+struct EntryMeta<'n> {
+    name: &'n str,
+    is_dir: bool,
+    is_symlink: bool,
+    size: u64,
+    crc32: Option<u32>,
+}
 
-#[derive(Debug, Clone)]
-pub enum ExtractProgress {
-    Started,
-    Extracting,
-    Progress { percent: u8 },
-    Completed,
-    Cancelled,
-    Error(String),
+impl std::fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExtractError::Cancelled => write!(f, "Extraction cancelled"),
+            ExtractError::Archive(message) => write!(f, "Archive is damaged or unreadable: {}", message),
+            ExtractError::Entry { name, message } => write!(f, "Failed to extract {}: {}", name, message),
+            ExtractError::Write { path, message } => write!(f, "Failed to write {}: {}", path.display(), message),
+        }
+    }
 }
 
-pub async fn extract_7z(
+/// Extract a .zip or .7z archive into `dest_dir`
+pub async fn extract_archive(
     archive_path: &Path,
     dest_dir: &Path,
+    options: &ExtractOptions,
     progress_tx: mpsc::UnboundedSender<ExtractProgress>,
     cancel_token: CancellationToken,
-) -> Result<(), String> {
-    crate::debug::log_section("7z Extraction");
-    crate::debug::log(&format!("Archive: {:?}", archive_path));
+) -> Result<ExtractReport, ExtractError> {
+    // Check for cancellation before starting
+    if cancel_token.is_cancelled() {
+        let _ = progress_tx.send(ExtractProgress::Cancelled);
+        return Err(ExtractError::Cancelled);
+    }
+
+    std::fs::create_dir_all(dest_dir).map_err(|e| ExtractError::Write {
+        path: dest_dir.to_path_buf(),
+        message: e.to_string(),
+    })?;
+
+    crate::debug::log_section("Extraction");
     crate::debug::log(&format!("Destination: {:?}", dest_dir));
+    let destination = Destination::Dir(dest_dir.to_path_buf());
+    extract_to(archive_path, destination, options, progress_tx, cancel_token).await.map(|(report, _)| report)
+}
 
-    // Check for cancellation before starting
+/// Extract a .zip or .7z archive into the root of a freshly formatted FAT32 volume and
+/// hand the volume back (updating isn't supported there)
+pub async fn extract_archive_to_volume(
+    archive_path: &Path,
+    volume: Volume<std::fs::File>,
+    options: &ExtractOptions,
+    progress_tx: mpsc::UnboundedSender<ExtractProgress>,
+    cancel_token: CancellationToken,
+) -> Result<(ExtractReport, Volume<std::fs::File>), ExtractError> {
     if cancel_token.is_cancelled() {
         let _ = progress_tx.send(ExtractProgress::Cancelled);
-        return Err("Extraction cancelled".to_string());
+        return Err(ExtractError::Cancelled);
     }
 
-    let _ = progress_tx.send(ExtractProgress::Started);
+    crate::debug::log_section("Extraction");
+    crate::debug::log("Destination: FAT32 volume");
+    let (report, destination) = extract_to(archive_path, Destination::Volume(volume), options, progress_tx, cancel_token).await?;
+    match destination {
+        Destination::Volume(volume) => Ok((report, volume)),
+        Destination::Dir(_) => unreachable!(),
+    }
+}
 
-    // Verify archive exists
-    if !archive_path.exists() {
-        crate::debug::log("ERROR: Archive not found");
-        return Err(format!("Archive not found: {:?}", archive_path));
+async fn extract_to(
+    archive_path: &Path,
+    mut destination: Destination,
+    options: &ExtractOptions,
+    progress_tx: mpsc::UnboundedSender<ExtractProgress>,
+    cancel_token: CancellationToken,
+) -> Result<(ExtractReport, Destination), ExtractError> {
+    let kind = detect_kind(archive_path).map_err(ExtractError::Archive)?;
+    crate::debug::log(&format!("Archive: {:?} ({})", archive_path, kind.name()));
+    if !options.excluded.is_empty() {
+        crate::debug::log(&format!("Skipping: {}", options.excluded.join(", ")));
+    }
+    if !options.only.is_empty() {
+        crate::debug::log(&format!("Only extracting {} entries", options.only.len()));
+    }
+    if options.update {
+        crate::debug::log(&format!("Updating in place, preserving: {}", options.preserve.join(", ")));
     }
-    crate::debug::log("Archive file exists");
 
-    // Ensure destination directory exists
-    if !dest_dir.exists() {
-        crate::debug::log("Creating destination directory...");
-        std::fs::create_dir_all(dest_dir)
-            .map_err(|e| format!("Failed to create destination directory: {}", e))?;
+    // Decompression is CPU-bound and uses blocking file IO
+    let (archive, options) = (archive_path.to_path_buf(), options.clone());
+    let (tx, token) = (progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        let report = match kind {
+            ArchiveKind::Zip => extract_zip(&archive, &mut destination, &options, &tx, &token),
+            ArchiveKind::SevenZip => extract_7z(&archive, &mut destination, &options, &tx, &token),
+        };
+        report.map(|report| (report, destination))
+    })
+    .await
+    .unwrap_or_else(|e| Err(ExtractError::Archive(format!("Extraction task failed: {}", e))));
+
+    match &result {
+        Ok(_) => {
+            let _ = progress_tx.send(ExtractProgress::Completed);
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            let _ = progress_tx.send(ExtractProgress::Cancelled);
+            return Err(ExtractError::Cancelled);
+        }
+        Err(e) => {
+            crate::debug::log(&format!("ERROR: {}", e));
+            let _ = progress_tx.send(ExtractProgress::Error(e.to_string()));
+        }
     }
-    crate::debug::log("Destination directory ready");
-
-    let _ = progress_tx.send(ExtractProgress::Extracting);
-
-    // On macOS, try to use the bundled 7zz from the app bundle first
-    // This avoids Gatekeeper quarantine issues since the app is already unquarantined
-    #[cfg(target_os = "macos")]
-    let (seven_zip_path, is_bundled) = {
-        // Try to find 7zz in app bundle: Contents/Resources/7zz
-        let bundled_path = std::env::current_exe()
-            .ok()
-            .and_then(|exe| {
-                // exe is at: NextUIInstaller.app/Contents/MacOS/nextui-installer
-                // We want: NextUIInstaller.app/Contents/Resources/7zz
-                exe.parent()  // Contents/MacOS
-                    .and_then(|p| p.parent())  // Contents
-                    .map(|contents| contents.join("Resources/7zz"))
-            })
-            .filter(|path| path.exists());
+    result
+}
 
-        if let Some(path) = bundled_path {
-            crate::debug::log(&format!("Using bundled 7zz from app bundle: {:?}", path));
-            (path, true)
-        } else {
-            crate::debug::log("Bundled 7zz not found, extracting to temp...");
-            // Fallback to temp extraction
-            let bin_dir = dirs::cache_dir().unwrap_or_else(std::env::temp_dir);
-            let temp_path = bin_dir.join(format!("7zr_{}", TEMP_PREFIX));
-            std::fs::write(&temp_path, SEVEN_ZIP_EXE)
-                .map_err(|e| format!("Failed to extract 7z tool: {}", e))?;
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = std::fs::metadata(&temp_path)
-                .map_err(|e| format!("Failed to get file permissions: {}", e))?
-                .permissions();
-            perms.set_mode(0o755);
-            std::fs::set_permissions(&temp_path, perms)
-                .map_err(|e| format!("Failed to set executable permission: {}", e))?;
-            crate::debug::log(&format!("Extracted 7z binary to: {:?}", temp_path));
-            (temp_path, false)
+/// Writes entries to a destination and reports progress; shared by both formats
+struct EntryWriter<'a> {
+    options: &'a ExtractOptions,
+    progress_tx: &'a mpsc::UnboundedSender<ExtractProgress>,
+    cancel_token: &'a CancellationToken,
+    total_bytes: u64,
+    total_files: u64,
+    extracted_bytes: u64,
+    last_reported: u64,
+    files_done: u64,
+    buffer: Vec<u8>,
+    report: ExtractReport,
+}
+
+impl<'a> EntryWriter<'a> {
+    fn new(
+        options: &'a ExtractOptions,
+        progress_tx: &'a mpsc::UnboundedSender<ExtractProgress>,
+        cancel_token: &'a CancellationToken,
+        total_bytes: u64,
+        total_files: u64,
+    ) -> Self {
+        crate::debug::log(&format!("{} files, {} bytes uncompressed", total_files, total_bytes));
+        let _ = progress_tx.send(ExtractProgress::Started { total_bytes, total_files });
+        Self {
+            options,
+            progress_tx,
+            cancel_token,
+            total_bytes,
+            total_files,
+            extracted_bytes: 0,
+            last_reported: 0,
+            files_done: 0,
+            buffer: vec![0u8; 256 * 1024],
+            report: ExtractReport::default(),
         }
-    };
+    }
 
-    // On non-macOS platforms, extract 7z binary to temp/cache directory (always temp-extracted, never bundled)
-    #[cfg(not(target_os = "macos"))]
-    let (seven_zip_path, is_bundled) = {
-        #[cfg(target_os = "linux")]
-        let bin_dir = {
-            // If running as root via sudo or pkexec, try to use the actual user's cache directory
-            if unsafe { libc::geteuid() } == 0 {
-                // First check for SUDO_USER (command-line sudo)
-                if let Ok(sudo_user) = std::env::var("SUDO_USER") {
-                    let user_home = std::path::PathBuf::from(format!("/home/{}", sudo_user));
-                    if user_home.exists() {
-                        let user_cache = user_home.join(".cache");
-                        crate::debug::log(&format!("Extracting 7z binary using cache dir for sudo user {}: {:?}", sudo_user, user_cache));
-                        user_cache
-                    } else {
-                        dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                    }
-                }
-                // Check for PKEXEC_UID (GUI elevation via pkexec)
-                else if let Ok(pkexec_uid) = std::env::var("PKEXEC_UID") {
-                    if let Ok(uid) = pkexec_uid.parse::<u32>() {
-                        let pwd = unsafe { libc::getpwuid(uid) };
-                        if !pwd.is_null() {
-                            let username = unsafe {
-                                std::ffi::CStr::from_ptr((*pwd).pw_name)
-                                    .to_string_lossy()
-                                    .to_string()
-                            };
-                            let user_home = std::path::PathBuf::from(format!("/home/{}", username));
-                            if user_home.exists() {
-                                let user_cache = user_home.join(".cache");
-                                crate::debug::log(&format!("Extracting 7z binary using cache dir for pkexec user {} (UID {}): {:?}", username, uid, user_cache));
-                                user_cache
-                            } else {
-                                dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                            }
-                        } else {
-                            dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                        }
-                    } else {
-                        dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
-                    }
+    /// Create the directory or write the file for one entry, streaming it from `reader`
+    /// Symlinks are refused: FAT32 can't store them and one could point anywhere
+    fn write_entry(
+        &mut self,
+        destination: &mut Destination,
+        entry: &EntryMeta,
+        reader: &mut dyn Read,
+    ) -> Result<(), ExtractError> {
+        let name = entry.name;
+        if self.cancel_token.is_cancelled() {
+            return Err(ExtractError::Cancelled);
+        }
+        if entry.is_symlink {
+            return Err(ExtractError::Entry {
+                name: name.to_string(),
+                message: "symbolic links are not supported".to_string(),
+            });
+        }
+
+        let relative = safe_relative_path(name).ok_or_else(|| ExtractError::Entry {
+            name: name.to_string(),
+            message: "unsafe path".to_string(),
+        })?;
+        let write_error = |path: &Path, e: std::io::Error| ExtractError::Write {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        };
+
+        let dest_dir = match destination {
+            Destination::Dir(dest_dir) => dest_dir,
+            Destination::Volume(volume) => {
+                let path = relative.to_string_lossy().replace('\\', "/");
+                if entry.is_dir {
+                    return volume.create_dir_all(&path).map_err(|e| write_error(&relative, e));
                 }
-                else {
-                    dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
+                if let Some((parent, _)) = path.rsplit_once('/') {
+                    volume.create_dir_all(parent).map_err(|e| write_error(Path::new(parent), e))?;
                 }
-            } else {
-                dirs::cache_dir().unwrap_or_else(std::env::temp_dir)
+                let mut out_file = volume.create_file(&path).map_err(|e| write_error(&relative, e))?;
+                self.copy_entry(entry, reader, &mut out_file, &relative)?;
+                out_file.close().map_err(|e| write_error(&relative, e))?;
+                self.report.added.push(name.to_string());
+                return Ok(());
             }
         };
-        #[cfg(not(target_os = "linux"))]
-        let bin_dir = std::env::temp_dir();
-
-        #[cfg(target_os = "windows")]
-        let temp_path = bin_dir.join(format!("7zr_{}.exe", TEMP_PREFIX));
-        #[cfg(not(target_os = "windows"))]
-        let temp_path = bin_dir.join(format!("7zr_{}", TEMP_PREFIX));
-
-        crate::debug::log(&format!("Extracting 7z binary to: {:?}", temp_path));
-        std::fs::write(&temp_path, SEVEN_ZIP_EXE)
-            .map_err(|e| format!("Failed to extract 7z tool: {}", e))?;
-        crate::debug::log("7z binary extracted successfully");
-
-        // On Unix (Linux), make the binary executable
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = std::fs::metadata(&temp_path)
-                .map_err(|e| format!("Failed to get file permissions: {}", e))?
-                .permissions();
-            perms.set_mode(0o755);
-            std::fs::set_permissions(&temp_path, perms)
-                .map_err(|e| format!("Failed to set executable permission: {}", e))?;
-        }
+        let out_path = dest_dir.join(relative);
 
-        (temp_path, false)
-    };
+        if entry.is_dir {
+            return std::fs::create_dir_all(&out_path).map_err(|e| write_error(&out_path, e));
+        }
+        if let Some(parent) = out_path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| write_error(parent, e))?;
+        }
 
-    // Run 7z to extract the archive with -bsp1 for progress output
-    let output_arg = format!("-o{}", dest_dir.display());
-    crate::debug::log(&format!("Running 7z extraction command with output arg: {}", output_arg));
-
-    #[cfg(target_os = "windows")]
-    let mut child = Command::new(&seven_zip_path)
-        .arg("x")
-        .arg(archive_path)
-        .arg(&output_arg)
-        .arg("-y")
-        .arg("-bsp1")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .creation_flags(CREATE_NO_WINDOW)
-        .spawn()
-        .map_err(|e| format!("Failed to start 7z: {}", e))?;
-
-    #[cfg(not(target_os = "windows"))]
-    let mut child = Command::new(&seven_zip_path)
-        .arg("x")
-        .arg(archive_path)
-        .arg(&output_arg)
-        .arg("-y")
-        .arg("-bsp1")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .map_err(|e| format!("Failed to start 7z: {}", e))?;
-
-    crate::debug::log(&format!("7z process started (PID: {:?})", child.id()));
-
-    // Take stdout for progress parsing
-    let mut stdout = child.stdout.take()
-        .ok_or_else(|| "Failed to capture 7z stdout".to_string())?;
-
-    // Take stderr for real-time logging
-    let mut stderr = child.stderr.take()
-        .ok_or_else(|| "Failed to capture 7z stderr".to_string())?;
-
-    // Log stderr in real-time instead of buffering
-    let stderr_handle = tokio::spawn(async move {
-        let mut buffer = Vec::new();
-        let mut chunk = [0u8; 512];
-        loop {
-            match stderr.read(&mut chunk).await {
-                Ok(0) => break, // EOF
-                Ok(n) => {
-                    let text = String::from_utf8_lossy(&chunk[..n]);
-                    if !text.trim().is_empty() {
-                        crate::debug::log(&format!("7z stderr: {}", text.trim()));
-                    }
-                    buffer.extend_from_slice(&chunk[..n]);
-                }
-                Err(e) => {
-                    crate::debug::log(&format!("Error reading 7z stderr: {}", e));
-                    break;
-                }
+        let exists = out_path.is_file();
+        if self.options.update && exists {
+            if is_excluded(name, &self.options.preserve) {
+                self.report.preserved.push(name.to_string());
+                self.skip(entry);
+                return Ok(());
+            }
+            if let Some(sha256) = identical_sha256(&out_path, entry) {
+                self.report.unchanged.push(name.to_string());
+                self.report.files.push(InstalledFile { path: name.to_string(), size: entry.size, sha256 });
+                self.skip(entry);
+                return Ok(());
             }
         }
-        buffer
-    });
 
-    let mut last_percent: u8 = 0;
-    let mut buffer = [0u8; 1024];
-    let mut last_output_time = std::time::Instant::now();
+        if !(self.options.update && exists) {
+            let mut out_file = std::fs::File::create(&out_path).map_err(|e| write_error(&out_path, e))?;
+            self.copy_entry(entry, reader, &mut out_file, &out_path)?;
+            self.report.added.push(name.to_string());
+            return Ok(());
+        }
 
-    // Read stdout looking for progress
-    // 7z with -bsp1 uses backspaces or carriage returns, so we read raw chunks
-    loop {
-        tokio::select! {
-            _ = cancel_token.cancelled() => {
-                crate::debug::log("Extraction cancelled by user");
-                let _ = child.kill().await;
-                // Only delete if it's a temp-extracted binary, not a bundled one
-                if !is_bundled {
-                    let _ = std::fs::remove_file(&seven_zip_path);
-                }
-                let _ = progress_tx.send(ExtractProgress::Cancelled);
-                return Err("Extraction cancelled".to_string());
+        // The old file stays whole until the new one is complete
+        let file_name = out_path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = out_path.with_file_name(format!(".{}.tmp", file_name));
+        let written = std::fs::File::create(&temp_path)
+            .map_err(|e| write_error(&temp_path, e))
+            .and_then(|mut out_file| {
+                self.copy_entry(entry, reader, &mut out_file, &temp_path)?;
+                out_file.sync_all().map_err(|e| write_error(&temp_path, e))
+            })
+            .and_then(|()| std::fs::rename(&temp_path, &out_path).map_err(|e| write_error(&out_path, e)));
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        if exists {
+            self.report.replaced.push(name.to_string());
+        } else {
+            self.report.added.push(name.to_string());
+        }
+        Ok(())
+    }
+
+    /// Stream an entry's data from `reader` to `out_file` and record its SHA-256
+    fn copy_entry(
+        &mut self,
+        entry: &EntryMeta,
+        reader: &mut dyn Read,
+        out_file: &mut dyn Write,
+        out_path: &Path,
+    ) -> Result<(), ExtractError> {
+        let name = entry.name;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        loop {
+            if self.cancel_token.is_cancelled() {
+                return Err(ExtractError::Cancelled);
             }
-            _ = tokio::time::sleep(std::time::Duration::from_secs(30)) => {
-                // Check if we've received output recently (within 5 minutes)
-                let elapsed = last_output_time.elapsed();
-                if elapsed > std::time::Duration::from_secs(300) {
-                    crate::debug::log(&format!("Extraction timeout: no output for {} seconds", elapsed.as_secs()));
-                    let _ = child.kill().await;
-                    if !is_bundled {
-                        let _ = std::fs::remove_file(&seven_zip_path);
-                    }
-                    let _ = progress_tx.send(ExtractProgress::Error("Extraction timed out (no progress for 5 minutes)".to_string()));
-                    return Err("Extraction timed out - the process may have hung".to_string());
-                }
+            // Read errors here are bad compressed data or a CRC mismatch
+            let read = reader.read(&mut self.buffer).map_err(|e| ExtractError::Entry {
+                name: name.to_string(),
+                message: e.to_string(),
+            })?;
+            if read == 0 {
+                break;
             }
-            read_result = stdout.read(&mut buffer) => {
-                match read_result {
-                    Ok(0) => {
-                        // EOF - process finished output
-                        crate::debug::log("7z stdout reached EOF");
-                        break;
-                    }
-                    Ok(n) => {
-                        last_output_time = std::time::Instant::now();
-                        // Parse the buffer for percentage
-                        let text = String::from_utf8_lossy(&buffer[..n]);
-                        if let Some(percent) = parse_last_percentage(&text) {
-                            if percent != last_percent {
-                                last_percent = percent;
-                                crate::debug::log(&format!("Extraction progress: {}%", percent));
-                                let _ = progress_tx.send(ExtractProgress::Progress { percent });
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        crate::debug::log(&format!("Error reading 7z output: {}", e));
-                        break;
-                    }
-                }
+            out_file.write_all(&self.buffer[..read]).map_err(|e| ExtractError::Write {
+                path: out_path.to_path_buf(),
+                message: e.to_string(),
+            })?;
+            hasher.update(&self.buffer[..read]);
+            size += read as u64;
+
+            self.extracted_bytes += read as u64;
+            if self.extracted_bytes - self.last_reported >= PROGRESS_INTERVAL_BYTES {
+                self.last_reported = self.extracted_bytes;
+                self.report(name);
             }
         }
+        self.files_done += 1;
+        let sha256 = to_hex(&hasher.finalize());
+        self.report.files.push(InstalledFile { path: name.to_string(), size, sha256 });
+        Ok(())
+    }
+
+    /// Count a file that is left as it is towards the progress
+    fn skip(&mut self, entry: &EntryMeta) {
+        self.extracted_bytes += entry.size;
+        self.files_done += 1;
+        if self.extracted_bytes - self.last_reported >= PROGRESS_INTERVAL_BYTES {
+            self.last_reported = self.extracted_bytes;
+            self.report(entry.name);
+        }
     }
 
-    // Wait for process to complete
-    let status = child.wait().await
-        .map_err(|e| format!("Failed to wait for 7z: {}", e))?;
+    fn report(&self, current_file: &str) {
+        let _ = self.progress_tx.send(ExtractProgress::Progress {
+            extracted_bytes: self.extracted_bytes,
+            total_bytes: self.total_bytes,
+            files_done: self.files_done,
+            total_files: self.total_files,
+            current_file: current_file.to_string(),
+        });
+    }
 
-    // Clean up the temp 7z executable (only if not bundled)
-    if !is_bundled {
-        let _ = std::fs::remove_file(&seven_zip_path);
-        crate::debug::log("Cleaned up temp 7z binary");
-    } else {
-        crate::debug::log("Keeping bundled 7z binary (from app bundle)");
+    /// `in_archive` is every file in the archive, extracted or not
+    fn finish(mut self, in_archive: &HashSet<String>) -> ExtractReport {
+        self.report("");
+        crate::debug::log(&format!("Extracted {} files ({} bytes)", self.files_done, self.extracted_bytes));
+        if self.options.update {
+            self.report.stale = self
+                .options
+                .installed
+                .iter()
+                .filter(|path| !in_archive.contains(*path) && !is_excluded(path, &self.options.preserve))
+                .cloned()
+                .collect();
+        }
+        self.report
     }
+}
 
-    if status.success() {
-        crate::debug::log("7z extraction completed successfully");
-        let _ = progress_tx.send(ExtractProgress::Completed);
-        Ok(())
-    } else {
-        // Get stderr output from background task
-        let stderr_output = if let Ok(buf) = stderr_handle.await {
-            String::from_utf8_lossy(&buf).trim().to_string()
-        } else {
-            String::new()
+/// The SHA-256 of the file on disk if it has the entry's size and CRC32 (entries
+/// without a CRC are always rewritten)
+fn identical_sha256(path: &Path, entry: &EntryMeta) -> Option<String> {
+    let expected = entry.crc32?;
+    if std::fs::metadata(path).map(|m| m.len()).ok() != Some(entry.size) {
+        return None;
+    }
+    let mut file = std::fs::File::open(path).ok()?;
+    let mut crc = crc32fast::Hasher::new();
+    let mut sha = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buffer).ok()? {
+            0 => break,
+            read => {
+                crc.update(&buffer[..read]);
+                sha.update(&buffer[..read]);
+            }
+        }
+    }
+    (crc.finalize() == expected).then(|| to_hex(&sha.finalize()))
+}
+
+/// Decompress every entry of a zip archive (blocking)
+fn extract_zip(
+    archive_path: &Path,
+    destination: &mut Destination,
+    options: &ExtractOptions,
+    progress_tx: &mpsc::UnboundedSender<ExtractProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<ExtractReport, ExtractError> {
+    let file = std::fs::File::open(archive_path).map_err(|e| ExtractError::Archive(e.to_string()))?;
+    let mut zip = zip::ZipArchive::new(std::io::BufReader::new(file)).map_err(|e| ExtractError::Archive(e.to_string()))?;
+
+    // Totals come from the central directory, so nothing is decompressed twice
+    let mut total_bytes = 0;
+    let mut total_files = 0;
+    let mut in_archive = HashSet::new();
+    for i in 0..zip.len() {
+        let entry = zip.by_index_raw(i).map_err(|e| ExtractError::Archive(e.to_string()))?;
+        if !entry.is_dir() {
+            in_archive.insert(entry.name().to_string());
+        }
+        if !entry.is_dir() && !options.skips(entry.name()) {
+            total_bytes += entry.size();
+            total_files += 1;
+        }
+    }
+
+    let mut writer = EntryWriter::new(options, progress_tx, cancel_token, total_bytes, total_files);
+    for i in 0..zip.len() {
+        let skip = zip.name_for_index(i).is_some_and(|name| options.skips(name));
+        if skip {
+            continue;
+        }
+        let mut entry = zip.by_index(i).map_err(|e| ExtractError::Entry {
+            name: format!("entry {}", i),
+            message: e.to_string(),
+        })?;
+        let name = entry.name().to_string();
+        let meta = EntryMeta {
+            name: &name,
+            is_dir: entry.is_dir(),
+            is_symlink: entry.is_symlink(),
+            size: entry.size(),
+            crc32: Some(entry.crc32()),
         };
+        writer.write_entry(destination, &meta, &mut entry)?;
+    }
+    Ok(writer.finish(&in_archive))
+}
 
-        let exit_code = status.code().map(|c| c.to_string()).unwrap_or_else(|| "unknown".to_string());
-        let err_msg = if stderr_output.is_empty() {
-            format!("7z extraction failed with exit code: {}", exit_code)
-        } else {
-            format!("7z extraction failed (code {}): {}", exit_code, stderr_output)
+/// Decompress every entry of a 7z archive (blocking)
+/// Solid archives can only be decoded front to back, so entries arrive in archive order
+fn extract_7z(
+    archive_path: &Path,
+    destination: &mut Destination,
+    options: &ExtractOptions,
+    progress_tx: &mpsc::UnboundedSender<ExtractProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<ExtractReport, ExtractError> {
+    let mut reader = sevenz_rust::SevenZReader::open(archive_path, sevenz_rust::Password::empty())
+        .map_err(|e| ExtractError::Archive(e.to_string()))?;
+
+    let files = || {
+        reader
+            .archive()
+            .files
+            .iter()
+            .filter(|f| !f.is_directory && !f.is_anti_item && !options.skips(f.name()))
+    };
+    let total_bytes = files().map(|f| f.size).sum();
+    let total_files = files().count() as u64;
+    let in_archive: HashSet<String> = reader
+        .archive()
+        .files
+        .iter()
+        .filter(|f| !f.is_directory && !f.is_anti_item)
+        .map(|f| f.name().to_string())
+        .collect();
+
+    let mut writer = EntryWriter::new(options, progress_tx, cancel_token, total_bytes, total_files);
+    // sevenz-rust only stops on an error, so ours is kept here and a placeholder returned
+    let mut failure = None;
+    let result = reader.for_each_entries(|entry, data| {
+        if entry.is_anti_item {
+            return Ok(true);
+        }
+        // Entries share one decompression stream, so whatever isn't written still has to be read
+        if options.skips(entry.name()) {
+            std::io::copy(data, &mut std::io::sink())?;
+            return Ok(true);
+        }
+        let meta = EntryMeta {
+            name: entry.name(),
+            is_dir: entry.is_directory,
+            is_symlink: is_7z_symlink(entry),
+            size: entry.size,
+            crc32: entry.has_crc.then_some(entry.crc as u32),
         };
+        match writer.write_entry(destination, &meta, data) {
+            Ok(()) => {
+                std::io::copy(data, &mut std::io::sink())?;
+                Ok(true)
+            }
+            Err(e) => {
+                failure = Some(e);
+                Err(sevenz_rust::Error::other("extraction stopped"))
+            }
+        }
+    });
 
-        crate::debug::log(&format!("ERROR: {}", err_msg));
-        let _ = progress_tx.send(ExtractProgress::Error(err_msg.clone()));
-        Err(err_msg)
+    if let Some(e) = failure {
+        return Err(e);
     }
+    result.map_err(|e| ExtractError::Entry {
+        name: archive_path.display().to_string(),
+        message: e.to_string(),
+    })?;
+    Ok(writer.finish(&in_archive))
 }
 
-/// Parse the last percentage from a text chunk
-/// Finds the last occurrence of "N%" in the text
-fn parse_last_percentage(text: &str) -> Option<u8> {
-    // Collect all matches of '%'
-    text.match_indices('%')
-        .fold(None, |acc, (idx, _)| {
-            // Check preceding characters for digits
-            let prefix = &text[..idx];
-            let num_str: String = prefix
-                .chars()
-                .rev()
-                .take_while(|c| c.is_ascii_digit())
-                .collect::<String>()
-                .chars()
-                .rev()
-                .collect();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_zip::write_zip;
+
+    #[tokio::test]
+    async fn test_extract_zip_with_progress() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("release.zip");
+        let big: Vec<u8> = (0..3_000_000u32).map(|i| (i % 7) as u8).collect();
+        write_zip(&archive, &[("MinUI.zip", b"minui"), (".system/bin/big.bin", &big)]);
+
+        let dest = dir.path().join("out");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        extract_archive(&archive, &dest, &ExtractOptions::default(), tx, CancellationToken::new()).await.unwrap();
+
+        assert_eq!(std::fs::read(dest.join("MinUI.zip")).unwrap(), b"minui");
+        assert_eq!(std::fs::read(dest.join(".system/bin/big.bin")).unwrap(), big);
+
+        let events: Vec<ExtractProgress> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let total = big.len() as u64 + 5;
+        assert!(matches!(events[0], ExtractProgress::Started { total_bytes, total_files: 2 } if total_bytes == total));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            ExtractProgress::Progress { current_file, files_done: 1, .. } if current_file == ".system/bin/big.bin"
+        )));
+        assert!(matches!(
+            events[events.len() - 2],
+            ExtractProgress::Progress { extracted_bytes, files_done: 2, .. } if extracted_bytes == total
+        ));
+        assert!(matches!(events.last(), Some(ExtractProgress::Completed)));
+    }
 
-            if !num_str.is_empty() {
-                if let Ok(percent) = num_str.parse::<u8>() {
-                    return Some(percent.min(100));
-                }
-            }
-            acc
-        })
-}
+    #[tokio::test]
+    async fn test_extract_7z() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(source.join(".system/res")).unwrap();
+        std::fs::write(source.join("MinUI.zip"), b"minui").unwrap();
+        std::fs::write(source.join(".system/res/font.ttf"), vec![42u8; 100_000]).unwrap();
+
+        // Named .zip on purpose: the format comes from the magic bytes
+        let archive = dir.path().join("release.zip");
+        sevenz_rust::compress_to_path(&source, &archive).unwrap();
+
+        let dest = dir.path().join("out");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        extract_archive(&archive, &dest, &ExtractOptions::default(), tx, CancellationToken::new()).await.unwrap();
+
+        assert_eq!(std::fs::read(dest.join("MinUI.zip")).unwrap(), b"minui");
+        assert_eq!(std::fs::read(dest.join(".system/res/font.ttf")).unwrap(), vec![42u8; 100_000]);
+
+        let events: Vec<ExtractProgress> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(matches!(events[0], ExtractProgress::Started { total_bytes: 100_005, total_files: 2 }));
+        assert!(matches!(events.last(), Some(ExtractProgress::Completed)));
+
+        // A skipped entry is still decoded, so the ones after it in the solid stream come out intact
+        let dest = dir.path().join("partial");
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let options = ExtractOptions {
+            excluded: vec![".system/".to_string()],
+            ..Default::default()
+        };
+        extract_archive(&archive, &dest, &options, tx, CancellationToken::new()).await.unwrap();
+        assert_eq!(std::fs::read(dest.join("MinUI.zip")).unwrap(), b"minui");
+        assert!(!dest.join(".system").exists());
+        let events: Vec<ExtractProgress> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        assert!(matches!(events[0], ExtractProgress::Started { total_bytes: 5, total_files: 1 }));
+    }
 
-/// Main entry point for extraction with cancellation support
-pub async fn extract_7z_with_progress(
-    archive_path: &Path,
-    dest_dir: &Path,
-    progress_tx: mpsc::UnboundedSender<ExtractProgress>,
-    cancel_token: CancellationToken,
-) -> Result<(), String> {
-    extract_7z(archive_path, dest_dir, progress_tx, cancel_token).await
+    #[tokio::test]
+    async fn test_update_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("release.zip");
+        write_zip(
+            &archive,
+            &[
+                ("MinUI.zip", b"new"),
+                (".system/version.txt", b"v2"),
+                ("Saves/readme.txt", b"release readme"),
+                ("Roms/GB/readme.txt", b"put roms here"),
+            ],
+        );
+
+        let card = dir.path().join("card");
+        std::fs::create_dir_all(card.join(".system")).unwrap();
+        std::fs::create_dir_all(card.join("Saves")).unwrap();
+        std::fs::write(card.join("MinUI.zip"), b"old").unwrap();
+        std::fs::write(card.join(".system/version.txt"), b"v2").unwrap();
+        std::fs::write(card.join("Saves/readme.txt"), b"my notes").unwrap();
+        std::fs::write(card.join("old.elf"), b"dropped").unwrap();
+
+        let options = ExtractOptions {
+            update: true,
+            preserve: vec!["Saves/".to_string()],
+            installed: ["MinUI.zip", ".system/version.txt", "old.elf", "Saves/notes.txt"].map(String::from).to_vec(),
+            ..Default::default()
+        };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let report = extract_archive(&archive, &card, &options, tx, CancellationToken::new()).await.unwrap();
+
+        assert_eq!(report.added, vec!["Roms/GB/readme.txt"]);
+        assert_eq!(report.replaced, vec!["MinUI.zip"]);
+        assert_eq!(report.unchanged, vec![".system/version.txt"]);
+        assert_eq!(report.preserved, vec!["Saves/readme.txt"]);
+        assert_eq!(report.stale, vec!["old.elf"]);
+        assert_eq!(std::fs::read(card.join("MinUI.zip")).unwrap(), b"new");
+        assert!(card.join("old.elf").exists());
+        assert!(!card.join(".MinUI.zip.tmp").exists());
+        assert_eq!(std::fs::read(card.join("Saves/readme.txt")).unwrap(), b"my notes");
+
+        // Unchanged files are part of the install and get hashed too, the user's are not
+        let mut paths: Vec<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec![".system/version.txt", "MinUI.zip", "Roms/GB/readme.txt"]);
+        let version = report.files.iter().find(|f| f.path == ".system/version.txt").unwrap();
+        assert_eq!((version.size, version.sha256.clone()), (2, to_hex(&Sha256::digest(b"v2"))));
+
+        // A repair only writes the files it is given
+        std::fs::write(card.join("MinUI.zip"), b"bad").unwrap();
+        std::fs::remove_file(card.join("Roms/GB/readme.txt")).unwrap();
+        let options = ExtractOptions { only: vec!["MinUI.zip".to_string()], ..options };
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let report = extract_archive(&archive, &card, &options, tx, CancellationToken::new()).await.unwrap();
+        assert_eq!(report.replaced, vec!["MinUI.zip"]);
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(std::fs::read(card.join("MinUI.zip")).unwrap(), b"new");
+        assert!(!card.join("Roms/GB/readme.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_extract_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("release.zip");
+        write_zip(&archive, &[("MinUI.zip", b"minui")]);
+        let options = ExtractOptions::default();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let result = extract_archive(&archive, &dir.path().join("out"), &options, tx.clone(), token).await;
+        assert_eq!(result, Err(ExtractError::Cancelled));
+
+        // Truncated download: the central directory at the end is missing
+        let bytes = std::fs::read(&archive).unwrap();
+        std::fs::write(&archive, &bytes[..bytes.len() / 2]).unwrap();
+        let result = extract_archive(&archive, &dir.path().join("out"), &options, tx.clone(), CancellationToken::new()).await;
+        assert!(matches!(result, Err(ExtractError::Archive(_))), "{:?}", result);
+
+        // Entries that would land outside the destination are never written
+        write_zip(&archive, &[("../escaped.txt", b"evil")]);
+        let result = extract_archive(&archive, &dir.path().join("out"), &options, tx, CancellationToken::new()).await;
+        assert!(matches!(result, Err(ExtractError::Entry { .. })), "{:?}", result);
+        assert!(!dir.path().join("escaped.txt").exists());
+    }
 }
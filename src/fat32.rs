@@ -1,64 +1,115 @@
 // Custom FAT32 formatter that works for drives > 32GB
 // Windows artificially limits FAT32 to 32GB, but the filesystem supports up to 2TB
-// This writes directly to the physical disk to avoid issues with unmounted volumes
+// Writes the partition table and filesystem straight to anything seekable: a physical
+// disk on Windows, a block device on Linux, or a plain image file, so no OS format
+// tools are needed. Files can then be written into the new volume and read back
+// from it the same way, without mounting it.
 
-use tokio::sync::mpsc;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom, Write};
 
-use crate::format::FormatProgress;
-
-#[cfg(windows)]
 const SECTOR_SIZE: u32 = 512;
-#[cfg(windows)]
 const RESERVED_SECTORS: u16 = 32;
-#[cfg(windows)]
 const NUM_FATS: u8 = 2;
-#[cfg(windows)]
 const PARTITION_START_SECTOR: u64 = 2048; // Standard 1MB alignment
 
-#[cfg(windows)]
+/// Byte offset of the partition on the disk
+pub const PARTITION_OFFSET: u64 = PARTITION_START_SECTOR * SECTOR_SIZE as u64;
+
+/// A FAT with fewer clusters than this is FAT16 or FAT12 by definition
+const MIN_CLUSTERS: u64 = 65525;
+
+/// MBR partition type: FAT32 with LBA addressing
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0C;
+
+/// A GPT keeps a backup of its header and partition entries in the last 33 sectors
+const GPT_BACKUP_SECTORS: u64 = 33;
+
+/// Zeros are written in chunks of this size
+const ZERO_CHUNK: usize = 1024 * 1024;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// FAT entry that ends a cluster chain
+const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
+/// Most entries a FAT32 directory can hold
+const MAX_DIR_ENTRIES: usize = 65536;
+
+/// File data is written to the volume in runs of this size
+const WRITE_CHUNK: usize = 1024 * 1024;
+
+/// Characters 8.3 names may contain besides A-Z and 0-9
+const SHORT_NAME_CHARS: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Cluster size in bytes for a FAT32 volume of this size (Microsoft recommendations)
+pub fn cluster_size(total_bytes: u64) -> u64 {
+    if total_bytes <= 64 * 1024 * 1024 {
+        512 // up to 64MB
+    } else if total_bytes <= 128 * 1024 * 1024 {
+        1024 // up to 128MB
+    } else if total_bytes <= 256 * 1024 * 1024 {
+        2048 // up to 256MB
+    } else if total_bytes <= 8u64 * 1024 * 1024 * 1024 {
+        4096 // up to 8GB
+    } else if total_bytes <= 16u64 * 1024 * 1024 * 1024 {
+        8192 // up to 16GB
+    } else if total_bytes <= 32u64 * 1024 * 1024 * 1024 {
+        16384 // up to 32GB
+    } else {
+        32768 // above 32GB
+    }
+}
+
+/// Roughly how many bytes of a freshly formatted FAT32 card of this size can hold files:
+/// everything minus the 1MB partition alignment, reserved sectors and both FATs
+pub fn usable_bytes(total_bytes: u64) -> u64 {
+    let clusters = total_bytes / cluster_size(total_bytes);
+    let fats = 2 * clusters * 4;
+    total_bytes.saturating_sub(1024 * 1024 + 32 * 512 + fats)
+}
+
 #[derive(Debug)]
 struct Fat32Params {
     sectors_per_cluster: u8,
     total_sectors: u64,
     fat_size_sectors: u32,
     root_cluster: u32,
+    /// Sectors on the disk before the volume
+    hidden_sectors: u32,
 }
 
-#[cfg(windows)]
-fn calculate_params(total_bytes: u64) -> Fat32Params {
+fn calculate_params(total_bytes: u64, hidden_sectors: u32) -> Result<Fat32Params, String> {
     let total_sectors = total_bytes / SECTOR_SIZE as u64;
+    if total_sectors > u32::MAX as u64 {
+        return Err(format!("{} bytes is too large for a FAT32 partition (2TB at most)", total_bytes));
+    }
 
-    // Choose cluster size based on volume size (Microsoft recommendations)
-    let sectors_per_cluster: u8 = if total_bytes <= 64 * 1024 * 1024 {
-        1 // 512 bytes - up to 64MB
-    } else if total_bytes <= 128 * 1024 * 1024 {
-        2 // 1KB - up to 128MB
-    } else if total_bytes <= 256 * 1024 * 1024 {
-        4 // 2KB - up to 256MB
-    } else if total_bytes <= 8u64 * 1024 * 1024 * 1024 {
-        8 // 4KB - up to 8GB
-    } else if total_bytes <= 16u64 * 1024 * 1024 * 1024 {
-        16 // 8KB - up to 16GB
-    } else if total_bytes <= 32u64 * 1024 * 1024 * 1024 {
-        32 // 16KB - up to 32GB
-    } else {
-        64 // 32KB - above 32GB
-    };
+    let sectors_per_cluster = (cluster_size(total_bytes) / SECTOR_SIZE as u64) as u8;
 
     // Calculate FAT size
     let data_sectors = total_sectors.saturating_sub(RESERVED_SECTORS as u64);
     let cluster_count = data_sectors / sectors_per_cluster as u64;
-    let fat_size_sectors = ((cluster_count + 2) * 4 + SECTOR_SIZE as u64 - 1) / SECTOR_SIZE as u64;
+    let fat_size_sectors = ((cluster_count + 2) * 4).div_ceil(SECTOR_SIZE as u64);
+
+    // What is left for clusters once both FATs are in place
+    let usable_clusters = data_sectors.saturating_sub(NUM_FATS as u64 * fat_size_sectors) / sectors_per_cluster as u64;
+    if usable_clusters < MIN_CLUSTERS {
+        return Err(format!("{} bytes is too small for a FAT32 partition", total_bytes));
+    }
 
-    Fat32Params {
+    Ok(Fat32Params {
         sectors_per_cluster,
         total_sectors,
         fat_size_sectors: fat_size_sectors as u32,
         root_cluster: 2,
-    }
+        hidden_sectors,
+    })
 }
 
-#[cfg(windows)]
 fn create_boot_sector(params: &Fat32Params, volume_label: &str) -> [u8; 512] {
     let mut boot = [0u8; 512];
 
@@ -108,15 +159,10 @@ fn create_boot_sector(params: &Fat32Params, volume_label: &str) -> [u8; 512] {
     boot[27] = 0;
 
     // Hidden sectors (sectors before partition = partition start)
-    boot[28..32].copy_from_slice(&(PARTITION_START_SECTOR as u32).to_le_bytes());
+    boot[28..32].copy_from_slice(&params.hidden_sectors.to_le_bytes());
 
-    // Total sectors 32-bit
-    let total_32 = if params.total_sectors > u32::MAX as u64 {
-        u32::MAX
-    } else {
-        params.total_sectors as u32
-    };
-    boot[32..36].copy_from_slice(&total_32.to_le_bytes());
+    // Total sectors 32-bit (calculate_params checked that it fits)
+    boot[32..36].copy_from_slice(&(params.total_sectors as u32).to_le_bytes());
 
     // FAT32 specific fields
     // FAT size 32-bit
@@ -158,11 +204,7 @@ fn create_boot_sector(params: &Fat32Params, volume_label: &str) -> [u8; 512] {
     boot[67..71].copy_from_slice(&serial.to_le_bytes());
 
     // Volume label (11 bytes, space-padded)
-    let mut label_bytes = [0x20u8; 11];
-    let label_src = volume_label.as_bytes();
-    let copy_len = label_src.len().min(11);
-    label_bytes[..copy_len].copy_from_slice(&label_src[..copy_len]);
-    boot[71..82].copy_from_slice(&label_bytes);
+    boot[71..82].copy_from_slice(&label_field(volume_label));
 
     // File system type
     boot[82..90].copy_from_slice(b"FAT32   ");
@@ -174,7 +216,6 @@ fn create_boot_sector(params: &Fat32Params, volume_label: &str) -> [u8; 512] {
     boot
 }
 
-#[cfg(windows)]
 fn create_fsinfo_sector() -> [u8; 512] {
     let mut fsinfo = [0u8; 512];
 
@@ -196,7 +237,6 @@ fn create_fsinfo_sector() -> [u8; 512] {
     fsinfo
 }
 
-#[cfg(windows)]
 fn create_fat_sector_with_entries() -> [u8; 512] {
     let mut fat = [0u8; 512];
 
@@ -212,151 +252,1078 @@ fn create_fat_sector_with_entries() -> [u8; 512] {
     fat
 }
 
-/// Format using physical disk access (works even when volume isn't mounted)
+/// Volume label as stored on disk: 11 bytes, space-padded
+fn label_field(volume_label: &str) -> [u8; 11] {
+    let mut label_bytes = [0x20u8; 11];
+    let label_src = volume_label.as_bytes();
+    let copy_len = label_src.len().min(11);
+    label_bytes[..copy_len].copy_from_slice(&label_src[..copy_len]);
+    label_bytes
+}
+
+fn create_mbr(partition_sectors: u32) -> [u8; 512] {
+    let mut mbr = [0u8; 512];
+
+    // Disk signature
+    let signature: u32 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+        .unwrap_or(0x12345678);
+    mbr[440..444].copy_from_slice(&signature.to_le_bytes());
+
+    // First partition entry: bootable, CHS fields maxed out (LBA only)
+    let entry = &mut mbr[446..462];
+    entry[0] = 0x80;
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = PARTITION_TYPE_FAT32_LBA;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&(PARTITION_START_SECTOR as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&partition_sectors.to_le_bytes());
+
+    // Boot signature
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+
+    mbr
+}
+
+fn write_at<W: Write + Seek>(disk: &mut W, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    disk.seek(SeekFrom::Start(offset))?;
+    disk.write_all(data)
+}
+
+/// Zero `len` bytes from `offset`, reporting how many bytes are done after each chunk
+fn write_zeros<W: Write + Seek>(
+    disk: &mut W,
+    offset: u64,
+    len: u64,
+    progress: &mut dyn FnMut(u64),
+) -> std::io::Result<()> {
+    let zeros = vec![0u8; ZERO_CHUNK];
+    disk.seek(SeekFrom::Start(offset))?;
+    let mut written = 0u64;
+    while written < len {
+        let chunk = (len - written).min(ZERO_CHUNK as u64) as usize;
+        disk.write_all(&zeros[..chunk])?;
+        written += chunk as u64;
+        progress(written);
+    }
+    Ok(())
+}
+
+/// Partition a whole disk of `disk_bytes` with an MBR holding a single FAT32 partition
+/// from 1MB to the end, and format that partition
+/// Progress is reported in percent
+pub fn format_disk<W: Write + Seek>(
+    disk: &mut W,
+    disk_bytes: u64,
+    volume_label: &str,
+    progress: &mut dyn FnMut(u8),
+) -> Result<(), String> {
+    let partition_offset = PARTITION_OFFSET;
+    let partition_sectors = disk_bytes.saturating_sub(partition_offset) / SECTOR_SIZE as u64;
+    // Fail before anything is written if the disk doesn't fit FAT32
+    calculate_params(partition_sectors * SECTOR_SIZE as u64, PARTITION_START_SECTOR as u32)?;
+
+    // Clear old partition tables, including the backup copy of a GPT at the end
+    write_zeros(disk, 0, partition_offset, &mut |_| {})
+        .map_err(|e| format!("Failed to clear partition table: {}", e))?;
+    let disk_sectors = disk_bytes / SECTOR_SIZE as u64;
+    write_zeros(
+        disk,
+        (disk_sectors - GPT_BACKUP_SECTORS) * SECTOR_SIZE as u64,
+        GPT_BACKUP_SECTORS * SECTOR_SIZE as u64,
+        &mut |_| {},
+    )
+    .map_err(|e| format!("Failed to clear backup partition table: {}", e))?;
+
+    write_at(disk, 0, &create_mbr(partition_sectors as u32))
+        .map_err(|e| format!("Failed to write partition table: {}", e))?;
+
+    format_volume(disk, partition_offset, partition_sectors * SECTOR_SIZE as u64, volume_label, progress)
+}
+
+/// Format the `volume_bytes` starting at `offset` of `disk` as FAT32
+/// Both FATs are cleared completely, so nothing of an older filesystem shows through
+/// Progress is reported in percent
+pub fn format_volume<W: Write + Seek>(
+    disk: &mut W,
+    offset: u64,
+    volume_bytes: u64,
+    volume_label: &str,
+    progress: &mut dyn FnMut(u8),
+) -> Result<(), String> {
+    let params = calculate_params(volume_bytes, (offset / SECTOR_SIZE as u64) as u32)?;
+    let sector = |n: u64| offset + n * SECTOR_SIZE as u64;
+
+    let fat_start = RESERVED_SECTORS as u64;
+    let fats_sectors = NUM_FATS as u64 * params.fat_size_sectors as u64;
+    let data_start = fat_start + fats_sectors;
+    let cluster_sectors = params.sectors_per_cluster as u64;
+
+    // Reserved area, both FATs and the root directory cluster, all zeroed first
+    let clear_bytes = (data_start + cluster_sectors) * SECTOR_SIZE as u64;
+    let mut last_percent = None;
+    write_zeros(disk, offset, clear_bytes, &mut |done| {
+        let percent = (done * 100 / clear_bytes) as u8;
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            progress(percent);
+        }
+    })
+    .map_err(|e| format!("Failed to clear FAT: {}", e))?;
+
+    // Boot sector and FSInfo, with backups at sectors 6 and 7
+    let boot_sector = create_boot_sector(&params, volume_label);
+    let fsinfo = create_fsinfo_sector();
+    write_at(disk, sector(0), &boot_sector).map_err(|e| format!("Failed to write boot sector: {}", e))?;
+    write_at(disk, sector(1), &fsinfo).map_err(|e| format!("Failed to write FSInfo: {}", e))?;
+    write_at(disk, sector(6), &boot_sector).map_err(|e| format!("Failed to write backup boot sector: {}", e))?;
+    write_at(disk, sector(7), &fsinfo).map_err(|e| format!("Failed to write backup FSInfo: {}", e))?;
+
+    // Reserved entries and the root directory chain at the start of each FAT
+    let fat_first_sector = create_fat_sector_with_entries();
+    for fat in 0..NUM_FATS as u64 {
+        write_at(disk, sector(fat_start + fat * params.fat_size_sectors as u64), &fat_first_sector)
+            .map_err(|e| format!("Failed to write FAT{}: {}", fat + 1, e))?;
+    }
+
+    // Root directory with the volume label entry
+    let mut root_sector = [0u8; 512];
+    root_sector[0..11].copy_from_slice(&label_field(volume_label));
+    root_sector[11] = ATTR_VOLUME_ID;
+    write_at(disk, sector(data_start), &root_sector)
+        .map_err(|e| format!("Failed to write root directory: {}", e))?;
+
+    disk.flush().map_err(|e| format!("Failed to flush disk: {}", e))?;
+    Ok(())
+}
+
+/// Format the partition diskpart created, through the physical disk (works even when
+/// the volume isn't mounted)
 #[cfg(windows)]
 pub async fn format_fat32_large(
     disk_number: u32,
     volume_label: &str,
     total_bytes: u64,
-    progress_tx: mpsc::UnboundedSender<FormatProgress>,
+    progress_tx: tokio::sync::mpsc::UnboundedSender<crate::format::FormatProgress>,
 ) -> Result<(), String> {
-    use windows::Win32::Foundation::{HANDLE, CloseHandle, GENERIC_READ, GENERIC_WRITE};
+    use crate::format::FormatProgress;
+    use std::os::windows::fs::OpenOptionsExt;
     use windows::Win32::Storage::FileSystem::{
-        CreateFileW, SetFilePointerEx, WriteFile, FILE_BEGIN,
-        FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
-        FILE_FLAG_NO_BUFFERING, FILE_FLAG_WRITE_THROUGH,
+        FILE_FLAG_NO_BUFFERING, FILE_FLAG_WRITE_THROUGH, FILE_SHARE_READ, FILE_SHARE_WRITE,
     };
-    use windows::core::PCWSTR;
 
     let _ = progress_tx.send(FormatProgress::Formatting);
 
     // Calculate partition size (total disk minus the 1MB alignment at start)
-    let partition_size = total_bytes.saturating_sub(PARTITION_START_SECTOR * SECTOR_SIZE as u64);
-    let params = calculate_params(partition_size);
-
-    // Open the physical disk for raw access with proper flags
-    let disk_path: Vec<u16> = format!("\\\\.\\PhysicalDrive{}", disk_number)
-        .encode_utf16()
-        .chain(Some(0))
-        .collect();
-
-    let handle = unsafe {
-        CreateFileW(
-            PCWSTR(disk_path.as_ptr()),
-            (GENERIC_READ.0 | GENERIC_WRITE.0).into(),
-            FILE_SHARE_READ | FILE_SHARE_WRITE,
-            None,
-            OPEN_EXISTING,
-            FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH,
-            None,
-        )
-    }.map_err(|e| format!("Failed to open disk {}: {}", disk_number, e))?;
-
-    // Helper to write a sector at a specific position
-    let write_sector = |h: HANDLE, offset: u64, data: &[u8; 512]| -> Result<(), String> {
-        let mut new_pos = 0i64;
-        unsafe {
-            SetFilePointerEx(h, offset as i64, Some(&mut new_pos), FILE_BEGIN)
-                .map_err(|e| format!("Seek failed: {}", e))?;
-
-            let mut written = 0u32;
-            WriteFile(h, Some(data), Some(&mut written), None)
-                .map_err(|e| format!("Write failed: {}", e))?;
-
-            if written != 512 {
-                return Err(format!("Incomplete write: {} bytes", written));
+    let partition_offset = PARTITION_OFFSET;
+    let partition_size = total_bytes.saturating_sub(partition_offset);
+
+    let label = volume_label.to_string();
+    let tx = progress_tx.clone();
+    tokio::task::spawn_blocking(move || {
+        // Open the physical disk for raw access with proper flags
+        let mut disk = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .share_mode((FILE_SHARE_READ | FILE_SHARE_WRITE).0)
+            .custom_flags((FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH).0)
+            .open(format!("\\\\.\\PhysicalDrive{}", disk_number))
+            .map_err(|e| format!("Failed to open disk {}: {}", disk_number, e))?;
+
+        // The caller reports 70% before formatting and 95% after
+        format_volume(&mut disk, partition_offset, partition_size, &label, &mut |percent| {
+            let _ = tx.send(FormatProgress::Progress { percent: 70 + percent / 4 });
+        })
+    })
+    .await
+    .map_err(|e| format!("Format task failed: {}", e))??;
+
+    let _ = progress_tx.send(FormatProgress::Completed);
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Writing files without mounting
+// -----------------------------------------------------------------------------
+
+/// Where the parts of a FAT32 volume are, from its boot sector
+struct Layout {
+    /// Byte offset of the volume on the disk
+    offset: u64,
+    cluster_bytes: u64,
+    num_fats: u64,
+    fat_start_sector: u64,
+    fat_size_sectors: u64,
+    data_start_sector: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+}
+
+impl Layout {
+    fn sector(&self, sector: u64) -> u64 {
+        self.offset + sector * SECTOR_SIZE as u64
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.sector(self.data_start_sector) + (cluster as u64 - 2) * self.cluster_bytes
+    }
+
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.cluster_count + 2).contains(&cluster)
+    }
+}
+
+fn read_layout<D: Read + Seek>(disk: &mut D, offset: u64) -> Result<Layout, String> {
+    let mut boot = [0u8; 512];
+    disk.seek(SeekFrom::Start(offset))
+        .and_then(|_| disk.read_exact(&mut boot))
+        .map_err(|e| format!("Failed to read boot sector: {}", e))?;
+    let u16_at = |i: usize| u16::from_le_bytes([boot[i], boot[i + 1]]) as u64;
+    let u32_at = |i: usize| u32::from_le_bytes([boot[i], boot[i + 1], boot[i + 2], boot[i + 3]]) as u64;
+    if boot[510..512] != [0x55, 0xAA] || &boot[82..90] != b"FAT32   " || u16_at(11) != SECTOR_SIZE as u64 {
+        return Err("Not a FAT32 volume".to_string());
+    }
+
+    let sectors_per_cluster = boot[13] as u64;
+    let fat_start_sector = u16_at(14);
+    let num_fats = boot[16] as u64;
+    let fat_size_sectors = u32_at(36);
+    if sectors_per_cluster == 0 || fat_size_sectors == 0 || u32_at(44) < 2 {
+        return Err("Unsupported FAT32 layout".to_string());
+    }
+    let data_start_sector = fat_start_sector + num_fats * fat_size_sectors;
+    let data_clusters = u32_at(32).saturating_sub(data_start_sector) / sectors_per_cluster;
+    // Cluster numbers start at 2, and the FAT needs room for every one
+    let cluster_count = data_clusters.min(fat_size_sectors * SECTOR_SIZE as u64 / 4 - 2) as u32;
+    Ok(Layout {
+        offset,
+        cluster_bytes: sectors_per_cluster * SECTOR_SIZE as u64,
+        num_fats,
+        fat_start_sector,
+        fat_size_sectors,
+        data_start_sector,
+        cluster_count,
+        root_cluster: u32_at(44) as u32,
+    })
+}
+
+/// Writes directories and files straight into a FAT32 volume that format_volume just
+/// made, so nothing has to be mounted
+/// Clusters are handed out front to back, so every file ends up contiguous. The FAT
+/// and the directories are kept in memory until flush() or finish() writes them; all
+/// writes are whole sectors, as raw disks require
+pub struct Volume<D: Read + Write + Seek> {
+    disk: D,
+    /// Byte offset of the volume on the disk
+    offset: u64,
+    cluster_bytes: usize,
+    num_fats: u64,
+    fat_start_sector: u64,
+    fat_size_sectors: u64,
+    data_start_sector: u64,
+    cluster_count: u32,
+    /// FAT entries of every cluster up to the first free one
+    fat: Vec<u32>,
+    /// Index 0 is the root directory
+    dirs: Vec<Directory>,
+    /// DOS date and time put on every entry
+    timestamp: (u16, u16),
+}
+
+struct Directory {
+    /// Allocated when the directory is created, so entries can point to it
+    first_cluster: u32,
+    /// The rest of its chain, allocated by flush() as the entries outgrow it
+    more_clusters: Vec<u32>,
+    entries: Vec<[u8; 32]>,
+    /// Lowercased long names, as FAT compares names without case
+    children: HashMap<String, Child>,
+    short_names: HashSet<[u8; 11]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Child {
+    Dir(usize),
+    File,
+}
+
+impl<D: Read + Write + Seek> Volume<D> {
+    /// Open the empty FAT32 volume at `offset` of `disk`
+    pub fn open(mut disk: D, offset: u64) -> Result<Self, String> {
+        let layout = read_layout(&mut disk, offset)?;
+        if layout.root_cluster != 2 {
+            return Err("Unsupported FAT32 layout".to_string());
+        }
+        let fat_start_sector = layout.fat_start_sector;
+        let mut volume = Self {
+            disk,
+            offset,
+            cluster_bytes: layout.cluster_bytes as usize,
+            num_fats: layout.num_fats,
+            fat_start_sector,
+            fat_size_sectors: layout.fat_size_sectors,
+            data_start_sector: layout.data_start_sector,
+            cluster_count: layout.cluster_count,
+            fat: Vec::new(),
+            dirs: Vec::new(),
+            timestamp: dos_timestamp(std::time::SystemTime::now()),
+        };
+
+        // Only the reserved entries and the root directory may be in use
+        let mut fat_sector = [0u8; 512];
+        volume
+            .read_at(volume.sector(fat_start_sector), &mut fat_sector)
+            .map_err(|e| format!("Failed to read FAT: {}", e))?;
+        let entries: Vec<u32> = fat_sector
+            .chunks(4)
+            .map(|e| u32::from_le_bytes([e[0], e[1], e[2], e[3]]) & 0x0FFFFFFF)
+            .collect();
+        if entries[2] < 0x0FFFFFF8 || entries[3..].iter().any(|&e| e != 0) {
+            return Err("The volume is not empty; format it first".to_string());
+        }
+        volume.fat = vec![entries[0], entries[1], END_OF_CHAIN];
+
+        let mut root_cluster = vec![0u8; volume.cluster_bytes];
+        volume
+            .read_at(volume.cluster_offset(2), &mut root_cluster)
+            .map_err(|e| format!("Failed to read root directory: {}", e))?;
+        let mut root = Directory {
+            first_cluster: 2,
+            more_clusters: Vec::new(),
+            entries: Vec::new(),
+            children: HashMap::new(),
+            short_names: HashSet::new(),
+        };
+        for entry in root_cluster.chunks(32).take_while(|e| e[0] != 0) {
+            if entry[11] != ATTR_VOLUME_ID {
+                return Err("The volume is not empty; format it first".to_string());
+            }
+            root.entries.push(entry.try_into().unwrap());
+        }
+        volume.dirs.push(root);
+        Ok(volume)
+    }
+
+    /// Create a directory and any missing parents ('/'-separated, relative to the root)
+    pub fn create_dir_all(&mut self, path: &str) -> std::io::Result<()> {
+        let mut dir = 0;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            dir = match self.dirs[dir].children.get(&name.to_lowercase()) {
+                Some(Child::Dir(index)) => *index,
+                Some(Child::File) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("{} is a file", name),
+                    ))
+                }
+                None => self.add_dir(dir, name)?,
+            };
+        }
+        Ok(())
+    }
+
+    /// Create a file in an existing directory; its data goes through the returned writer
+    pub fn create_file(&mut self, path: &str) -> std::io::Result<FileWriter<'_, D>> {
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let dir = self.find_dir(parent)?;
+        let entry = self.add_entry(dir, name, ATTR_ARCHIVE, 0)?;
+        self.dirs[dir].children.insert(name.to_lowercase(), Child::File);
+        Ok(FileWriter {
+            volume: self,
+            dir,
+            entry,
+            first_cluster: None,
+            last_cluster: None,
+            size: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Create a file in an existing directory with `data` as its contents
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> std::io::Result<()> {
+        let mut file = self.create_file(path)?;
+        file.write_all(data)?;
+        file.close()
+    }
+
+    /// Write the directories, both FATs and the free cluster count. The volume stays
+    /// open, so files can still be added and flushed again
+    pub fn flush(&mut self) -> Result<(), String> {
+        let write_error = |e: std::io::Error| format!("Failed to write volume: {}", e);
+
+        for index in 0..self.dirs.len() {
+            let mut data = self.dirs[index].entries.concat();
+            let clusters = data.len().div_ceil(self.cluster_bytes).max(1);
+            data.resize(clusters * self.cluster_bytes, 0);
+            let allocated = 1 + self.dirs[index].more_clusters.len();
+            if clusters > allocated {
+                let directory = &self.dirs[index];
+                let last = directory.more_clusters.last().copied().unwrap_or(directory.first_cluster);
+                let more = self.allocate(clusters - allocated, Some(last)).map_err(write_error)?;
+                self.dirs[index].more_clusters.extend((0..(clusters - allocated) as u32).map(|i| more + i));
+            }
+            let chain: Vec<u32> = std::iter::once(self.dirs[index].first_cluster)
+                .chain(self.dirs[index].more_clusters.iter().copied())
+                .collect();
+            for (cluster, chunk) in chain.into_iter().zip(data.chunks(self.cluster_bytes)) {
+                self.write_at(self.cluster_offset(cluster), chunk).map_err(write_error)?;
             }
         }
+
+        let mut fat: Vec<u8> = self.fat.iter().flat_map(|entry| entry.to_le_bytes()).collect();
+        fat.resize(fat.len().div_ceil(SECTOR_SIZE as usize) * SECTOR_SIZE as usize, 0);
+        for copy in 0..self.num_fats {
+            let start = self.sector(self.fat_start_sector + copy * self.fat_size_sectors);
+            self.write_at(start, &fat).map_err(write_error)?;
+        }
+
+        let used = self.fat.len() as u32 - 2;
+        let mut fsinfo = create_fsinfo_sector();
+        fsinfo[488..492].copy_from_slice(&(self.cluster_count - used).to_le_bytes());
+        fsinfo[492..496].copy_from_slice(&(self.fat.len() as u32).to_le_bytes());
+        self.write_at(self.sector(1), &fsinfo).map_err(write_error)?;
+        self.write_at(self.sector(7), &fsinfo).map_err(write_error)?;
+
+        self.disk.flush().map_err(write_error)
+    }
+
+    /// Flush the volume and hand back the disk
+    pub fn finish(mut self) -> Result<D, String> {
+        self.flush()?;
+        Ok(self.disk)
+    }
+
+    /// The disk the volume is written to
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+
+    fn sector(&self, sector: u64) -> u64 {
+        self.offset + sector * SECTOR_SIZE as u64
+    }
+
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.sector(self.data_start_sector) + (cluster as u64 - 2) * self.cluster_bytes as u64
+    }
+
+    fn read_at(&mut self, offset: u64, data: &mut [u8]) -> std::io::Result<()> {
+        self.disk.seek(SeekFrom::Start(offset))?;
+        self.disk.read_exact(data)
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        write_at(&mut self.disk, offset, data)
+    }
+
+    /// Take the next `count` free clusters as one chain, appended to the chain ending
+    /// at `after`
+    fn allocate(&mut self, count: usize, after: Option<u32>) -> std::io::Result<u32> {
+        let first = self.fat.len() as u32;
+        if first as u64 + count as u64 > self.cluster_count as u64 + 2 {
+            return Err(std::io::Error::other("not enough space on the volume"));
+        }
+        self.fat.extend((1..count as u32).map(|i| first + i));
+        self.fat.push(END_OF_CHAIN);
+        if let Some(previous) = after {
+            self.fat[previous as usize] = first;
+        }
+        Ok(first)
+    }
+
+    fn find_dir(&self, path: &str) -> std::io::Result<usize> {
+        let mut dir = 0;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            dir = match self.dirs[dir].children.get(&name.to_lowercase()) {
+                Some(Child::Dir(index)) => *index,
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("{} does not exist", path),
+                    ))
+                }
+            };
+        }
+        Ok(dir)
+    }
+
+    fn add_dir(&mut self, parent: usize, name: &str) -> std::io::Result<usize> {
+        let cluster = self.allocate(1, None)?;
+        self.add_entry(parent, name, ATTR_DIRECTORY, cluster)?;
+        // ".." points to cluster 0 when the parent is the root
+        let parent_cluster = if parent == 0 { 0 } else { self.dirs[parent].first_cluster };
+        let dot = short_entry(b".          ", ATTR_DIRECTORY, cluster, self.timestamp);
+        let dot_dot = short_entry(b"..         ", ATTR_DIRECTORY, parent_cluster, self.timestamp);
+        self.dirs.push(Directory {
+            first_cluster: cluster,
+            more_clusters: Vec::new(),
+            entries: vec![dot, dot_dot],
+            children: HashMap::new(),
+            short_names: HashSet::new(),
+        });
+        let index = self.dirs.len() - 1;
+        self.dirs[parent].children.insert(name.to_lowercase(), Child::Dir(index));
+        Ok(index)
+    }
+
+    /// Add the long name and 8.3 entries for `name` to a directory and return the
+    /// index of the 8.3 entry
+    fn add_entry(&mut self, dir: usize, name: &str, attributes: u8, cluster: u32) -> std::io::Result<usize> {
+        let invalid = name.is_empty()
+            || name.ends_with(['.', ' '])
+            || name.encode_utf16().count() > 255
+            || name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c));
+        if invalid {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' is not a valid FAT32 name", name),
+            ));
+        }
+        let timestamp = self.timestamp;
+        let directory = &mut self.dirs[dir];
+        if directory.children.contains_key(&name.to_lowercase()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", name),
+            ));
+        }
+
+        let (short, needs_long_name) = short_name(name, &directory.short_names);
+        let long_entries = if needs_long_name { long_name_entries(name, &short) } else { Vec::new() };
+        if directory.entries.len() + long_entries.len() + 1 > MAX_DIR_ENTRIES {
+            return Err(std::io::Error::other("too many files in one folder"));
+        }
+        directory.short_names.insert(short);
+        directory.entries.extend(long_entries);
+        directory.entries.push(short_entry(&short, attributes, cluster, timestamp));
+        Ok(directory.entries.len() - 1)
+    }
+}
+
+/// Streams a file's data into the volume; close() records its size and must be called
+pub struct FileWriter<'v, D: Read + Write + Seek> {
+    volume: &'v mut Volume<D>,
+    dir: usize,
+    entry: usize,
+    first_cluster: Option<u32>,
+    last_cluster: Option<u32>,
+    size: u64,
+    buffer: Vec<u8>,
+}
+
+impl<D: Read + Write + Seek> FileWriter<'_, D> {
+    /// Write what is left and fill in the directory entry
+    pub fn close(mut self) -> std::io::Result<()> {
+        self.write_clusters(true)?;
+        let cluster = self.first_cluster.unwrap_or(0);
+        let entry = &mut self.volume.dirs[self.dir].entries[self.entry];
+        entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&(self.size as u32).to_le_bytes());
         Ok(())
+    }
+
+    /// Write the buffered data that fills whole clusters, or all of it (padded) when
+    /// the file is complete
+    fn write_clusters(&mut self, last: bool) -> std::io::Result<()> {
+        let cluster_bytes = self.volume.cluster_bytes;
+        let len = if last {
+            self.buffer.len().div_ceil(cluster_bytes) * cluster_bytes
+        } else {
+            self.buffer.len() / cluster_bytes * cluster_bytes
+        };
+        if len == 0 {
+            return Ok(());
+        }
+        self.buffer.resize(len.max(self.buffer.len()), 0);
+        let count = len / cluster_bytes;
+        let first = self.volume.allocate(count, self.last_cluster)?;
+        self.first_cluster.get_or_insert(first);
+        self.last_cluster = Some(first + count as u32 - 1);
+        let offset = self.volume.cluster_offset(first);
+        self.volume.write_at(offset, &self.buffer[..len])?;
+        self.buffer.drain(..len);
+        Ok(())
+    }
+}
+
+impl<D: Read + Write + Seek> Write for FileWriter<'_, D> {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        if self.size + data.len() as u64 > u32::MAX as u64 {
+            return Err(std::io::Error::other("FAT32 can't store files of 4GB or more"));
+        }
+        self.buffer.extend_from_slice(data);
+        self.size += data.len() as u64;
+        if self.buffer.len() >= WRITE_CHUNK {
+            self.write_clusters(false)?;
+        }
+        Ok(data.len())
+    }
+
+    /// Data only reaches the volume in whole clusters; close() writes the rest
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The 8.3 name stored for `name` and whether a long name entry has to go with it
+/// Names that already are upper case 8.3 are kept; others get a "BASIS~N.EXT" that
+/// isn't in `taken`
+fn short_name(name: &str, taken: &HashSet<[u8; 11]>) -> ([u8; 11], bool) {
+    let valid = |c: char| c.is_ascii_uppercase() || c.is_ascii_digit() || (c.is_ascii() && SHORT_NAME_CHARS.contains(&(c as u8)));
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
     };
 
-    // Calculate the byte offset where the partition starts
-    let partition_offset = PARTITION_START_SECTOR * SECTOR_SIZE as u64;
+    if (1..=8).contains(&base.len()) && ext.len() <= 3 && base.chars().chain(ext.chars()).all(valid) {
+        let mut short = [b' '; 11];
+        short[..base.len()].copy_from_slice(base.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+        if !taken.contains(&short) {
+            return (short, false);
+        }
+    }
 
-    // Write boot sector at partition start
-    let boot_sector = create_boot_sector(&params, volume_label);
-    write_sector(handle, partition_offset, &boot_sector)
-        .map_err(|e| format!("Failed to write boot sector: {}", e))?;
+    let clean = |part: &str, max: usize| -> Vec<u8> {
+        part.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| c.to_ascii_uppercase())
+            .map(|c| if valid(c) { c as u8 } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let mut base = clean(base, 8);
+    let ext = clean(ext, 3);
+    if base.is_empty() {
+        base.push(b'_');
+    }
 
-    // Write FSInfo sector
-    let fsinfo = create_fsinfo_sector();
-    write_sector(handle, partition_offset + SECTOR_SIZE as u64, &fsinfo)
-        .map_err(|e| format!("Failed to write FSInfo: {}", e))?;
+    let mut n = 1u32;
+    loop {
+        let tail = format!("~{}", n);
+        let keep = base.len().min(8 - tail.len());
+        let mut short = [b' '; 11];
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(&ext);
+        if !taken.contains(&short) {
+            return (short, true);
+        }
+        n += 1;
+    }
+}
+
+/// Long name entries for `name`, in the order they are stored (last part first)
+fn long_name_entries(name: &str, short: &[u8; 11]) -> Vec<[u8; 32]> {
+    let checksum = short.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    // Terminated and padded unless it fills the last entry exactly
+    if !units.len().is_multiple_of(13) {
+        units.push(0);
+        units.resize(units.len().div_ceil(13) * 13, 0xFFFF);
+    }
 
-    // Write backup boot sector at sector 6
-    write_sector(handle, partition_offset + 6 * SECTOR_SIZE as u64, &boot_sector)
-        .map_err(|e| format!("Failed to write backup boot sector: {}", e))?;
+    let count = units.len() / 13;
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut entry = [0u8; 32];
+            entry[0] = (i + 1) as u8 | if i + 1 == count { 0x40 } else { 0 };
+            entry[11] = ATTR_LONG_NAME;
+            entry[13] = checksum;
+            for (j, unit) in units[i * 13..(i + 1) * 13].iter().enumerate() {
+                let pos = match j {
+                    0..=4 => 1 + j * 2,
+                    5..=10 => 14 + (j - 5) * 2,
+                    _ => 28 + (j - 11) * 2,
+                };
+                entry[pos..pos + 2].copy_from_slice(&unit.to_le_bytes());
+            }
+            entry
+        })
+        .collect()
+}
 
-    // Write backup FSInfo at sector 7
-    write_sector(handle, partition_offset + 7 * SECTOR_SIZE as u64, &fsinfo)
-        .map_err(|e| format!("Failed to write backup FSInfo: {}", e))?;
+fn short_entry(short: &[u8; 11], attributes: u8, cluster: u32, (date, time): (u16, u16)) -> [u8; 32] {
+    let mut entry = [0u8; 32];
+    entry[0..11].copy_from_slice(short);
+    entry[11] = attributes;
+    // Created, last accessed and modified
+    entry[14..16].copy_from_slice(&time.to_le_bytes());
+    entry[16..18].copy_from_slice(&date.to_le_bytes());
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+    entry
+}
 
-    // Write FAT tables
-    let fat_start = partition_offset + RESERVED_SECTORS as u64 * SECTOR_SIZE as u64;
-    let fat_first_sector = create_fat_sector_with_entries();
-    let zero_sector = [0u8; 512];
+/// DOS date and time (UTC) as FAT stores them; FAT can't go back before 1980
+fn dos_timestamp(time: std::time::SystemTime) -> (u16, u16) {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Days since 1970-01-01 to a calendar date (Howard Hinnant's civil_from_days)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let date = (((year.min(2107) - 1980) << 9) | (month << 5) | day) as u16;
+    let time = (((secs / 3600) << 11) | ((secs / 60 % 60) << 5) | (secs % 60 / 2)) as u16;
+    (date, time)
+}
 
-    // First FAT - first sector with entries
-    write_sector(handle, fat_start, &fat_first_sector)
-        .map_err(|e| format!("Failed to write FAT1: {}", e))?;
+// -----------------------------------------------------------------------------
+// Reading files back without mounting
+// -----------------------------------------------------------------------------
+
+/// Reads files from a FAT32 volume without mounting it, to check what Volume wrote
+/// Everything is read from the disk as it is, so a card that lost data shows it;
+/// broken chains and sizes that don't fit them are InvalidData errors
+pub struct VolumeReader<D: Read + Seek> {
+    disk: D,
+    layout: Layout,
+    /// The FAT sector read last, by number
+    fat_sector: Option<(u64, [u8; 512])>,
+}
 
-    // Clear a few more sectors of FAT1
-    for i in 1..16.min(params.fat_size_sectors) {
-        write_sector(handle, fat_start + i as u64 * SECTOR_SIZE as u64, &zero_sector)
-            .map_err(|e| format!("Failed to clear FAT1: {}", e))?;
+/// A directory entry that was looked up
+struct Entry {
+    attributes: u8,
+    cluster: u32,
+    size: u32,
+}
+
+impl<D: Read + Seek> VolumeReader<D> {
+    /// Open the FAT32 volume at `offset` of `disk`
+    pub fn open(mut disk: D, offset: u64) -> Result<Self, String> {
+        let layout = read_layout(&mut disk, offset)?;
+        Ok(Self { disk, layout, fat_sector: None })
     }
 
-    // Second FAT
-    let fat2_start = fat_start + params.fat_size_sectors as u64 * SECTOR_SIZE as u64;
-    write_sector(handle, fat2_start, &fat_first_sector)
-        .map_err(|e| format!("Failed to write FAT2: {}", e))?;
+    /// Open the file at `path` ('/'-separated, case doesn't matter); None if there is
+    /// no such file
+    pub fn open_file(&mut self, path: &str) -> std::io::Result<Option<VolumeFile<'_, D>>> {
+        let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+        let mut dir = self.layout.root_cluster;
+        while let Some(name) = names.next() {
+            let Some(entry) = self.find_entry(dir, name)? else {
+                return Ok(None);
+            };
+            let is_dir = entry.attributes & ATTR_DIRECTORY != 0;
+            if names.peek().is_some() && is_dir {
+                dir = entry.cluster;
+            } else if names.peek().is_none() && !is_dir {
+                let runs = self.runs(entry.cluster, entry.size as u64)?;
+                return Ok(Some(VolumeFile { volume: self, runs, position: 0, remaining: entry.size as u64 }));
+            } else {
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
 
-    // Clear a few more sectors of FAT2
-    for i in 1..16.min(params.fat_size_sectors) {
-        write_sector(handle, fat2_start + i as u64 * SECTOR_SIZE as u64, &zero_sector)
-            .map_err(|e| format!("Failed to clear FAT2: {}", e))?;
+    fn find_entry(&mut self, dir_cluster: u32, name: &str) -> std::io::Result<Option<Entry>> {
+        let wanted = name.to_lowercase();
+        let mut data = vec![0u8; self.layout.cluster_bytes as usize];
+        // Long name parts come before their 8.3 entry, last part first
+        let mut long_name: Vec<u16> = Vec::new();
+        for cluster in self.chain(dir_cluster)? {
+            self.disk.seek(SeekFrom::Start(self.layout.cluster_offset(cluster)))?;
+            self.disk.read_exact(&mut data)?;
+            for entry in data.chunks(32) {
+                match entry[0] {
+                    0 => return Ok(None),
+                    0xE5 => {
+                        long_name.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+                if entry[11] == ATTR_LONG_NAME {
+                    let part = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30].map(|i| u16::from_le_bytes([entry[i], entry[i + 1]]));
+                    long_name.splice(0..0, part);
+                    continue;
+                }
+                let entry_name = if long_name.is_empty() {
+                    short_name_text(entry[..11].try_into().unwrap())
+                } else {
+                    let end = long_name.iter().position(|&unit| unit == 0).unwrap_or(long_name.len());
+                    String::from_utf16_lossy(&long_name[..end])
+                };
+                long_name.clear();
+                if entry[11] & ATTR_VOLUME_ID == 0 && entry_name.to_lowercase() == wanted {
+                    let u16_at = |i: usize| u16::from_le_bytes([entry[i], entry[i + 1]]) as u32;
+                    return Ok(Some(Entry {
+                        attributes: entry[11],
+                        cluster: (u16_at(20) << 16) | u16_at(26),
+                        size: u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]),
+                    }));
+                }
+            }
+        }
+        Ok(None)
     }
 
-    // Write root directory cluster
-    let data_start = fat_start + (NUM_FATS as u64 * params.fat_size_sectors as u64 * SECTOR_SIZE as u64);
+    /// The clusters of the chain starting at `first`
+    fn chain(&mut self, first: u32) -> std::io::Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster < 0x0FFFFFF8 {
+            // A chain longer than the volume has clusters loops
+            if !self.layout.is_cluster(cluster) || clusters.len() > self.layout.cluster_count as usize {
+                return Err(broken("broken cluster chain"));
+            }
+            clusters.push(cluster);
+            cluster = self.next_cluster(cluster)?;
+        }
+        Ok(clusters)
+    }
 
-    // Create root directory sector with volume label
-    let mut root_sector = [0u8; 512];
-    let mut label_bytes = [0x20u8; 11];
-    let label_src = volume_label.as_bytes();
-    let copy_len = label_src.len().min(11);
-    label_bytes[..copy_len].copy_from_slice(&label_src[..copy_len]);
-    root_sector[0..11].copy_from_slice(&label_bytes);
-    root_sector[11] = 0x08; // Volume label attribute
+    /// The chain of a file of `size` bytes as runs of consecutive clusters
+    fn runs(&mut self, first: u32, size: u64) -> std::io::Result<Vec<(u32, u32)>> {
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+        let clusters = self.chain(first)?;
+        if clusters.len() as u64 != size.div_ceil(self.layout.cluster_bytes) {
+            return Err(broken("file size doesn't match its clusters"));
+        }
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for cluster in clusters {
+            match runs.last_mut() {
+                Some((start, count)) if *start + *count == cluster => *count += 1,
+                _ => runs.push((cluster, 1)),
+            }
+        }
+        Ok(runs)
+    }
 
-    write_sector(handle, data_start, &root_sector)
-        .map_err(|e| format!("Failed to write root directory: {}", e))?;
+    fn next_cluster(&mut self, cluster: u32) -> std::io::Result<u32> {
+        let byte = cluster as u64 * 4;
+        let sector = self.layout.fat_start_sector + byte / SECTOR_SIZE as u64;
+        if self.fat_sector.is_none_or(|(cached, _)| cached != sector) {
+            let mut data = [0u8; 512];
+            self.disk.seek(SeekFrom::Start(self.layout.sector(sector)))?;
+            self.disk.read_exact(&mut data)?;
+            self.fat_sector = Some((sector, data));
+        }
+        let (_, data) = self.fat_sector.as_ref().unwrap();
+        let i = (byte % SECTOR_SIZE as u64) as usize;
+        Ok(u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) & 0x0FFFFFFF)
+    }
+}
+
+/// Reads one file of a VolumeReader
+pub struct VolumeFile<'v, D: Read + Seek> {
+    volume: &'v mut VolumeReader<D>,
+    /// Runs of consecutive clusters not read completely yet: (first cluster, count)
+    runs: Vec<(u32, u32)>,
+    /// Bytes read from the first run
+    position: u64,
+    remaining: u64,
+}
 
-    // Clear rest of root directory cluster
-    let sectors_per_cluster = params.sectors_per_cluster as u32;
-    for i in 1..sectors_per_cluster {
-        write_sector(handle, data_start + i as u64 * SECTOR_SIZE as u64, &zero_sector)
-            .map_err(|e| format!("Failed to clear root directory: {}", e))?;
+impl<D: Read + Seek> Read for VolumeFile<'_, D> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(&(first, count)) = self.runs.first() else {
+            return Ok(0);
+        };
+        let run_bytes = count as u64 * self.volume.layout.cluster_bytes;
+        let len = (buf.len() as u64).min(run_bytes - self.position).min(self.remaining) as usize;
+        let offset = self.volume.layout.cluster_offset(first) + self.position;
+        self.volume.disk.seek(SeekFrom::Start(offset))?;
+        self.volume.disk.read_exact(&mut buf[..len])?;
+
+        self.position += len as u64;
+        self.remaining -= len as u64;
+        if self.position == run_bytes || self.remaining == 0 {
+            self.runs.remove(0);
+            self.position = 0;
+        }
+        Ok(len)
     }
+}
 
-    // Close handle
-    unsafe { let _ = CloseHandle(handle); }
+fn broken(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
 
-    let _ = progress_tx.send(FormatProgress::Completed);
-    Ok(())
+/// "NAME    EXT" as "NAME.EXT"
+fn short_name_text(short: &[u8; 11]) -> String {
+    let part = |bytes: &[u8]| String::from_utf8_lossy(bytes).trim_end().to_string();
+    let (base, extension) = (part(&short[..8]), part(&short[8..]));
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{}.{}", base, extension)
+    }
 }
 
-#[cfg(not(windows))]
-#[allow(dead_code)]
-pub async fn format_fat32_large(
-    _disk_number: u32,
-    _volume_label: &str,
-    _total_bytes: u64,
-    progress_tx: mpsc::UnboundedSender<FormatProgress>,
-) -> Result<(), String> {
-    let _ = progress_tx.send(FormatProgress::Formatting);
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-    let _ = progress_tx.send(FormatProgress::Completed);
-    eprintln!("Warning: FAT32 format simulation - not running on Windows.");
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, SeekFrom};
+
+    #[test]
+    fn test_format_disk_image() {
+        let disk_bytes = 64 * 1024 * 1024;
+        let mut image = tempfile::tempfile().unwrap();
+        image.set_len(disk_bytes).unwrap();
+        // Leftovers of an older filesystem that must not show through
+        write_at(&mut image, 3 * 1024 * 1024, &[0xAB; 4096]).unwrap();
+
+        let mut percents = Vec::new();
+        format_disk(&mut image, disk_bytes, "NEXTUI", &mut |p| percents.push(p)).unwrap();
+        assert_eq!(percents.last(), Some(&100));
+
+        let mut mbr = [0u8; 512];
+        image.seek(SeekFrom::Start(0)).unwrap();
+        image.read_exact(&mut mbr).unwrap();
+        assert_eq!(&mbr[510..], &[0x55, 0xAA]);
+        assert_eq!(mbr[446 + 4], PARTITION_TYPE_FAT32_LBA);
+        assert_eq!(u32::from_le_bytes(mbr[454..458].try_into().unwrap()), 2048);
+        let partition_sectors = u32::from_le_bytes(mbr[458..462].try_into().unwrap()) as u64;
+        assert_eq!(partition_sectors, disk_bytes / 512 - 2048);
+
+        // The partition mounts as FAT32 with the label and takes files
+        let mut volume = vec![0u8; (partition_sectors * 512) as usize];
+        image.seek(SeekFrom::Start(2048 * 512)).unwrap();
+        image.read_exact(&mut volume).unwrap();
+        let fs = fatfs::FileSystem::new(std::io::Cursor::new(volume), fatfs::FsOptions::new()).unwrap();
+        assert_eq!(fs.fat_type(), fatfs::FatType::Fat32);
+        assert_eq!(fs.volume_label().trim_end(), "NEXTUI");
+        let root = fs.root_dir();
+        assert_eq!(root.iter().count(), 0);
+        root.create_dir(".system").unwrap();
+        root.create_file(".system/version.txt").unwrap().write_all(b"v6.2.0").unwrap();
+        let mut contents = String::new();
+        root.open_file(".system/version.txt").unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "v6.2.0");
+        let stats = fs.stats().unwrap();
+        assert!(stats.free_clusters() > 120_000, "{}", stats.free_clusters());
+
+        // Too small to hold the minimum number of FAT32 clusters
+        let mut small = std::io::Cursor::new(vec![0u8; 16 * 1024 * 1024]);
+        assert!(format_disk(&mut small, 16 * 1024 * 1024, "NEXTUI", &mut |_| {}).is_err());
+        assert!(small.get_ref().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_volume_writer() {
+        let volume_bytes = 48 * 1024 * 1024;
+        let mut disk = std::io::Cursor::new(vec![0u8; volume_bytes as usize]);
+        format_volume(&mut disk, 0, volume_bytes, "NEXTUI", &mut |_| {}).unwrap();
+
+        // Bigger than one write chunk, so the file's clusters are allocated in runs
+        let big: Vec<u8> = (0..3 * 1024 * 1024 + 100).map(|i| (i % 251) as u8).collect();
+        let mut volume = Volume::open(disk, 0).unwrap();
+        volume.create_dir_all(".system/bin/").unwrap();
+        volume.write_file(".system/bin/big.elf", &big).unwrap();
+        volume.write_file("MINUI.ZIP", b"minui").unwrap();
+        volume.write_file("empty.txt", b"").unwrap();
+        // Enough long names that share a short name to fill several directory clusters
+        volume.create_dir_all("Roms/Game Boy Advance (GBA)").unwrap();
+        for i in 0..40 {
+            volume.write_file(&format!("Roms/Game Boy Advance (GBA)/Long Game Name {}.gba", i), &[i as u8]).unwrap();
+            // Flushing halfway doesn't stop the folder from growing afterwards
+            if i == 20 {
+                volume.flush().unwrap();
+            }
+        }
+        assert_eq!(volume.write_file("minui.zip", b"again").unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(volume.write_file("Missing/file.txt", b"").unwrap_err().kind(), std::io::ErrorKind::NotFound);
+        assert!(volume.write_file("bad:name.txt", b"").is_err());
+        let mut disk = volume.finish().unwrap();
+        disk.set_position(0);
+
+        // A volume that already has files can't be written to again
+        assert!(Volume::open(std::io::Cursor::new(disk.get_ref().clone()), 0).is_err());
+
+        let fs = fatfs::FileSystem::new(disk, fatfs::FsOptions::new()).unwrap();
+        let root = fs.root_dir();
+        let read = |path: &str| {
+            let mut data = Vec::new();
+            root.open_file(path).unwrap().read_to_end(&mut data).unwrap();
+            data
+        };
+        assert_eq!(read(".system/bin/big.elf"), big);
+        assert_eq!(read("MINUI.ZIP"), b"minui");
+        assert_eq!(read("empty.txt"), b"");
+        let names: Vec<String> = root.iter().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, vec![".system", "MINUI.ZIP", "empty.txt", "Roms"]);
+        let roms = root.open_dir("Roms/Game Boy Advance (GBA)").unwrap();
+        let games: Vec<_> = roms.iter().map(|e| e.unwrap()).filter(|e| e.is_file()).collect();
+        assert_eq!(games.len(), 40);
+        assert_eq!(games[39].file_name(), "Long Game Name 39.gba");
+        assert_eq!(read("Roms/Game Boy Advance (GBA)/Long Game Name 39.gba"), vec![39]);
+        // FSInfo agrees with the FAT
+        let big_clusters = big.len().div_ceil(512) as u32;
+        assert!(fs.stats().unwrap().free_clusters() < fs.stats().unwrap().total_clusters() - big_clusters);
+
+        // 2024-02-29 12:34:56 UTC
+        let leap_day = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1709210096);
+        assert_eq!(dos_timestamp(leap_day), ((44 << 9) | (2 << 5) | 29, (12 << 11) | (34 << 5) | 28));
+    }
+
+    #[test]
+    fn test_volume_reader() {
+        let volume_bytes = 48 * 1024 * 1024;
+        let mut disk = std::io::Cursor::new(vec![0u8; volume_bytes as usize]);
+        format_volume(&mut disk, 0, volume_bytes, "NEXTUI", &mut |_| {}).unwrap();
+
+        let big: Vec<u8> = (0..2 * 1024 * 1024 + 7).map(|i| (i % 241) as u8).collect();
+        let mut volume = Volume::open(disk, 0).unwrap();
+        volume.create_dir_all(".system/bin").unwrap();
+        // The directory is written between the file's runs, so the file isn't one run
+        let mut file = volume.create_file(".system/bin/big.elf").unwrap();
+        file.write_all(&big[..WRITE_CHUNK]).unwrap();
+        file.volume.create_dir_all("Roms").unwrap();
+        file.write_all(&big[WRITE_CHUNK..]).unwrap();
+        file.close().unwrap();
+        volume.write_file("MINUI.ZIP", b"minui").unwrap();
+        volume.write_file("Roms/Long Game Name.gba", b"rom").unwrap();
+        volume.write_file("empty.txt", b"").unwrap();
+        let disk = volume.finish().unwrap();
+
+        let mut reader = VolumeReader::open(disk, 0).unwrap();
+        let mut read = |path: &str| {
+            let mut data = Vec::new();
+            reader.open_file(path).unwrap().map(|mut file| file.read_to_end(&mut data).unwrap()).map(|_| data)
+        };
+        assert_eq!(read(".system/bin/big.elf").unwrap(), big);
+        assert_eq!(read("minui.zip").unwrap(), b"minui");
+        assert_eq!(read("ROMS/long game name.gba").unwrap(), b"rom");
+        assert_eq!(read("empty.txt").unwrap(), b"");
+        assert_eq!(read("Roms/missing.gba"), None);
+        assert_eq!(read("Roms"), None);
+        assert_eq!(read("MINUI.ZIP/file"), None);
+
+        // A chain cut short no longer holds the file's size
+        let system = reader.find_entry(2, ".system").unwrap().unwrap().cluster;
+        let bin = reader.find_entry(system, "bin").unwrap().unwrap().cluster;
+        let big_first = reader.find_entry(bin, "big.elf").unwrap().unwrap().cluster;
+        let fat_entry = reader.layout.sector(reader.layout.fat_start_sector) + big_first as u64 * 4;
+        let mut disk = reader.disk;
+        disk.seek(SeekFrom::Start(fat_entry)).unwrap();
+        disk.write_all(&END_OF_CHAIN.to_le_bytes()).unwrap();
+        let mut reader = VolumeReader::open(disk, 0).unwrap();
+        assert_eq!(reader.open_file(".system/bin/big.elf").err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    }
 }
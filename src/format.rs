@@ -359,13 +359,24 @@ async fn lock_and_dismount_volume(drive_letter: char) {
 // Linux Implementation
 // =============================================================================
 
+/// ioctl that makes the kernel re-read a disk's partition table (not exported by libc)
 #[cfg(target_os = "linux")]
-pub async fn format_drive_fat32(
+const BLKRRPART: u32 = 0x125F;
+
+/// Format a drive to FAT32 with MBR partition table (Linux) and return its empty
+/// volume, for writing the release straight into it (see fat32::Volume)
+/// The device stays open exclusively until finish_card(), so nothing can mount the
+/// card while it is being written; the kernel only learns about the new partition then
+#[cfg(target_os = "linux")]
+pub async fn format_card(
     device_path: &str,
     volume_label: &str,
     progress_tx: mpsc::UnboundedSender<FormatProgress>,
     cancel_token: CancellationToken,
-) -> Result<(), String> {
+) -> Result<crate::fat32::Volume<std::fs::File>, String> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::fs::OpenOptionsExt;
+
     crate::debug::log_section("Linux Format Operation");
     crate::debug::log(&format!("Device path: {}", device_path));
     crate::debug::log(&format!("Volume label: {}", volume_label));
@@ -394,100 +405,84 @@ pub async fn format_drive_fat32(
 
     let _ = progress_tx.send(FormatProgress::CleaningDisk);
     let _ = progress_tx.send(FormatProgress::Progress { percent: 20 });
-    crate::debug::log("Creating msdos partition table with parted...");
 
-    // Create a new partition table and partition using parted
-    // First, create a new msdos partition table
-    let output = Command::new("parted")
-        .args(["-s", device_path, "mklabel", "msdos"])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to run parted: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        crate::debug::log(&format!("Parted mklabel failed: {}", stderr));
-        return Err(format!("Failed to create partition table: {}", stderr));
-    }
-    crate::debug::log("Partition table created");
+    // Exclusive access fails if anything still has the device or a partition mounted
+    let mut disk = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_EXCL)
+        .open(device_path)
+        .map_err(|e| format!("Failed to open {}: {}", device_path, e))?;
+    let disk_bytes = disk
+        .seek(SeekFrom::End(0))
+        .map_err(|e| format!("Failed to get size of {}: {}", device_path, e))?;
+    crate::debug::log(&format!("Device size: {} bytes", disk_bytes));
 
     let _ = progress_tx.send(FormatProgress::CreatingPartition);
-    let _ = progress_tx.send(FormatProgress::Progress { percent: 40 });
-    crate::debug::log("Creating primary partition...");
-
-    // Create a primary partition spanning the entire disk
-    let output = Command::new("parted")
-        .args([
-            "-s", device_path, "mkpart", "primary", "fat32", "1MiB", "100%",
-        ])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to create partition: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        crate::debug::log(&format!("Parted mkpart failed: {}", stderr));
-        return Err(format!("Failed to create partition: {}", stderr));
-    }
-    crate::debug::log("Primary partition created");
-
-    let _ = progress_tx.send(FormatProgress::Progress { percent: 50 });
-
-    // Set the partition as bootable
-    crate::debug::log("Setting boot flag...");
-    let _ = Command::new("parted")
-        .args(["-s", device_path, "set", "1", "boot", "on"])
-        .output()
-        .await;
-
-    // Wait for the kernel to recognize the new partition
-    crate::debug::log("Running partprobe...");
-    let _ = Command::new("partprobe")
-        .args([device_path])
-        .output()
-        .await;
-
-    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
-
-    // Check for cancellation before format
-    if cancel_token.is_cancelled() {
-        let _ = progress_tx.send(FormatProgress::Cancelled);
-        return Err("Format cancelled".to_string());
-    }
-
+    let _ = progress_tx.send(FormatProgress::Progress { percent: 30 });
     let _ = progress_tx.send(FormatProgress::Formatting);
-    let _ = progress_tx.send(FormatProgress::Progress { percent: 60 });
-
-    // Determine the partition path (e.g., /dev/sdb1 or /dev/mmcblk0p1)
-    let partition_path = if device_path.contains("mmcblk") || device_path.contains("nvme") {
-        format!("{}p1", device_path)
-    } else {
-        format!("{}1", device_path)
-    };
-    crate::debug::log(&format!("Partition path: {}", partition_path));
-
-    // Format the partition as FAT32
-    crate::debug::log("Running mkfs.vfat...");
-    let output = Command::new("mkfs.vfat")
-        .args(["-F", "32", "-n", volume_label, &partition_path])
-        .output()
-        .await
-        .map_err(|e| format!("Failed to run mkfs.vfat: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        crate::debug::log(&format!("mkfs.vfat failed: {}", stderr));
-        return Err(format!("Failed to format partition: {}", stderr));
-    }
+    crate::debug::log("Writing partition table and FAT32 filesystem...");
+
+    // Partition table and filesystem are written directly, no parted or mkfs.vfat needed
+    let label = volume_label.to_string();
+    let tx = progress_tx.clone();
+    let volume = tokio::task::spawn_blocking(move || {
+        crate::fat32::format_disk(&mut disk, disk_bytes, &label, &mut |percent| {
+            let _ = tx.send(FormatProgress::Progress { percent: 30 + (percent as u16 * 7 / 10) as u8 });
+        })?;
+        crate::fat32::Volume::open(disk, crate::fat32::PARTITION_OFFSET)
+    })
+    .await
+    .map_err(|e| format!("Format task failed: {}", e))??;
 
     let _ = progress_tx.send(FormatProgress::Progress { percent: 100 });
     crate::debug::log("Linux format operation completed successfully");
     let _ = progress_tx.send(FormatProgress::Completed);
+    Ok(volume)
+}
+
+/// Write out what `volume` holds so far and flush the card, keeping the volume open so
+/// files can still be added once the card has been read back
+#[cfg(target_os = "linux")]
+pub async fn flush_card(
+    mut volume: crate::fat32::Volume<std::fs::File>,
+) -> Result<crate::fat32::Volume<std::fs::File>, String> {
+    tokio::task::spawn_blocking(move || {
+        volume.flush()?;
+        volume.disk().sync_all().map_err(|e| format!("Failed to flush device: {}", e))?;
+        Ok(volume)
+    })
+    .await
+    .map_err(|e| format!("Card task failed: {}", e))?
+}
+
+/// Write out what `volume` still holds, flush the card and have the kernel pick up
+/// the new partition
+#[cfg(target_os = "linux")]
+pub async fn finish_card(volume: crate::fat32::Volume<std::fs::File>) -> Result<(), String> {
+    use std::os::unix::io::AsRawFd;
+
+    tokio::task::spawn_blocking(move || {
+        let disk = volume.finish()?;
+        disk.sync_all()
+            .map_err(|e| format!("Failed to flush device: {}", e))?;
+
+        if unsafe { libc::ioctl(disk.as_raw_fd(), BLKRRPART as _) } != 0 {
+            crate::debug::log(&format!(
+                "Re-reading partition table failed: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok::<(), String>(())
+    })
+    .await
+    .map_err(|e| format!("Card task failed: {}", e))??;
+    crate::debug::log("Card written and flushed");
     Ok(())
 }
 
 #[cfg(target_os = "linux")]
-async fn unmount_linux_device(device_path: &str) -> Result<(), String> {
+pub async fn unmount_linux_device(device_path: &str) -> Result<(), String> {
     // Read /proc/mounts to find all mount points for this device
     let mounts = std::fs::read_to_string("/proc/mounts").unwrap_or_default();
 
@@ -1,30 +1,77 @@
-use crate::config::{ASSET_EXTENSION, USER_AGENT};
+use crate::config::{ASSET_EXTENSION, GITHUB_TOKEN, USER_AGENT};
+use crate::retry::{classify_request_error, is_transient_status, retry, Failure, RetryNotice, RetryPolicy};
 use futures_util::StreamExt;
-use serde::Deserialize;
-use std::path::Path;
-use tokio::fs::File;
+use serde::de::DeserializeOwned;
+use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 
-#[derive(Debug, Deserialize)]
+/// Environment variables checked for a GitHub token, in order
+const TOKEN_ENV_VARS: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+lazy_static::lazy_static! {
+    /// Token entered in the UI; takes precedence over the environment and config
+    static ref TOKEN_OVERRIDE: Mutex<Option<String>> = Mutex::new(None);
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct Release {
     pub tag_name: String,
     #[allow(dead_code)]
     pub name: Option<String>,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub published_at: Option<String>,
     pub assets: Vec<Asset>,
 }
 
+impl Release {
+    /// Publish date as YYYY-MM-DD (GitHub returns a full RFC 3339 timestamp)
+    pub fn published_date(&self) -> &str {
+        self.published_at
+            .as_deref()
+            .map(|date| date.split('T').next().unwrap_or(date))
+            .unwrap_or("unknown date")
+    }
+
+    /// One-line description for version pickers, e.g. "v6.3.0 (2025-01-31, 98.2 MB)"
+    pub fn summary(&self) -> String {
+        let mut details = vec![self.published_date().to_string()];
+        if let Some(asset) = find_release_asset(self) {
+            details.push(format!("{:.1} MB", asset.size as f64 / 1_048_576.0));
+        }
+        if self.prerelease {
+            details.push("pre-release".to_string());
+        }
+        format!("{} ({})", self.tag_name, details.join(", "))
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Asset {
     pub name: String,
     pub size: u64,
     pub browser_download_url: String,
+    /// "sha256:<hex>", published by GitHub for newer uploads
+    #[serde(default)]
+    pub digest: Option<String>,
 }
 
 #[derive(Debug)]
 pub enum DownloadProgress {
-    Started { total_bytes: u64 },
+    /// `resumed_from` is non-zero when a partial download is being continued;
+    /// `attempt` counts from 1 and goes up when a failed attempt is retried
+    Started { total_bytes: u64, resumed_from: u64, attempt: u32 },
+    /// The last attempt failed with a network error; waiting before the next one
+    Retrying(RetryNotice),
     Progress { downloaded: u64, total: u64 },
     Completed,
     Cancelled,
@@ -32,44 +79,226 @@ pub enum DownloadProgress {
     Error(String),
 }
 
-pub async fn get_latest_release(repo_url: &str) -> Result<Release, String> {
-    let (owner, repo) = parse_github_url(repo_url)?;
-    let api_url = format!("https://api.github.com/repos/{}/{}/releases/latest", owner, repo);
+/// Use `token` for GitHub API requests instead of the environment/config one (None to go back)
+pub fn set_api_token(token: Option<String>) {
+    let token = token.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+    *TOKEN_OVERRIDE.lock().unwrap() = token;
+}
+
+/// The GitHub token to send, if any: UI override, then $GITHUB_TOKEN/$GH_TOKEN, then config
+/// Unauthenticated clients get 60 API requests an hour, authenticated ones 5000
+pub fn api_token() -> Option<String> {
+    if let Some(token) = TOKEN_OVERRIDE.lock().unwrap().clone() {
+        return Some(token);
+    }
+    TOKEN_ENV_VARS
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .chain(std::iter::once(GITHUB_TOKEN.to_string()))
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty())
+}
+
+/// Rate limit headers of an API response
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimit {
+    /// X-RateLimit-Remaining
+    pub remaining: Option<u64>,
+    /// X-RateLimit-Reset (when the quota refills)
+    pub reset: Option<SystemTime>,
+    /// Retry-After, sent with 429s and secondary rate limits
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Self {
+        let number = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+        };
+        Self {
+            remaining: number("x-ratelimit-remaining"),
+            reset: number("x-ratelimit-reset").map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            retry_after: number("retry-after").map(Duration::from_secs),
+        }
+    }
+
+    /// Whether a 403/429 with these headers is a rate limit rather than a permission problem
+    pub fn is_limited(&self, status: StatusCode) -> bool {
+        status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN && (self.remaining == Some(0) || self.retry_after.is_some()))
+    }
 
+    /// When requests are allowed again; Retry-After wins over the quota reset
+    pub fn retry_at(&self, now: SystemTime) -> Option<SystemTime> {
+        self.retry_after.map(|wait| now + wait).or(self.reset)
+    }
+}
+
+/// "in 12 minutes (at 14:32 UTC)"
+fn describe_retry_time(at: SystemTime, now: SystemTime) -> String {
+    let wait = at.duration_since(now).unwrap_or_default().as_secs();
+    let relative = match wait.div_ceil(60) {
+        _ if wait < 60 => "in under a minute".to_string(),
+        1 => "in 1 minute".to_string(),
+        minutes => format!("in {} minutes", minutes),
+    };
+    let secs_of_day = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86_400;
+    format!("{} (at {:02}:{:02} UTC)", relative, secs_of_day / 3600, secs_of_day % 3600 / 60)
+}
+
+/// Explain a failed API response, telling rate limits apart from other 401/403 causes
+fn describe_api_error(status: StatusCode, rate_limit: &RateLimit, body: &str, service: &str, has_token: bool) -> String {
+    // GitHub and Gitea put the reason in {"message": "..."}
+    let message = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|v| v.get("message").and_then(|m| m.as_str()).map(String::from));
+
+    if rate_limit.is_limited(status) {
+        let mut text = format!("{} API rate limit exceeded.", service);
+        match rate_limit.retry_at(SystemTime::now()) {
+            Some(at) => text.push_str(&format!(" Try again {}.", describe_retry_time(at, SystemTime::now()))),
+            None => text.push_str(" Please wait a while and try again."),
+        }
+        if !has_token && service == "GitHub" {
+            text.push_str(" Adding a GitHub token raises the limit.");
+        }
+        return text;
+    }
+
+    let detail = message.map(|m| format!(": {}", m)).unwrap_or_default();
+    match status {
+        StatusCode::UNAUTHORIZED if has_token => {
+            format!("{} rejected the access token{}. Check the token or remove it.", service, detail)
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN if has_token => {
+            format!("{} denied access{}. The token may lack access to this repository.", service, detail)
+        }
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => format!("{} denied access{}.", service, detail),
+        _ => format!("{} API returned error: {}{}. Please try again later.", service, status, detail),
+    }
+}
+
+/// Download a small text asset (checksum files), retried like fetch_json
+pub async fn fetch_text(
+    url: &str,
+    cancel_token: &CancellationToken,
+    on_retry: &(dyn Fn(&RetryNotice) + Send + Sync),
+) -> Result<String, String> {
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_secs(30))
         .build()
         .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
 
+    retry(&RetryPolicy::API, cancel_token, on_retry, |_| fetch_text_attempt(&client, url)).await
+}
+
+async fn fetch_text_attempt(client: &reqwest::Client, url: &str) -> Result<String, Failure> {
     let response = client
-        .get(&api_url)
+        .get(url)
         .header("User-Agent", USER_AGENT)
-        .header("Accept", "application/vnd.github.v3+json")
+        .send()
+        .await
+        .map_err(|e| classify_request_error(&e, format!("Failed to fetch {}: {}", url, e)))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = format!("Failed to fetch {}: {}", url, status);
+        return Err(if is_transient_status(status) {
+            Failure::Transient(message)
+        } else {
+            Failure::Permanent(message)
+        });
+    }
+
+    response
+        .text()
+        .await
+        .map_err(|e| classify_request_error(&e, format!("Failed to read {}: {}", url, e)))
+}
+
+/// GET a JSON document from a release API or index; `service` names the host in errors
+/// `token` is sent as a bearer token (only pass one to the host it belongs to)
+/// Network failures are retried with RetryPolicy::API, reporting each retry to `on_retry`,
+/// until `cancel_token` is cancelled
+pub async fn fetch_json<T: DeserializeOwned>(
+    api_url: &str,
+    not_found: &str,
+    service: &str,
+    token: Option<&str>,
+    cancel_token: &CancellationToken,
+    on_retry: &(dyn Fn(&RetryNotice) + Send + Sync),
+) -> Result<T, String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    retry(&RetryPolicy::API, cancel_token, on_retry, |_| {
+        fetch_json_attempt(&client, api_url, not_found, service, token)
+    })
+    .await
+}
+
+async fn fetch_json_attempt<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    api_url: &str,
+    not_found: &str,
+    service: &str,
+    token: Option<&str>,
+) -> Result<T, Failure> {
+    let mut request = client
+        .get(api_url)
+        .header("User-Agent", USER_AGENT)
+        .header("Accept", "application/vnd.github.v3+json, application/json");
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request
         .send()
         .await
         .map_err(|e| {
-            if e.is_timeout() {
+            let message = if e.is_timeout() {
                 "Connection timed out. Please check your internet connection and try again.".to_string()
             } else if e.is_connect() {
-                "Cannot reach GitHub. Please check your internet connection and firewall settings.".to_string()
+                format!("Cannot reach {}. Please check your internet connection and firewall settings.", service)
             } else {
                 format!("Failed to fetch release: {}", e)
-            }
+            };
+            classify_request_error(&e, message)
         })?;
 
-    // Check for rate limiting (HTTP 403)
-    if response.status() == 403 {
-        return Err("GitHub API rate limit exceeded. Please wait an hour and try again, or check your internet connection.".to_string());
+    let status = response.status();
+    let rate_limit = RateLimit::from_headers(response.headers());
+    if let Some(remaining) = rate_limit.remaining {
+        crate::debug::log(&format!("{} API requests left this hour: {}", service, remaining));
     }
 
-    if !response.status().is_success() {
-        return Err(format!("GitHub API returned error: {}. Please try again later.", response.status()));
+    if status == 404 {
+        return Err(Failure::Permanent(not_found.to_string()));
     }
 
-    response
-        .json::<Release>()
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        let message = describe_api_error(status, &rate_limit, &body, service, token.is_some());
+        crate::debug::log(&format!("{} API error {} for {}: {}", service, status, api_url, body.trim()));
+        // A rate limit lasts until its reset time; waiting a few seconds won't help
+        if is_transient_status(status) && !rate_limit.is_limited(status) {
+            return Err(Failure::Transient(message));
+        }
+        return Err(Failure::Permanent(message));
+    }
+
+    let body = response
+        .bytes()
         .await
-        .map_err(|e| format!("Failed to parse release data: {}. The release format may be invalid.", e))
+        .map_err(|e| classify_request_error(&e, format!("Failed to read release data: {}", e)))?;
+    serde_json::from_slice(&body).map_err(|e| {
+        Failure::Permanent(format!("Failed to parse release data: {}. The release format may be invalid.", e))
+    })
 }
 
 pub fn find_release_asset(release: &Release) -> Option<&Asset> {
@@ -80,6 +309,63 @@ pub fn find_release_asset(release: &Release) -> Option<&Asset> {
         .max_by_key(|a| a.size)
 }
 
+/// Where an in-progress download is kept until it completes
+fn partial_path(dest_path: &Path) -> PathBuf {
+    let mut name = dest_path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Sidecar next to the .part file describing what it is a prefix of
+fn partial_meta_path(dest_path: &Path) -> PathBuf {
+    let mut name = dest_path.as_os_str().to_owned();
+    name.push(".part.json");
+    PathBuf::from(name)
+}
+
+/// What we know about a .part file, so it is only resumed against the same remote file
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialDownload {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl PartialDownload {
+    fn load(path: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save(&self, path: &Path) {
+        if let Ok(text) = serde_json::to_string(self) {
+            let _ = std::fs::write(path, text);
+        }
+    }
+
+    /// Value for If-Range: a strong ETag, or else Last-Modified
+    /// Weak ETags can't be used for ranges, and without a validator we can't
+    /// prove the server still has the same file, so we don't resume at all
+    fn validator(&self) -> Option<&str> {
+        self.etag
+            .as_deref()
+            .filter(|etag| !etag.starts_with("W/"))
+            .or(self.last_modified.as_deref())
+    }
+}
+
+/// Delete a partial download and its sidecar
+fn discard_partial(dest_path: &Path) {
+    let _ = std::fs::remove_file(partial_path(dest_path));
+    let _ = std::fs::remove_file(partial_meta_path(dest_path));
+}
+
+/// Download `asset` to `dest_path`
+/// Bytes are written to "<dest>.part" and the file is renamed once complete. A dropped
+/// connection or a cancel keeps the .part file, and the next attempt resumes it with
+/// Range/If-Range; servers that ignore ranges (or whose file changed) get a full download.
+/// Network failures are retried with RetryPolicy::DOWNLOAD, so a flaky connection
+/// resumes on its own.
 pub async fn download_asset(
     asset: &Asset,
     dest_path: &Path,
@@ -93,60 +379,146 @@ pub async fn download_asset(
     }
 
     // Create client with connection timeout (but no overall timeout for large downloads)
+    // The read timeout turns a stalled connection into a retry
     let client = reqwest::Client::builder()
         .connect_timeout(std::time::Duration::from_secs(30))
+        .read_timeout(std::time::Duration::from_secs(60))
         .build()
         .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
 
-    let response = client
-        .get(&asset.browser_download_url)
-        .header("User-Agent", USER_AGENT)
-        .send()
-        .await
-        .map_err(|e| {
-            if e.is_timeout() {
-                "Connection timed out while starting download. Please check your internet connection.".to_string()
-            } else if e.is_connect() {
-                "Cannot reach download server. Please check your internet connection and firewall settings.".to_string()
-            } else {
-                format!("Failed to start download: {}", e)
-            }
-        })?;
+    let result = retry(
+        &RetryPolicy::DOWNLOAD,
+        &cancel_token,
+        |notice| {
+            let _ = progress_tx.send(DownloadProgress::Retrying(notice.clone()));
+        },
+        |attempt| download_attempt(&client, asset, dest_path, &progress_tx, &cancel_token, attempt),
+    )
+    .await;
+
+    if result.is_err() && cancel_token.is_cancelled() {
+        let _ = progress_tx.send(DownloadProgress::Cancelled);
+        return Err("Download cancelled".to_string());
+    }
+    result
+}
+
+/// One try at the download, continuing the .part file when possible
+async fn download_attempt(
+    client: &reqwest::Client,
+    asset: &Asset,
+    dest_path: &Path,
+    progress_tx: &mpsc::UnboundedSender<DownloadProgress>,
+    cancel_token: &CancellationToken,
+    attempt: u32,
+) -> Result<(), Failure> {
+    let part_path = partial_path(dest_path);
+    let meta_path = partial_meta_path(dest_path);
+    let url = &asset.browser_download_url;
 
-    if !response.status().is_success() {
-        return Err(format!("Download failed with status {}: Please try again later.", response.status()));
+    // Only resume a .part file that belongs to this URL and can be validated
+    let mut resume = PartialDownload::load(&meta_path)
+        .filter(|meta| &meta.url == url && meta.validator().is_some())
+        .and_then(|meta| {
+            let offset = std::fs::metadata(&part_path).map(|m| m.len()).unwrap_or(0);
+            (offset > 0).then_some((meta, offset))
+        });
+    if resume.is_none() {
+        discard_partial(dest_path);
     }
 
-    let total_size = response.content_length().unwrap_or(asset.size);
+    let mut response = send_download_request(client, url, resume.as_ref()).await?;
+
+    // Our .part file is not a prefix of what the server has (e.g. it is already complete
+    // or the file shrank): start over
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        crate::debug::log("Server rejected the resume range, restarting download");
+        discard_partial(dest_path);
+        resume = None;
+        response = send_download_request(client, url, None).await?;
+    }
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = format!("Download failed with status {}: Please try again later.", status);
+        return Err(if is_transient_status(status) {
+            Failure::Transient(message)
+        } else {
+            Failure::Permanent(message)
+        });
+    }
+
+    let resume_offset = match &resume {
+        Some((_, offset))
+            if response.status() == StatusCode::PARTIAL_CONTENT
+                && content_range_start(&response) == Some(*offset) =>
+        {
+            Some(*offset)
+        }
+        Some(_) => {
+            crate::debug::log("Server did not honour the resume request, downloading the whole file");
+            None
+        }
+        None => None,
+    };
+
+    let remaining = response.content_length();
+    let total_size = match resume_offset {
+        Some(offset) => offset + remaining.unwrap_or(asset.size.saturating_sub(offset)),
+        None => remaining.unwrap_or(asset.size),
+    };
+
+    // Remember the validators for the next resume (keep the old ones if the server omits them)
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+    };
+    let previous = resume.map(|(meta, _)| meta);
+    PartialDownload {
+        url: url.clone(),
+        etag: header(ETAG).or_else(|| previous.as_ref().and_then(|m| m.etag.clone())),
+        last_modified: header(LAST_MODIFIED).or_else(|| previous.as_ref().and_then(|m| m.last_modified.clone())),
+    }
+    .save(&meta_path);
 
     // Log download size for user awareness
     let size_mb = total_size as f64 / 1_048_576.0;
     crate::debug::log(&format!("Download size: {:.1} MB ({} bytes)", size_mb, total_size));
+    if let Some(offset) = resume_offset {
+        crate::debug::log(&format!("Resuming download at {} bytes", offset));
+    }
 
-    let _ = progress_tx.send(DownloadProgress::Started { total_bytes: total_size });
+    let _ = progress_tx.send(DownloadProgress::Started {
+        total_bytes: total_size,
+        resumed_from: resume_offset.unwrap_or(0),
+        attempt,
+    });
 
-    let mut file = File::create(dest_path)
-        .await
-        .map_err(|e| format!("Failed to create file: {}", e))?;
+    let mut file = match resume_offset {
+        Some(_) => OpenOptions::new().append(true).open(&part_path).await,
+        None => File::create(&part_path).await,
+    }
+    .map_err(|e| Failure::Permanent(format!("Failed to create file: {}", e)))?;
 
-    let mut downloaded: u64 = 0;
+    let mut downloaded: u64 = resume_offset.unwrap_or(0);
     let mut stream = response.bytes_stream();
 
     loop {
         tokio::select! {
             _ = cancel_token.cancelled() => {
-                // Clean up partial file
-                drop(file);
-                let _ = tokio::fs::remove_file(dest_path).await;
-                let _ = progress_tx.send(DownloadProgress::Cancelled);
-                return Err("Download cancelled".to_string());
+                // Keep the partial file so the next attempt can resume it
+                let _ = file.flush().await;
+                return Err(Failure::Permanent("Download cancelled".to_string()));
             }
             chunk_result = stream.next() => {
                 match chunk_result {
                     Some(Ok(chunk)) => {
                         file.write_all(&chunk)
                             .await
-                            .map_err(|e| format!("Write error: {}", e))?;
+                            .map_err(|e| Failure::Permanent(format!("Write error: {}", e)))?;
 
                         downloaded += chunk.len() as u64;
                         let _ = progress_tx.send(DownloadProgress::Progress {
@@ -155,7 +527,8 @@ pub async fn download_asset(
                         });
                     }
                     Some(Err(e)) => {
-                        return Err(format!("Download error: {}", e));
+                        let _ = file.flush().await;
+                        return Err(classify_request_error(&e, format!("Download error: {}", e)));
                     }
                     None => {
                         // Stream complete
@@ -166,13 +539,65 @@ pub async fn download_asset(
         }
     }
 
-    file.flush().await.map_err(|e| format!("Flush error: {}", e))?;
+    file.flush().await.map_err(|e| Failure::Permanent(format!("Flush error: {}", e)))?;
+    drop(file);
+
+    if downloaded < total_size {
+        return Err(Failure::Transient(format!(
+            "Download incomplete: received {} of {} bytes. Try again to resume.",
+            downloaded, total_size
+        )));
+    }
+
+    tokio::fs::rename(&part_path, dest_path)
+        .await
+        .map_err(|e| Failure::Permanent(format!("Failed to move download into place: {}", e)))?;
+    let _ = std::fs::remove_file(&meta_path);
     let _ = progress_tx.send(DownloadProgress::Completed);
 
     Ok(())
 }
 
-fn parse_github_url(url: &str) -> Result<(String, String), String> {
+async fn send_download_request(
+    client: &reqwest::Client,
+    url: &str,
+    resume: Option<&(PartialDownload, u64)>,
+) -> Result<reqwest::Response, Failure> {
+    let mut request = client.get(url).header("User-Agent", USER_AGENT);
+    if let Some((meta, offset)) = resume {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+        if let Some(validator) = meta.validator() {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+
+    request.send().await.map_err(|e| {
+        let message = if e.is_timeout() {
+            "Connection timed out while starting download. Please check your internet connection.".to_string()
+        } else if e.is_connect() {
+            "Cannot reach download server. Please check your internet connection and firewall settings.".to_string()
+        } else {
+            format!("Failed to start download: {}", e)
+        };
+        classify_request_error(&e, message)
+    })
+}
+
+/// First byte offset of a 206 response ("Content-Range: bytes 100-199/200")
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+pub fn parse_github_url(url: &str) -> Result<(String, String), String> {
     // Handle various GitHub URL formats:
     // https://github.com/owner/repo
     // https://github.com/owner/repo.git
@@ -223,4 +648,213 @@ mod tests {
             ("owner".to_string(), "repo".to_string())
         );
     }
+
+    #[test]
+    fn test_release_summary() {
+        let release: Release = serde_json::from_str(r#"{
+            "tag_name": "v6.3.0",
+            "name": null,
+            "prerelease": true,
+            "published_at": "2025-01-31T18:04:11Z",
+            "assets": [
+                {"name": "NextUI-v6.3.0.zip", "size": 104857600, "browser_download_url": "https://example.com/a.zip"},
+                {"name": "notes.txt", "size": 10, "browser_download_url": "https://example.com/notes.txt"}
+            ]
+        }"#).unwrap();
+
+        assert_eq!(release.published_date(), "2025-01-31");
+        assert_eq!(release.summary(), "v6.3.0 (2025-01-31, 100.0 MB, pre-release)");
+    }
+
+    fn test_asset(url: String, size: u64) -> Asset {
+        Asset {
+            name: "NextUI.zip".to_string(),
+            size,
+            browser_download_url: url,
+            digest: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_after_dropped_connection() {
+        use crate::test_server::{file_response, TestServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let served = body.clone();
+        let calls = AtomicUsize::new(0);
+        let attempts = crate::config::NETWORK_RETRY_ATTEMPTS as usize;
+        let server = TestServer::start(move |request| {
+            let mut response = file_response(request, &served, "\"v1\"", true);
+            // Drop the first `attempts` connections halfway through, so the first
+            // download gives up with a partial file and the second one resumes it
+            if calls.fetch_add(1, Ordering::SeqCst) < attempts {
+                response.cut_after = Some(response.body.len() / 2);
+            }
+            response
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("NextUI.zip");
+        let asset = test_asset(format!("{}/NextUI.zip", server.url), body.len() as u64);
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        assert!(download_asset(&asset, &dest, tx.clone(), CancellationToken::new()).await.is_err());
+        let partial_len = std::fs::metadata(partial_path(&dest)).unwrap().len();
+        assert!(partial_len > 0 && partial_len < body.len() as u64);
+
+        download_asset(&asset, &dest, tx, CancellationToken::new()).await.unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), body);
+        assert!(!partial_path(&dest).exists());
+        assert!(!partial_meta_path(&dest).exists());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), attempts + 1);
+        assert!(requests.iter().all(|r| r.path == "/NextUI.zip"));
+        assert!(requests[1..].iter().all(|r| r.header("if-range") == Some("\"v1\"")));
+        assert_eq!(requests[attempts].header("range"), Some(format!("bytes={}-", partial_len).as_str()));
+    }
+
+    #[tokio::test]
+    async fn test_download_retry_resumes() {
+        use crate::test_server::{file_response, Response, TestServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let body: Vec<u8> = (0..100_000u32).map(|i| (i % 13) as u8).collect();
+        let served = body.clone();
+        let calls = AtomicUsize::new(0);
+        let server = TestServer::start(move |request| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::new(503, "try later"),
+            1 => {
+                let mut response = file_response(request, &served, "\"v1\"", true);
+                response.cut_after = Some(30_000);
+                response
+            }
+            _ => file_response(request, &served, "\"v1\"", true),
+        })
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("NextUI.zip");
+        let asset = test_asset(format!("{}/NextUI.zip", server.url), body.len() as u64);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        download_asset(&asset, &dest, tx, CancellationToken::new()).await.unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), body);
+
+        let events: Vec<DownloadProgress> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        let retries: Vec<u32> = events
+            .iter()
+            .filter_map(|e| match e {
+                DownloadProgress::Retrying(notice) => Some(notice.attempt),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(retries, vec![2, 3]);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            DownloadProgress::Started { resumed_from: 30_000, attempt: 3, .. }
+        )));
+        assert_eq!(server.requests()[2].header("range"), Some("bytes=30000-"));
+    }
+
+    #[tokio::test]
+    async fn test_download_falls_back_to_full_download() {
+        use crate::test_server::{file_response, TestServer};
+
+        let body = b"the complete release archive".to_vec();
+
+        // (server honours ranges, etag of the stale .part file)
+        for (honor_ranges, stale_etag) in [(false, "\"v1\""), (true, "\"v0\"")] {
+            let served = body.clone();
+            let server = TestServer::start(move |request| file_response(request, &served, "\"v1\"", honor_ranges)).await;
+
+            let dir = tempfile::tempdir().unwrap();
+            let dest = dir.path().join("NextUI.zip");
+            let asset = test_asset(format!("{}/NextUI.zip", server.url), body.len() as u64);
+
+            std::fs::write(partial_path(&dest), b"stale bytes").unwrap();
+            PartialDownload {
+                url: asset.browser_download_url.clone(),
+                etag: Some(stale_etag.to_string()),
+                last_modified: None,
+            }
+            .save(&partial_meta_path(&dest));
+
+            let (tx, _rx) = mpsc::unbounded_channel();
+            download_asset(&asset, &dest, tx, CancellationToken::new()).await.unwrap();
+
+            assert_eq!(std::fs::read(&dest).unwrap(), body);
+            assert_eq!(server.requests()[0].header("range"), Some("bytes=11-"));
+        }
+    }
+
+    #[test]
+    fn test_describe_retry_time() {
+        let at = UNIX_EPOCH + Duration::from_secs(3 * 86_400 + 14 * 3600 + 32 * 60);
+        assert_eq!(describe_retry_time(at, at - Duration::from_secs(11 * 60 + 30)), "in 12 minutes (at 14:32 UTC)");
+        assert_eq!(describe_retry_time(at, at), "in under a minute (at 14:32 UTC)");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_text_retries() {
+        use crate::test_server::{Response, TestServer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = AtomicUsize::new(0);
+        let server = TestServer::start(move |_| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::new(503, ""),
+            _ => Response::new(200, "abc  NextUI.zip"),
+        })
+        .await;
+        let url = format!("{}/SHA256SUMS", server.url);
+
+        let retries = Mutex::new(0);
+        let on_retry = |_: &RetryNotice| *retries.lock().unwrap() += 1;
+        assert_eq!(fetch_text(&url, &CancellationToken::new(), &on_retry).await.unwrap(), "abc  NextUI.zip");
+        assert_eq!(*retries.lock().unwrap(), 1);
+
+        // A cancelled run doesn't wait for another attempt
+        let token = CancellationToken::new();
+        token.cancel();
+        let server = TestServer::start(|_| Response::new(503, "")).await;
+        assert!(fetch_text(&server.url, &token, &|_| {}).await.is_err());
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_and_access_errors() {
+        use crate::test_server::{Response, TestServer};
+
+        let reset = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 20 * 60;
+        let server = TestServer::start(move |request| match request.path.as_str() {
+            "/limited" => Response::new(403, r#"{"message": "API rate limit exceeded for 192.0.2.1."}"#)
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", &reset.to_string()),
+            "/secondary" => Response::new(429, "").header("Retry-After", "90"),
+            _ => Response::new(403, r#"{"message": "Resource protected by organization SAML enforcement."}"#)
+                .header("X-RateLimit-Remaining", "4999"),
+        })
+        .await;
+
+        let fetch = |path: &str, token: Option<&'static str>| {
+            let url = format!("{}{}", server.url, path);
+            async move { fetch_json::<serde_json::Value>(&url, "not found", "GitHub", token, &CancellationToken::new(), &|_| {}).await.unwrap_err() }
+        };
+
+        let err = fetch("/limited", None).await;
+        assert!(err.starts_with("GitHub API rate limit exceeded. Try again in 20 minutes"), "{}", err);
+        assert!(err.ends_with("Adding a GitHub token raises the limit."), "{}", err);
+
+        let err = fetch("/secondary", Some("secret")).await;
+        assert!(err.contains("Try again in 2 minutes") && !err.contains("token"), "{}", err);
+
+        let err = fetch("/protected", Some("secret")).await;
+        assert!(!err.contains("rate limit") && err.contains("SAML enforcement"), "{}", err);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("authorization"), None);
+        assert_eq!(requests[1].header("authorization"), Some("Bearer secret"));
+    }
 }
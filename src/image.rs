@@ -0,0 +1,173 @@
+// Disk image output
+// Builds a ready-to-flash .img instead of writing to a card, for emulators and dd or
+// Etcher-style tools. The image is a sparse file with the same MBR and FAT32 volume a
+// card gets (see fat32.rs), the release is written into the volume directly, so
+// nothing is mounted, and the finished image can be gzipped.
+
+use crate::drives::DriveInfo;
+use crate::fat32::{format_disk, Volume, PARTITION_OFFSET};
+use crate::format::FormatProgress;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// A disk image to build instead of installing to a card
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageTarget {
+    pub path: PathBuf,
+    /// Whole sectors
+    pub size_bytes: u64,
+    /// Gzip the image into "<path>.gz" and delete the uncompressed one
+    pub compress: bool,
+}
+
+impl ImageTarget {
+    pub fn new(path: PathBuf, size_bytes: u64, compress: bool) -> Self {
+        Self { path, size_bytes: size_bytes / 512 * 512, compress }
+    }
+
+    /// The file the user ends up with
+    pub fn output_path(&self) -> PathBuf {
+        if self.compress {
+            let mut name = self.path.clone().into_os_string();
+            name.push(".gz");
+            PathBuf::from(name)
+        } else {
+            self.path.clone()
+        }
+    }
+
+    /// The image as a drive, for the size checks and the logs
+    pub fn drive(&self) -> DriveInfo {
+        DriveInfo {
+            name: self.path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            device_path: self.path.display().to_string(),
+            mount_path: None,
+            label: String::new(),
+            size_bytes: self.size_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CompressProgress {
+    Progress { read_bytes: u64, total_bytes: u64 },
+    Completed,
+    Cancelled,
+}
+
+/// Create the image file (replacing an existing one), partition and format it, and
+/// return its empty FAT32 volume
+pub async fn create_image(
+    target: &ImageTarget,
+    volume_label: &str,
+    progress_tx: mpsc::UnboundedSender<FormatProgress>,
+) -> Result<Volume<std::fs::File>, String> {
+    crate::debug::log_section("Creating Image");
+    crate::debug::log(&format!("Image: {:?} ({} bytes)", target.path, target.size_bytes));
+    let _ = progress_tx.send(FormatProgress::Started);
+
+    let (path, size, label) = (target.path.clone(), target.size_bytes, volume_label.to_string());
+    let tx = progress_tx.clone();
+    let volume = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        // Parts that are never written take no room where the filesystem supports sparse files
+        file.set_len(size)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+        let _ = tx.send(FormatProgress::Formatting);
+        format_disk(&mut file, size, &label, &mut |percent| {
+            let _ = tx.send(FormatProgress::Progress { percent });
+        })?;
+        Volume::open(file, PARTITION_OFFSET)
+    })
+    .await
+    .map_err(|e| format!("Image task failed: {}", e))??;
+
+    let _ = progress_tx.send(FormatProgress::Completed);
+    crate::debug::log("Image formatted");
+    Ok(volume)
+}
+
+/// Write out the volume's directories and FATs and flush the image to disk
+pub async fn finish_image(volume: Volume<std::fs::File>) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let file = volume.finish()?;
+        file.sync_all().map_err(|e| format!("Failed to flush image: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Image task failed: {}", e))?
+}
+
+/// Gzip `path` into "<path>.gz" and delete `path`; returns the compressed file
+pub async fn compress_image(
+    path: &Path,
+    progress_tx: mpsc::UnboundedSender<CompressProgress>,
+    cancel_token: CancellationToken,
+) -> Result<PathBuf, String> {
+    crate::debug::log_section("Compressing Image");
+    let mut output = path.to_path_buf().into_os_string();
+    output.push(".gz");
+    let output = PathBuf::from(output);
+    crate::debug::log(&format!("Output: {:?}", output));
+
+    let (input, out, tx, token) = (path.to_path_buf(), output.clone(), progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || gzip_file(&input, &out, &tx, &token))
+        .await
+        .map_err(|e| format!("Compression task failed: {}", e))?;
+
+    match &result {
+        Ok(()) => {
+            let _ = std::fs::remove_file(path);
+            let _ = progress_tx.send(CompressProgress::Completed);
+        }
+        Err(_) => {
+            let _ = std::fs::remove_file(&output);
+            if cancel_token.is_cancelled() {
+                let _ = progress_tx.send(CompressProgress::Cancelled);
+            }
+        }
+    }
+    result.map(|()| output)
+}
+
+fn gzip_file(
+    input: &Path,
+    output: &Path,
+    progress_tx: &mpsc::UnboundedSender<CompressProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<(), String> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", input.display(), e);
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", output.display(), e);
+
+    let mut source = std::fs::File::open(input).map_err(read_error)?;
+    let total_bytes = source.metadata().map_err(read_error)?.len();
+    let file = std::fs::File::create(output).map_err(write_error)?;
+    let mut encoder = flate2::write::GzEncoder::new(std::io::BufWriter::new(file), flate2::Compression::default());
+
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let mut read_bytes = 0u64;
+    loop {
+        if cancel_token.is_cancelled() {
+            return Err("Compression cancelled".to_string());
+        }
+        let read = source.read(&mut buffer).map_err(read_error)?;
+        if read == 0 {
+            break;
+        }
+        encoder.write_all(&buffer[..read]).map_err(write_error)?;
+        read_bytes += read as u64;
+        let _ = progress_tx.send(CompressProgress::Progress { read_bytes, total_bytes });
+    }
+    let file = encoder.finish().map_err(write_error)?.into_inner().map_err(|e| write_error(e.into_error()))?;
+    file.sync_all().map_err(write_error)?;
+    crate::debug::log(&format!("Compressed {} bytes", read_bytes));
+    Ok(())
+}
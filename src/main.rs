@@ -1,6 +1,12 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod app;
+mod archive;
+mod benchmark;
+mod cache;
+mod capacity;
+mod checksum;
+mod cli;
 mod config;
 mod copy;
 mod debug;
@@ -10,6 +16,16 @@ mod extract;
 mod fat32;
 mod format;
 mod github;
+mod image;
+mod manifest;
+mod pipeline;
+mod retry;
+mod source;
+mod verify;
+#[cfg(test)]
+mod test_server;
+#[cfg(test)]
+mod test_zip;
 
 use app::InstallerApp;
 use config::{load_app_icon, load_custom_fonts, WINDOW_MIN_SIZE, WINDOW_SIZE, WINDOW_TITLE};
@@ -79,6 +95,12 @@ fn check_and_request_privileges() {
 }
 
 fn main() -> eframe::Result<()> {
+    // Any arguments switch to the headless command-line mode (no window, no relaunch)
+    let args = cli::args();
+    if !args.is_empty() {
+        std::process::exit(cli::run(args));
+    }
+
     // Call the privilege check at the very beginning of main (not needed on Windows due to manifest)
     #[cfg(not(windows))]
     check_and_request_privileges();
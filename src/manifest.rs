@@ -0,0 +1,128 @@
+// Installation manifest
+// A JSON file on the card that records which release was installed and every file it
+// put there, with its size and SHA-256, so later updates, verifies and repairs know
+// what the card is supposed to contain.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest in the root of the card
+pub const MANIFEST_FILE_NAME: &str = "installer_manifest.json";
+
+/// Bumped when a field changes meaning, so older installers can tell
+pub const MANIFEST_FORMAT: u32 = 1;
+
+/// One installed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstalledFile {
+    /// Path relative to the card root, '/'-separated as in the archive
+    pub path: String,
+    pub size: u64,
+    /// Lowercase hex
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub installer: String,
+    pub installer_version: String,
+    /// Unix time in seconds
+    pub installed_at: u64,
+    /// Release source (see config::REPO_OPTIONS); None for a local archive
+    pub repo: Option<String>,
+    pub tag: Option<String>,
+    /// File name of the release archive
+    pub asset: String,
+    /// SHA-256 of the release archive, lowercase hex
+    pub asset_sha256: String,
+    /// Components left out of the install (see archive::components)
+    pub skipped: Vec<String>,
+    /// Sorted by path
+    pub files: Vec<InstalledFile>,
+}
+
+impl Manifest {
+    pub fn new(asset: &str, asset_sha256: &str, mut files: Vec<InstalledFile>) -> Self {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Self {
+            format: MANIFEST_FORMAT,
+            installer: env!("CARGO_PKG_NAME").to_string(),
+            installer_version: env!("CARGO_PKG_VERSION").to_string(),
+            installed_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            repo: None,
+            tag: None,
+            asset: asset.to_string(),
+            asset_sha256: asset_sha256.to_string(),
+            skipped: Vec::new(),
+            files,
+        }
+    }
+
+    /// Read the manifest from the root of `card_dir`
+    pub fn read_from(card_dir: &Path) -> Result<Self, String> {
+        let path = card_dir.join(MANIFEST_FILE_NAME);
+        let json = match std::fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(format!(
+                    "There is no {} on the card; it was installed by an older installer or by hand",
+                    MANIFEST_FILE_NAME
+                ))
+            }
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let manifest: Self = serde_json::from_str(&json).map_err(|e| format!("{} is damaged: {}", MANIFEST_FILE_NAME, e))?;
+        if manifest.format > MANIFEST_FORMAT {
+            return Err(format!("{} was written by a newer installer; update this one", MANIFEST_FILE_NAME));
+        }
+        Ok(manifest)
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| format!("Failed to encode manifest: {}", e))
+    }
+
+    /// Write the manifest to the root of `card_dir`, replacing an older one
+    /// It is written next to the old one first, so an interrupted write never leaves
+    /// half a manifest behind
+    pub fn write_to(&self, card_dir: &Path) -> Result<PathBuf, String> {
+        let path = card_dir.join(MANIFEST_FILE_NAME);
+        let temp_path = card_dir.join(format!("{}.tmp", MANIFEST_FILE_NAME));
+        let json = self.to_json()?;
+
+        std::fs::write(&temp_path, json).map_err(|e| format!("Failed to write {}: {}", temp_path.display(), e))?;
+        std::fs::rename(&temp_path, &path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp_path);
+            format!("Failed to write {}: {}", path.display(), e)
+        })?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |path: &str| InstalledFile { path: path.to_string(), size: 3, sha256: "ab".repeat(32) };
+        let mut manifest = Manifest::new("NextUI.zip", &"cd".repeat(32), vec![file("b.txt"), file(".system/a.bin")]);
+        manifest.repo = Some("LoveRetro/NextUI".to_string());
+        manifest.tag = Some("v6.2.0".to_string());
+
+        // Writing again replaces the previous manifest
+        manifest.write_to(dir.path()).unwrap();
+        let path = manifest.write_to(dir.path()).unwrap();
+        assert_eq!(path, dir.path().join(MANIFEST_FILE_NAME));
+        assert!(!dir.path().join(format!("{}.tmp", MANIFEST_FILE_NAME)).exists());
+
+        let read = Manifest::read_from(dir.path()).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.files[0].path, ".system/a.bin");
+    }
+}
@@ -0,0 +1,2076 @@
+// Install pipeline shared by the GUI and the command-line mode
+// fetch -> download -> verify -> check archive -> (check capacity) -> format -> extract
+// -> copy -> verify card
+// (a local archive skips fetch/download/verify, extracting straight onto the card
+// skips copy, an update checks the mounted card instead of formatting it, a card
+// verification only checks the card and verifies it, a repair also reads the card
+// back first and only rewrites what is damaged, an image target formats and fills a
+// disk image file instead of a card and then compresses it, a benchmark only times a
+// test file on the mounted card; eject is left to the caller)
+// On Linux a fresh install writes the release straight into the card's FAT32 volume
+// and reads it back from there, so the card is never mounted.
+//
+// Each stage is a method on InstallPipeline. Progress leaves the pipeline as typed
+// PipelineEvents on a channel, errors as PipelineError, and every temp file the run
+// creates is tracked in one Workspace that is cleaned up once, however the run ends.
+
+use crate::archive::{
+    check_entry_names, check_exclusions, check_release_layout, components, detect_kind, is_excluded, list_entries,
+    size_on_card, summarize, Component,
+};
+use crate::benchmark::{benchmark_card, BenchmarkProgress, BenchmarkReport};
+use crate::cache::{cache_base_dir, CacheKey, DownloadCache};
+use crate::capacity::{check_card_capacity, CapacityProgress};
+use crate::checksum::{find_expected_digest, hash_file, verify_file, ExpectedDigest, VerifyProgress};
+use crate::config::{
+    APP_NAME, ASSET_EXTENSION, CHECK_CAPACITY, EXTRACT_TO_CARD, NETWORK_RETRY_ATTEMPTS, PRESERVE_PATHS,
+    RELEASE_MARKER_PATHS, REQUIRE_CHECKSUM, TEMP_PREFIX, VERIFY_AFTER_INSTALL, VOLUME_LABEL,
+};
+#[cfg(target_os = "linux")]
+use crate::copy::copy_directory_to_volume;
+use crate::copy::{copy_directory_with_progress, CopyProgress};
+use crate::drives::DriveInfo;
+use crate::extract::{extract_archive, extract_archive_to_volume, ExtractOptions, ExtractProgress, ExtractReport};
+use crate::fat32;
+#[cfg(target_os = "linux")]
+use crate::format::{finish_card, flush_card, format_card};
+#[cfg(not(target_os = "linux"))]
+use crate::format::format_drive_fat32;
+use crate::format::FormatProgress;
+use crate::github::{download_asset, find_release_asset, Asset, DownloadProgress};
+use crate::image::{compress_image, create_image, finish_image, CompressProgress, ImageTarget};
+use crate::manifest::{InstalledFile, Manifest, MANIFEST_FILE_NAME};
+use crate::retry::RetryNotice;
+use crate::source::ReleaseSource;
+#[cfg(target_os = "linux")]
+use crate::verify::verify_volume;
+use crate::verify::{verify_card, CardVerifyProgress, VerifyReport};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// How many unsafe archive entries are listed in the log (all go to the debug log)
+const MAX_REPORTED_ISSUES: usize = 20;
+
+/// How many added or replaced files an update lists in the log (all go to the debug log)
+const MAX_LISTED_CHANGES: usize = 50;
+
+/// The step the pipeline is currently working on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstallStage {
+    FetchingRelease,
+    Downloading,
+    Verifying,
+    CheckingArchive,
+    CheckingCard,
+    CheckingCapacity,
+    Formatting,
+    Extracting,
+    Copying,
+    VerifyingCard,
+    Compressing,
+    Benchmarking,
+}
+
+impl InstallStage {
+    pub fn label(&self) -> &'static str {
+        match self {
+            InstallStage::FetchingRelease => "Fetching release",
+            InstallStage::Downloading => "Downloading",
+            InstallStage::Verifying => "Verifying",
+            InstallStage::CheckingArchive => "Checking archive",
+            InstallStage::CheckingCard => "Checking card",
+            InstallStage::CheckingCapacity => "Checking capacity",
+            InstallStage::Formatting => "Formatting",
+            InstallStage::Extracting => "Extracting",
+            InstallStage::Copying => "Copying",
+            InstallStage::VerifyingCard => "Verifying card",
+            InstallStage::Compressing => "Compressing image",
+            InstallStage::Benchmarking => "Benchmarking",
+        }
+    }
+}
+
+/// Everything the pipeline reports while it runs, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineEvent {
+    /// The pipeline moved on to a new stage
+    StageStarted(InstallStage),
+    /// A user-facing log line
+    Log(String),
+    /// Replace the status message without touching the progress values
+    Status(String),
+    /// Progress within the current stage
+    Progress { current: u64, total: u64, message: String },
+    /// What a benchmark measured (its results are also logged)
+    Benchmark(BenchmarkReport),
+    /// The parts of the downloaded release (see InstallMode::Download)
+    Components(Vec<Component>),
+    /// Always the last event of a run
+    Finished(Result<(), PipelineError>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    Cancelled,
+    Failed { stage: InstallStage, message: String },
+}
+
+impl std::fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PipelineError::Cancelled => write!(f, "Installation cancelled"),
+            PipelineError::Failed { stage, message } => write!(f, "{} failed: {}", stage.label(), message),
+        }
+    }
+}
+
+/// What happens to the card
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InstallMode {
+    /// Format the card and install from scratch
+    #[default]
+    Fresh,
+    /// Only download the release into the cache and report its components, so
+    /// they can be picked before the install (which then finds it in the cache)
+    Download,
+    /// Install over the files on the mounted card, keeping the user's files
+    Update,
+    /// Only read the installed files back and compare them with the card's manifest
+    Verify,
+    /// Rewrite the installed files that are missing or damaged, keeping the user's files
+    Repair,
+    /// Only time reads and writes of a test file on the mounted card
+    Benchmark,
+}
+
+impl InstallMode {
+    /// What a run in this mode is called in messages ("Installation complete")
+    pub fn label(&self) -> &'static str {
+        match self {
+            InstallMode::Fresh => "Installation",
+            InstallMode::Update => "Update",
+            InstallMode::Verify => "Verification",
+            InstallMode::Download => "Download",
+            InstallMode::Repair => "Repair",
+            InstallMode::Benchmark => "Benchmark",
+        }
+    }
+
+    /// Whether the release is written over the files already on the card
+    pub fn in_place(&self) -> bool {
+        matches!(self, InstallMode::Update | InstallMode::Repair)
+    }
+}
+
+/// What to install and where
+#[derive(Debug, Clone)]
+pub struct InstallOptions {
+    pub drive: DriveInfo,
+    pub repo_name: String,
+    pub repo_url: String,
+    /// Release tag to install; None installs the latest release
+    pub tag: Option<String>,
+    /// Keep the download in the persistent cache and reuse a cached copy
+    pub use_cache: bool,
+    /// Install this release archive instead of downloading one (offline install)
+    pub local_archive: Option<PathBuf>,
+    /// Extract straight onto the card (false: extract locally, then copy)
+    pub extract_to_card: bool,
+    /// Parts of the release to leave out (archive paths, see archive::components)
+    pub excluded_components: Vec<String>,
+    pub mode: InstallMode,
+    /// Read the installed files back from the card after installing
+    pub verify_after_install: bool,
+    /// Test the card's real capacity before formatting it (fresh installs only)
+    pub check_capacity: bool,
+    /// Existing files an update never overwrites (see config::PRESERVE_PATHS)
+    pub preserve: Vec<String>,
+    pub volume_label: String,
+    /// Build this disk image instead of writing to the drive (fresh installs only)
+    pub image: Option<ImageTarget>,
+}
+
+impl InstallOptions {
+    pub fn new(drive: DriveInfo, repo_name: &str, repo_url: &str) -> Self {
+        Self {
+            drive,
+            repo_name: repo_name.to_string(),
+            repo_url: repo_url.to_string(),
+            tag: None,
+            use_cache: true,
+            local_archive: None,
+            extract_to_card: EXTRACT_TO_CARD,
+            excluded_components: Vec::new(),
+            mode: InstallMode::Fresh,
+            verify_after_install: VERIFY_AFTER_INSTALL,
+            check_capacity: CHECK_CAPACITY,
+            preserve: PRESERVE_PATHS.iter().map(|p| p.to_string()).collect(),
+            volume_label: VOLUME_LABEL.to_string(),
+            image: None,
+        }
+    }
+
+    /// Install into a new disk image instead of a card
+    pub fn for_image(image: ImageTarget, repo_name: &str, repo_url: &str) -> Self {
+        let mut options = Self::new(image.drive(), repo_name, repo_url);
+        options.image = Some(image);
+        options
+    }
+
+    /// What the run is called in messages ("Installation complete")
+    pub fn label(&self) -> &'static str {
+        match self.image {
+            Some(_) => "Image build",
+            None => self.mode.label(),
+        }
+    }
+}
+
+/// The release asset chosen in the fetch stage
+struct SelectedRelease {
+    tag: String,
+    asset: Asset,
+    expected_digest: Option<ExpectedDigest>,
+}
+
+/// What a repair found by comparing the card with its manifest
+struct RepairPlan {
+    manifest: Manifest,
+    /// Missing and corrupted files (archive paths)
+    damaged: Vec<String>,
+}
+
+/// Temp files created during a run
+#[derive(Debug, Default)]
+struct Workspace {
+    /// The completed download when the cache is off; an interrupted one is left as
+    /// "<name>.part" (see github::download_asset) so the next run can resume it
+    download_path: Option<PathBuf>,
+    extract_dir: Option<PathBuf>,
+}
+
+impl Workspace {
+    fn cleanup(&mut self) {
+        if let Some(dir) = self.extract_dir.take() {
+            let _ = std::fs::remove_dir_all(&dir);
+            crate::debug::log("Cleaned up temp extraction folder");
+        }
+        if let Some(path) = self.download_path.take() {
+            let _ = std::fs::remove_file(&path);
+            crate::debug::log("Cleaned up temp download file");
+        }
+    }
+}
+
+pub struct InstallPipeline {
+    options: InstallOptions,
+    events: mpsc::UnboundedSender<PipelineEvent>,
+    cancel_token: CancellationToken,
+    workspace: Workspace,
+    /// install_log.txt on the card, once it is mounted
+    card_log_path: Option<PathBuf>,
+}
+
+impl InstallPipeline {
+    pub fn new(
+        options: InstallOptions,
+        events: mpsc::UnboundedSender<PipelineEvent>,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            options,
+            events,
+            cancel_token,
+            workspace: Workspace::default(),
+            card_log_path: None,
+        }
+    }
+
+    /// Run every stage, clean up, and report the result (also sent as PipelineEvent::Finished)
+    pub async fn run(mut self) -> Result<(), PipelineError> {
+        let result = self.run_stages().await;
+
+        self.workspace.cleanup();
+
+        let label = self.options.label();
+        match &result {
+            Ok(()) if self.options.image.is_some() || self.options.mode == InstallMode::Download => {
+                self.log(&format!("{} complete!", label))
+            }
+            Ok(()) => {
+                self.log(&format!("{} complete! You can now safely eject the SD card.", label));
+                self.card_log(&format!("{} complete!", label));
+            }
+            Err(PipelineError::Cancelled) => {
+                self.log(&format!("{} cancelled", label));
+                self.card_log(&format!("{} cancelled", label));
+            }
+            Err(e) => {
+                self.log(&format!("Error: {}", e));
+                crate::debug::log(&format!("ERROR: {}", e));
+                self.card_log(&format!("Error: {}", e));
+            }
+        }
+
+        self.emit(PipelineEvent::Finished(result.clone()));
+        result
+    }
+
+    async fn run_stages(&mut self) -> Result<(), PipelineError> {
+        self.log_start();
+
+        let temp_dir = cache_base_dir();
+
+        // Nothing to download if there is no card to update
+        let mounted_card = match self.options.mode {
+            InstallMode::Fresh | InstallMode::Download => None,
+            InstallMode::Update | InstallMode::Repair => Some(self.check_card()?),
+            InstallMode::Verify => {
+                // Read-only: nothing is written to the card, not even the logs
+                let card = self.check_card()?;
+                let manifest = Manifest::read_from(&card).map_err(|e| self.fail(InstallStage::CheckingCard, e))?;
+                self.log_manifest(&manifest);
+                return self.verify_card(&card, &manifest.files).await;
+            }
+            InstallMode::Benchmark => return self.benchmark().await,
+        };
+
+        let repair = match (&mounted_card, self.options.mode) {
+            (Some(card), InstallMode::Repair) => self.plan_repair(card).await?,
+            _ => None,
+        };
+        if repair.as_ref().is_some_and(|plan| plan.damaged.is_empty()) {
+            self.log("Nothing to repair: every installed file is intact");
+            return Ok(());
+        }
+
+        // Download and verify before touching the card, so a bad download never
+        // costs the user the data on it
+        let mut release = None;
+        let archive_path = match self.options.local_archive.clone() {
+            Some(path) => path,
+            None => {
+                let selected = self.fetch_release().await?;
+                let path = if self.options.use_cache {
+                    self.obtain_cached(&selected, &temp_dir).await?
+                } else {
+                    self.obtain_uncached(&selected, &temp_dir).await?
+                };
+                release = Some(selected);
+                path
+            }
+        };
+        // There is no card yet, so none of the card checks apply
+        if self.options.mode == InstallMode::Download {
+            return self.list_components(&archive_path);
+        }
+        self.check_archive(&archive_path, &temp_dir, mounted_card.as_deref())?;
+        if let Some(plan) = &repair {
+            // The manifest's hashes are only good for the archive they were taken from
+            let stage = InstallStage::CheckingArchive;
+            let sha256 = self.archive_sha256(&archive_path, release.as_ref()).await.map_err(|e| self.fail(stage, e))?;
+            if sha256 != plan.manifest.asset_sha256 {
+                return Err(self.fail(stage, format!(
+                    "{} is not the release installed on the card ({})",
+                    archive_path.file_name().unwrap_or_default().to_string_lossy(),
+                    plan.manifest.asset
+                )));
+            }
+        }
+
+        if let Some(image) = self.options.image.clone() {
+            return self.build_image(&image, &archive_path, release.as_ref()).await;
+        }
+
+        if mounted_card.is_none() && self.options.check_capacity {
+            self.check_capacity().await?;
+        }
+
+        let archive_name = archive_path.file_name().unwrap_or_default().to_string_lossy();
+        let dest_path = match mounted_card {
+            Some(card) => {
+                self.card_log_path = Some(card.join("install_log.txt"));
+                self.card_log(&format!("{} using {}...", self.options.mode.label(), archive_name));
+                card
+            }
+            #[cfg(target_os = "linux")]
+            None => return self.install_unmounted(&archive_path, release.as_ref(), &temp_dir).await,
+            #[cfg(not(target_os = "linux"))]
+            None => {
+                let dest_path = self.format_drive().await?;
+                self.card_log(&format!("Format complete, installing {}...", archive_name));
+                dest_path
+            }
+        };
+
+        // An update has to compare every file with the card, so it always extracts there
+        let only = repair.as_ref().map(|plan| plan.damaged.clone()).unwrap_or_default();
+        let report = if self.options.extract_to_card || self.options.mode.in_place() {
+            self.log("Extracting files to SD card...");
+            let report = self.extract(&archive_path, &dest_path, &only).await?;
+            self.card_log("Extraction complete");
+            self.log_changes(&report);
+            report
+        } else {
+            self.log("Extracting files to local temp folder...");
+            let extract_dir = self.create_extract_dir(&extract_base_dir(&temp_dir))?;
+            let report = self.extract(&archive_path, &extract_dir, &only).await?;
+
+            self.card_log("Extraction complete");
+            self.copy_files(&extract_dir, &dest_path).await?;
+            self.card_log("Copy complete");
+            report
+        };
+
+        // A repair guided by the manifest restored the card to what it describes
+        if repair.is_none() {
+            self.write_manifest(&dest_path, &archive_path, release.as_ref(), report.files.clone()).await;
+        }
+        let verified = if self.options.verify_after_install {
+            self.verify_card(&dest_path, &report.files).await
+        } else {
+            Ok(())
+        };
+        // The log on the card is most useful when the check failed
+        self.write_debug_log(&dest_path);
+        verified
+    }
+
+    // -------------------------------------------------------------------------
+    // Event helpers
+    // -------------------------------------------------------------------------
+
+    fn emit(&self, event: PipelineEvent) {
+        let _ = self.events.send(event);
+    }
+
+    fn log(&self, message: &str) {
+        self.emit(PipelineEvent::Log(message.to_string()));
+        // Also log to debug file/console
+        crate::debug::log(message);
+    }
+
+    fn set_progress(&self, current: u64, total: u64, message: &str) {
+        self.emit(PipelineEvent::Progress { current, total, message: message.to_string() });
+    }
+
+    fn start_stage(&self, stage: InstallStage) {
+        self.emit(PipelineEvent::StageStarted(stage));
+    }
+
+    /// Append a line to install_log.txt on the card (no-op until the card is mounted)
+    fn card_log(&self, message: &str) {
+        use std::io::Write;
+        let Some(path) = &self.card_log_path else {
+            return;
+        };
+        if let Ok(mut file) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let _ = writeln!(file, "[{}] {}", timestamp, message);
+        }
+    }
+
+    /// Turn an error from a step into a PipelineError, treating anything that
+    /// happened after the user pressed Cancel as a cancellation
+    fn fail(&self, stage: InstallStage, message: String) -> PipelineError {
+        if self.cancel_token.is_cancelled() {
+            PipelineError::Cancelled
+        } else {
+            PipelineError::Failed { stage, message }
+        }
+    }
+
+    /// Forward a step's progress channel as pipeline events until the step drops its sender
+    fn forward<P: Send + 'static>(
+        &self,
+        mut rx: mpsc::UnboundedReceiver<P>,
+        map: fn(P) -> PipelineEvent,
+    ) -> tokio::task::JoinHandle<()> {
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            while let Some(progress) = rx.recv().await {
+                let _ = events.send(map(progress));
+            }
+        })
+    }
+
+    // -------------------------------------------------------------------------
+    // Stages
+    // -------------------------------------------------------------------------
+
+    fn log_start(&self) {
+        let drive = &self.options.drive;
+        let source = match &self.options.local_archive {
+            Some(path) => path.display().to_string(),
+            None => self.options.repo_name.clone(),
+        };
+        match self.options.mode {
+            InstallMode::Fresh if self.options.image.is_some() => {
+                self.log(&format!("Starting image build of {} using {}", drive.name, source))
+            }
+            InstallMode::Fresh => self.log(&format!("Starting installation to {} using {}", drive.name, source)),
+            InstallMode::Update => self.log(&format!("Starting update of {} using {}", drive.name, source)),
+            InstallMode::Download => self.log(&format!("Starting download of {}", source)),
+            InstallMode::Verify => self.log(&format!("Starting verification of {}", drive.name)),
+            InstallMode::Repair => self.log(&format!("Starting repair of {}", drive.name)),
+            InstallMode::Benchmark => self.log(&format!("Starting benchmark of {}", drive.name)),
+        }
+
+        // Log installation start to debug log
+        crate::debug::log_section(&format!("{} Started", self.options.mode.label()));
+        crate::debug::log(&format!("Drive: {} ({})", drive.name, drive.device_path));
+        crate::debug::log(&format!("Drive size: {} bytes", drive.size_bytes));
+        crate::debug::log(&format!("Mount path: {:?}", drive.mount_path));
+        crate::debug::log(&format!("Repository: {} ({})", self.options.repo_name, self.options.repo_url));
+
+        // Check if running as root on Linux
+        #[cfg(target_os = "linux")]
+        {
+            if unsafe { libc::geteuid() } == 0 {
+                crate::debug::log("WARNING: Running as root user");
+                if let Ok(sudo_user) = std::env::var("SUDO_USER") {
+                    crate::debug::log(&format!("Detected sudo execution by user: {}", sudo_user));
+                    self.emit(PipelineEvent::Log("Note: Running with sudo/root privileges".to_string()));
+                } else if let Ok(pkexec_uid) = std::env::var("PKEXEC_UID") {
+                    crate::debug::log(&format!("Detected pkexec execution by UID: {}", pkexec_uid));
+                    self.emit(PipelineEvent::Log("Note: Running with elevated privileges (pkexec)".to_string()));
+                } else {
+                    crate::debug::log("Running as actual root user (not via sudo/pkexec)");
+                    self.emit(PipelineEvent::Log("Note: Running as root user".to_string()));
+                }
+            }
+        }
+    }
+
+    /// Step 1: Fetch release info and pick the asset to install
+    async fn fetch_release(&self) -> Result<SelectedRelease, PipelineError> {
+        let stage = InstallStage::FetchingRelease;
+        self.start_stage(stage);
+        let source = ReleaseSource::parse(&self.options.repo_url).map_err(|e| self.fail(stage, e))?;
+        match &self.options.tag {
+            Some(tag) => self.log(&format!("Fetching release {} from {}...", tag, source.service_name())),
+            None => self.log(&format!("Fetching latest release from {}...", source.service_name())),
+        }
+        crate::debug::log_section("Fetching Release");
+        crate::debug::log(&format!("Repository URL: {}", self.options.repo_url));
+        crate::debug::log(&format!("Release source: {}", source));
+        self.set_progress(0, 100, "Fetching release info...");
+
+        let on_retry = |notice: &RetryNotice| {
+            self.log(&format!("Could not fetch release info: {}", notice.error));
+            self.emit(PipelineEvent::Status(notice.status()));
+        };
+        let lookup = async {
+            match &self.options.tag {
+                Some(tag) => source.release_by_tag(tag, &self.cancel_token, &on_retry).await,
+                None => source.latest_release(&self.cancel_token, &on_retry).await,
+            }
+        };
+        let release = tokio::select! {
+            _ = self.cancel_token.cancelled() => return Err(PipelineError::Cancelled),
+            result = lookup => result.map_err(|e| self.fail(stage, e))?,
+        };
+
+        let asset = find_release_asset(&release)
+            .cloned()
+            .ok_or_else(|| self.fail(stage, format!("No {} file found in release", ASSET_EXTENSION)))?;
+
+        self.log(&format!("Found release: {} ({})", release.tag_name, asset.name));
+        crate::debug::log(&format!("Release: {}", release.tag_name));
+        crate::debug::log(&format!("Asset: {} ({} bytes)", asset.name, asset.size));
+
+        let expected_digest = find_expected_digest(&release, &asset, &self.cancel_token, &on_retry)
+            .await
+            .map_err(|e| self.fail(stage, format!("Could not read the published checksum: {}", e)))?;
+
+        match &expected_digest {
+            Some(expected) => {
+                crate::debug::log(&format!("Expected SHA-256 ({}): {}", expected.source, expected.sha256));
+            }
+            None if REQUIRE_CHECKSUM => {
+                return Err(self.fail(stage, format!(
+                    "Release {} does not publish a SHA-256 checksum for {}",
+                    release.tag_name, asset.name
+                )));
+            }
+            None => self.log("Warning: this release does not publish a SHA-256 checksum; the download will not be verified"),
+        }
+
+        Ok(SelectedRelease {
+            tag: release.tag_name,
+            asset,
+            expected_digest,
+        })
+    }
+
+    /// Make sure the temp location can hold the download or the extracted files
+    fn check_disk_space(&self, stage: InstallStage, temp_dir: &Path, required_space: u64) -> Result<(), PipelineError> {
+        crate::debug::log(&format!("Cache/temp directory: {:?}", temp_dir));
+
+        let available_space = get_available_disk_space(temp_dir);
+
+        crate::debug::log(&format!("Required disk space: {} MB", required_space / 1_048_576));
+        crate::debug::log(&format!("Available disk space: {} MB", available_space / 1_048_576));
+
+        if available_space < required_space {
+            return Err(self.fail(stage, format!(
+                "Insufficient disk space. Need {} MB, but only {} MB available in cache directory. Please free up disk space (or clear the download cache) and try again.",
+                required_space / 1_048_576,
+                available_space / 1_048_576
+            )));
+        }
+
+        self.log(&format!("Disk space check passed: {} MB available", available_space / 1_048_576));
+        Ok(())
+    }
+
+    /// Steps 2-3: Get a verified copy of the asset through the download cache
+    async fn obtain_cached(&mut self, release: &SelectedRelease, temp_dir: &Path) -> Result<PathBuf, PipelineError> {
+        let asset = &release.asset;
+        let mut cache = DownloadCache::open_default();
+        let key = CacheKey::new(
+            &self.options.repo_url,
+            &release.tag,
+            asset,
+            release.expected_digest.as_ref().map(|d| d.sha256.as_str()),
+        );
+
+        let (archive_path, cached) = match cache.lookup(&key) {
+            Some(path) => (path, true),
+            None => (cache.path_for(&key), false),
+        };
+
+        if cached {
+            self.log(&format!("Using cached download of {} ({})", release.tag, asset.name));
+            crate::debug::log(&format!("Cached file: {:?}", archive_path));
+        } else {
+            // Room for the extraction is checked once the archive can be read
+            self.check_disk_space(InstallStage::Downloading, temp_dir, asset.size)?;
+            if let Some(parent) = archive_path.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| {
+                    self.fail(InstallStage::Downloading, format!("Failed to create cache folder: {}", e))
+                })?;
+            }
+            self.download(asset, &archive_path).await?;
+        }
+
+        if let Some(expected) = &release.expected_digest {
+            if let Err(e) = self.verify(&archive_path, expected).await {
+                // A file that fails the check must never be reused, but cancelling the
+                // check of a good cached copy is no reason to throw it away
+                if !(cached && e == PipelineError::Cancelled) {
+                    cache.remove(&key);
+                }
+                return Err(e);
+            }
+        }
+
+        cache.insert(key);
+        Ok(archive_path)
+    }
+
+    /// Steps 2-3: Download into the temp folder and delete it after the install
+    async fn obtain_uncached(&mut self, release: &SelectedRelease, temp_dir: &Path) -> Result<PathBuf, PipelineError> {
+        self.check_disk_space(InstallStage::Downloading, temp_dir, release.asset.size)?;
+
+        let archive_path = temp_dir.join(&release.asset.name);
+        self.workspace.download_path = Some(archive_path.clone());
+        self.download(&release.asset, &archive_path).await?;
+
+        if let Some(expected) = &release.expected_digest {
+            self.verify(&archive_path, expected).await?;
+        }
+        Ok(archive_path)
+    }
+
+    /// Step 2: Download the release asset to `download_path`
+    async fn download(&self, asset: &Asset, download_path: &Path) -> Result<(), PipelineError> {
+        let stage = InstallStage::Downloading;
+        self.start_stage(stage);
+        let size_mb = asset.size as f64 / 1_048_576.0;
+        self.log(&format!("Downloading release ({:.1} MB)...", size_mb));
+        crate::debug::log_section("Downloading Release");
+        crate::debug::log(&format!("Download path: {:?}", download_path));
+
+        let (dl_tx, dl_rx) = mpsc::unbounded_channel::<DownloadProgress>();
+        let dl_handle = self.forward(dl_rx, download_event);
+
+        download_asset(asset, download_path, dl_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+
+        let _ = dl_handle.await;
+        self.log("Download complete");
+        Ok(())
+    }
+
+    /// Step 3: Check the download against the published SHA-256
+    async fn verify(&self, archive_path: &Path, expected: &ExpectedDigest) -> Result<(), PipelineError> {
+        let stage = InstallStage::Verifying;
+        self.start_stage(stage);
+        self.log(&format!("Verifying download (SHA-256 from {})...", expected.source));
+        crate::debug::log_section("Verifying Download");
+
+        let (verify_tx, verify_rx) = mpsc::unbounded_channel::<VerifyProgress>();
+        let verify_handle = self.forward(verify_rx, verify_event);
+
+        verify_file(archive_path, &expected.sha256, verify_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+
+        let _ = verify_handle.await;
+        self.log("Checksum verified");
+        Ok(())
+    }
+
+    /// Step 4 (optional): make sure the card really holds what it reports. This
+    /// overwrites the card, so it only runs right before formatting
+    async fn check_capacity(&self) -> Result<(), PipelineError> {
+        let stage = InstallStage::CheckingCapacity;
+        let drive = &self.options.drive;
+        self.start_stage(stage);
+        self.log(&format!("Checking the real capacity of {}...", drive.name));
+        self.set_progress(0, 100, "Checking capacity...");
+
+        let (cap_tx, cap_rx) = mpsc::unbounded_channel::<CapacityProgress>();
+        let cap_handle = self.forward(cap_rx, capacity_event);
+        let report = check_card_capacity(&drive.device_path, cap_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+        let _ = cap_handle.await;
+
+        if !report.passed() {
+            return Err(self.fail(stage, format!(
+                "{} reports {} MB, but only about {} MB of it hold data ({} of {} test blocks failed). The card is counterfeit or failing; use another one",
+                drive.name,
+                report.reported_bytes / 1_048_576,
+                report.usable_bytes / 1_048_576,
+                report.bad_blocks,
+                report.tested_blocks
+            )));
+        }
+        self.log(&format!("Capacity check passed: all {} test blocks read back intact", report.tested_blocks));
+        Ok(())
+    }
+
+    /// Step 4: Format the card and return where it is mounted afterwards
+    #[cfg(not(target_os = "linux"))]
+    async fn format_drive(&mut self) -> Result<PathBuf, PipelineError> {
+        let stage = InstallStage::Formatting;
+        let drive = self.options.drive.clone();
+        self.start_stage(stage);
+        self.log(&format!("Formatting {}...", drive.name));
+        crate::debug::log_section("Formatting Drive");
+        self.set_progress(0, 100, "Formatting drive...");
+
+        let (fmt_tx, fmt_rx) = mpsc::unbounded_channel::<FormatProgress>();
+        let fmt_handle = self.forward(fmt_rx, format_event);
+
+        format_drive_fat32(&drive.device_path, &self.options.volume_label, fmt_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+
+        let _ = fmt_handle.await;
+        self.log("Format complete");
+
+        // Get the destination path for extraction (platform-specific)
+        crate::debug::log("Getting mount path after format...");
+        let dest_path = get_mount_path_after_format(&drive, &self.options.volume_label)
+            .await
+            .map_err(|e| self.fail(stage, format!("Error getting mount path: {}", e)))?;
+
+        self.log(&format!("Destination: {}", dest_path.display()));
+        crate::debug::log(&format!("Mount path: {:?}", dest_path));
+
+        // Keep a log file on the SD card for debugging
+        self.card_log_path = Some(dest_path.join("install_log.txt"));
+        Ok(dest_path)
+    }
+
+    /// Step 4 (update, verify, repair): find where the card is mounted and make sure it holds a release
+    fn check_card(&self) -> Result<PathBuf, PipelineError> {
+        let stage = InstallStage::CheckingCard;
+        let drive = &self.options.drive;
+        self.start_stage(stage);
+        crate::debug::log_section("Checking Card");
+
+        let card = self.mounted_card(stage)?;
+
+        let has_release = RELEASE_MARKER_PATHS
+            .iter()
+            .any(|marker| card.join(marker.trim_end_matches('/')).exists());
+        if !has_release {
+            return Err(self.fail(stage, format!(
+                "No {} install found on {}; use a fresh install instead",
+                APP_NAME,
+                drive.name
+            )));
+        }
+        Ok(card)
+    }
+
+    /// Where the card is mounted
+    fn mounted_card(&self, stage: InstallStage) -> Result<PathBuf, PipelineError> {
+        let drive = &self.options.drive;
+        let card = drive
+            .mount_path
+            .clone()
+            .filter(|path| path.is_dir())
+            .ok_or_else(|| self.fail(stage, format!("{} is not mounted; mount it and try again", drive.name)))?;
+        self.log(&format!("Using the card at {}", card.display()));
+        crate::debug::log(&format!("Mount path: {:?}", card));
+        Ok(card)
+    }
+
+    /// Benchmark: time reads and writes of a test file on the mounted card. Any card
+    /// will do, it doesn't have to hold a release
+    async fn benchmark(&self) -> Result<(), PipelineError> {
+        let stage = InstallStage::Benchmarking;
+        self.start_stage(stage);
+        let card = self.mounted_card(stage)?;
+        self.log("Timing reads and writes of a test file on the card...");
+        self.set_progress(0, 100, "Benchmarking...");
+
+        let (bench_tx, bench_rx) = mpsc::unbounded_channel::<BenchmarkProgress>();
+        let bench_handle = self.forward(bench_rx, benchmark_event);
+        let report = benchmark_card(&card, bench_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+        let _ = bench_handle.await;
+
+        for line in report.lines() {
+            self.log(&line);
+        }
+        self.emit(PipelineEvent::Benchmark(report));
+        Ok(())
+    }
+
+    /// Report the parts of a downloaded release for the component picker
+    fn list_components(&self, archive_path: &Path) -> Result<(), PipelineError> {
+        let stage = InstallStage::CheckingArchive;
+        self.start_stage(stage);
+        let entries = list_entries(archive_path).map_err(|e| self.fail(stage, e))?;
+        check_release_layout(&entries).map_err(|e| self.fail(stage, e))?;
+        let components = components(&entries);
+        self.log(&format!("The release has {} components", components.len()));
+        self.emit(PipelineEvent::Components(components));
+        Ok(())
+    }
+
+    /// Step 4 (repair): read the card back against its manifest and pin the release it
+    /// was installed from. None if the card has no manifest; the chosen release is then
+    /// compared with the card file by file while extracting
+    async fn plan_repair(&mut self, card: &Path) -> Result<Option<RepairPlan>, PipelineError> {
+        let manifest = match Manifest::read_from(card) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                self.log(&format!("{}. Comparing the card with the selected release instead", e));
+                return Ok(None);
+            }
+        };
+        self.log_manifest(&manifest);
+
+        if self.options.local_archive.is_none() {
+            let Some(repo) = &manifest.repo else {
+                return Err(self.fail(InstallStage::CheckingCard, format!(
+                    "The card was installed from a local archive; choose {} to repair it",
+                    manifest.asset
+                )));
+            };
+            self.options.repo_url = repo.clone();
+            self.options.tag = manifest.tag.clone();
+        }
+        // What was left out when installing stays left out
+        self.options.excluded_components = manifest.skipped.clone();
+
+        let report = self.check_files(card, &manifest.files).await?;
+        let damaged = report.missing.into_iter().chain(report.corrupted).collect();
+        Ok(Some(RepairPlan { manifest, damaged }))
+    }
+
+    /// Tell the user which release a manifest describes
+    fn log_manifest(&self, manifest: &Manifest) {
+        self.log(&format!(
+            "Installed: {} {} ({}) by {} {}",
+            manifest.repo.as_deref().unwrap_or("local archive"),
+            manifest.tag.as_deref().unwrap_or(""),
+            manifest.asset,
+            manifest.installer,
+            manifest.installer_version
+        ));
+    }
+
+    /// Make sure the archive opens, looks like a release and fits on the card (and
+    /// in the temp folder, if it is extracted there) before the card is wiped.
+    /// `mounted_card` is the card being updated, None for a fresh install
+    fn check_archive(
+        &self,
+        archive_path: &Path,
+        temp_dir: &Path,
+        mounted_card: Option<&Path>,
+    ) -> Result<(), PipelineError> {
+        let stage = InstallStage::CheckingArchive;
+        self.start_stage(stage);
+        self.log(&format!("Checking {}...", archive_path.display()));
+        crate::debug::log_section("Checking Archive");
+
+        let kind = detect_kind(archive_path).map_err(|e| self.fail(stage, e))?;
+        let entries = list_entries(archive_path).map_err(|e| self.fail(stage, e))?;
+        check_release_layout(&entries).map_err(|e| self.fail(stage, e))?;
+
+        // Everything below only looks at what will actually be installed
+        let excluded = &self.options.excluded_components;
+        check_exclusions(&entries, excluded).map_err(|e| self.fail(stage, e))?;
+        for component in excluded {
+            self.log(&format!("Skipping {}", component));
+        }
+        let entries: Vec<_> = entries.into_iter().filter(|e| !is_excluded(&e.path, excluded)).collect();
+
+        let issues = check_entry_names(&entries);
+        if !issues.is_empty() {
+            self.log(&format!("{} archive entries can't be installed safely:", issues.len()));
+            for issue in issues.iter().take(MAX_REPORTED_ISSUES) {
+                self.log(&format!("  {}", issue));
+            }
+            if issues.len() > MAX_REPORTED_ISSUES {
+                self.log(&format!("  ...and {} more (see the debug log)", issues.len() - MAX_REPORTED_ISSUES));
+            }
+            for issue in &issues {
+                crate::debug::log(&format!("Unsafe entry: {}", issue));
+            }
+            return Err(self.fail(stage, format!(
+                "{} archive entries can't be written safely to the card (first: {})",
+                issues.len(),
+                issues[0]
+            )));
+        }
+
+        let summary = summarize(&entries);
+        self.log(&format!(
+            "Archive OK ({}, {} files, {:.1} MB uncompressed)",
+            kind.name(),
+            summary.total_files,
+            summary.total_bytes as f64 / 1_048_576.0
+        ));
+        for item in &summary.top_level {
+            self.log(&format!(
+                "  {} ({} files, {:.1} MB)",
+                item.name,
+                item.files,
+                item.bytes as f64 / 1_048_576.0
+            ));
+        }
+
+        // Every file takes up whole clusters, so small files need more than their size
+        let card_size = self.options.drive.size_bytes;
+        if let Some(card) = mounted_card {
+            // Only files that grow or are new take up more room
+            let required: u64 = entries
+                .iter()
+                .filter(|e| !e.is_dir)
+                .map(|e| {
+                    let existing = std::fs::metadata(card.join(&e.path)).map(|m| m.len()).ok();
+                    match existing {
+                        Some(_) if is_excluded(&e.path, &self.options.preserve) => 0,
+                        Some(size) => e.size.saturating_sub(size),
+                        None => e.size,
+                    }
+                })
+                .sum();
+            let available = get_available_disk_space(card);
+            crate::debug::log(&format!("Space needed on card: {} MB of {} MB free", required / 1_048_576, available / 1_048_576));
+            if required > available {
+                return Err(self.fail(stage, format!(
+                    "The update needs {} MB more on the card, but only {} MB is free",
+                    required / 1_048_576,
+                    available / 1_048_576
+                )));
+            }
+        } else if card_size > 0 {
+            let required = size_on_card(&entries, fat32::cluster_size(card_size));
+            let usable = fat32::usable_bytes(card_size);
+            crate::debug::log(&format!("Space needed on card: {} MB of {} MB", required / 1_048_576, usable / 1_048_576));
+            if required > usable {
+                return Err(self.fail(stage, format!(
+                    "The release needs {} MB on the card, but {} only holds about {} MB once formatted",
+                    required / 1_048_576,
+                    self.options.drive.name,
+                    usable / 1_048_576
+                )));
+            }
+        }
+
+        // Images are always filled straight from the archive
+        if !self.options.extract_to_card && mounted_card.is_none() && self.options.image.is_none() {
+            self.check_disk_space(stage, &extract_base_dir(temp_dir), summary.total_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Create an empty temp folder on the local PC to extract into before copying
+    fn create_extract_dir(&mut self, base_dir: &Path) -> Result<PathBuf, PipelineError> {
+        let extract_dir = base_dir.join(format!("{}_extract", TEMP_PREFIX));
+        crate::debug::log(&format!("Temp extract dir: {:?}", extract_dir));
+
+        // Clean up any previous extraction
+        let _ = std::fs::remove_dir_all(&extract_dir);
+        self.workspace.extract_dir = Some(extract_dir.clone());
+        std::fs::create_dir_all(&extract_dir).map_err(|e| {
+            self.fail(InstallStage::Extracting, format!("Failed to create temp extract dir: {}", e))
+        })?;
+        Ok(extract_dir)
+    }
+
+    /// Step 5: Extract the archive into `dest_dir` (the card, or a local temp folder);
+    /// `only` limits it to those files when not empty
+    async fn extract(&self, archive_path: &Path, dest_dir: &Path, only: &[String]) -> Result<ExtractReport, PipelineError> {
+        let stage = InstallStage::Extracting;
+        self.start_stage(stage);
+        crate::debug::log_section("Extracting Files");
+        crate::debug::log(&format!("Extracting to: {:?}", dest_dir));
+        self.set_progress(0, 100, "Extracting files...");
+
+        let (ext_tx, ext_rx) = mpsc::unbounded_channel::<ExtractProgress>();
+        let ext_handle = self.forward(ext_rx, extract_event);
+
+        self.card_log(&format!("Extracting {:?} -> {:?}", archive_path, dest_dir));
+        let options = ExtractOptions {
+            excluded: self.options.excluded_components.clone(),
+            update: self.options.mode.in_place(),
+            preserve: self.options.preserve.clone(),
+            only: only.to_vec(),
+            installed: self.installed_files(dest_dir),
+        };
+        let report = extract_archive(archive_path, dest_dir, &options, ext_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e.to_string()))?;
+
+        let _ = ext_handle.await;
+        self.log("Extraction complete");
+        Ok(report)
+    }
+
+    /// What the previous release put on the card being updated, according to its manifest
+    fn installed_files(&self, card: &Path) -> Vec<String> {
+        if self.options.mode != InstallMode::Update {
+            return Vec::new();
+        }
+        match Manifest::read_from(card) {
+            Ok(manifest) => manifest.files.into_iter().map(|f| f.path).collect(),
+            Err(e) => {
+                crate::debug::log(&format!("Can't look for files dropped from the release: {}", e));
+                Vec::new()
+            }
+        }
+    }
+
+    /// Tell the user what an update changed on the card
+    fn log_changes(&self, report: &ExtractReport) {
+        match self.options.mode {
+            InstallMode::Update => self.log(&format!(
+                "Update complete: {} added, {} replaced, {} unchanged, {} user files kept",
+                report.added.len(),
+                report.replaced.len(),
+                report.unchanged.len(),
+                report.preserved.len()
+            )),
+            InstallMode::Repair => self.log(&format!(
+                "Repair complete: {} missing files restored, {} damaged files replaced, {} intact, {} user files kept",
+                report.added.len(),
+                report.replaced.len(),
+                report.unchanged.len(),
+                report.preserved.len()
+            )),
+            InstallMode::Fresh | InstallMode::Verify | InstallMode::Benchmark | InstallMode::Download => return,
+        }
+        let changes = report
+            .added
+            .iter()
+            .map(|path| format!("  added    {}", path))
+            .chain(report.replaced.iter().map(|path| format!("  replaced {}", path)));
+        for (i, line) in changes.enumerate() {
+            if i < MAX_LISTED_CHANGES {
+                self.log(&line);
+            } else {
+                crate::debug::log(&line);
+            }
+        }
+        let changed = report.added.len() + report.replaced.len();
+        if changed > MAX_LISTED_CHANGES {
+            self.log(&format!("  ...and {} more (see the debug log)", changed - MAX_LISTED_CHANGES));
+        }
+        for path in &report.preserved {
+            crate::debug::log(&format!("  kept     {}", path));
+        }
+        if !report.stale.is_empty() {
+            self.log(&format!(
+                "{} files from the previous release are no longer part of it and were left on the card:",
+                report.stale.len()
+            ));
+            for (i, path) in report.stale.iter().enumerate() {
+                let line = format!("  stale    {}", path);
+                if i < MAX_LISTED_CHANGES {
+                    self.log(&line);
+                } else {
+                    crate::debug::log(&line);
+                }
+            }
+            if report.stale.len() > MAX_LISTED_CHANGES {
+                self.log(&format!("  ...and {} more (see the debug log)", report.stale.len() - MAX_LISTED_CHANGES));
+            }
+        }
+    }
+
+    /// Step 6: Copy the extracted files to the SD card
+    async fn copy_files(&self, source_dir: &Path, dest_path: &Path) -> Result<(), PipelineError> {
+        let stage = InstallStage::Copying;
+        self.start_stage(stage);
+        self.log("Copying files to SD card...");
+        crate::debug::log_section("Copying Files");
+        self.set_progress(0, 100, "Copying files...");
+
+        let (copy_tx, copy_rx) = mpsc::unbounded_channel::<CopyProgress>();
+        let copy_handle = self.forward(copy_rx, copy_event);
+
+        self.card_log(&format!("Copying files: {:?} -> {:?}", source_dir, dest_path));
+        copy_directory_with_progress(source_dir, dest_path, copy_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+
+        let _ = copy_handle.await;
+        self.log("Copy complete");
+        Ok(())
+    }
+
+    /// SHA-256 of the release archive: the published digest it was verified against, or
+    /// its own hash if there is none
+    async fn archive_sha256(&self, archive_path: &Path, release: Option<&SelectedRelease>) -> Result<String, String> {
+        if let Some(expected) = release.and_then(|r| r.expected_digest.as_ref()) {
+            return Ok(expected.sha256.clone());
+        }
+        let (hash_tx, _hash_rx) = mpsc::unbounded_channel();
+        hash_file(archive_path, hash_tx, self.cancel_token.clone()).await
+    }
+
+    /// The manifest of an install of `archive_path` that put `files` on the card
+    async fn build_manifest(
+        &self,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+        files: Vec<InstalledFile>,
+    ) -> Result<Manifest, String> {
+        let asset_sha256 = self.archive_sha256(archive_path, release).await?;
+        let asset = match release {
+            Some(release) => release.asset.name.clone(),
+            None => archive_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        };
+        let mut manifest = Manifest::new(&asset, &asset_sha256, files);
+        if let Some(release) = release {
+            manifest.repo = Some(self.options.repo_url.clone());
+            manifest.tag = Some(release.tag.clone());
+        }
+        manifest.skipped = self.options.excluded_components.clone();
+        Ok(manifest)
+    }
+
+    /// Record the release and every installed file on the SD card (failure is only a warning)
+    async fn write_manifest(
+        &self,
+        dest_path: &Path,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+        files: Vec<InstalledFile>,
+    ) {
+        self.log("Writing install manifest to SD card...");
+        let manifest = match self.build_manifest(archive_path, release, files).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                self.log(&format!("Warning: Could not write install manifest: {}", e));
+                return;
+            }
+        };
+
+        match manifest.write_to(dest_path) {
+            Ok(path) => {
+                self.log(&format!("Manifest saved to: {}", path.display()));
+                crate::debug::log(&format!("Manifest lists {} files", manifest.files.len()));
+            }
+            Err(e) => self.log(&format!("Warning: Could not write install manifest: {}", e)),
+        }
+    }
+
+    /// Step 7: Read the installed files back from the card and compare them with `files`
+    async fn verify_card(&self, card: &Path, files: &[InstalledFile]) -> Result<(), PipelineError> {
+        let report = self.check_files(card, files).await?;
+        self.require_passed(&report)
+    }
+
+    /// Step 7 (card written without mounting): the same, read through the card's FAT32 volume
+    #[cfg(target_os = "linux")]
+    async fn verify_unmounted(&self, device: &Path, files: &[InstalledFile]) -> Result<(), PipelineError> {
+        let token = self.cancel_token.clone();
+        let report = self
+            .check_with(files, |tx| verify_volume(device, fat32::PARTITION_OFFSET, files, tx, token))
+            .await?;
+        self.require_passed(&report)
+    }
+
+    fn require_passed(&self, report: &VerifyReport) -> Result<(), PipelineError> {
+        if !report.passed() {
+            return Err(self.fail(InstallStage::VerifyingCard, format!(
+                "{} files on the card are missing or don't match the release. The card may be failing or counterfeit",
+                report.missing.len() + report.corrupted.len()
+            )));
+        }
+        self.log("Verification passed");
+        Ok(())
+    }
+
+    /// Read `files` back from the mounted card and log what doesn't match
+    async fn check_files(&self, card: &Path, files: &[InstalledFile]) -> Result<VerifyReport, PipelineError> {
+        let token = self.cancel_token.clone();
+        self.check_with(files, |tx| verify_card(card, files, &self.options.preserve, tx, token))
+            .await
+    }
+
+    /// Run `check` on `files` as the verify stage and log what doesn't match
+    async fn check_with<F>(
+        &self,
+        files: &[InstalledFile],
+        check: impl FnOnce(mpsc::UnboundedSender<CardVerifyProgress>) -> F,
+    ) -> Result<VerifyReport, PipelineError>
+    where
+        F: std::future::Future<Output = Result<VerifyReport, String>>,
+    {
+        let stage = InstallStage::VerifyingCard;
+        self.start_stage(stage);
+        self.log(&format!("Verifying {} files on the SD card...", files.len()));
+        self.set_progress(0, 100, "Verifying card...");
+
+        let (verify_tx, verify_rx) = mpsc::unbounded_channel::<CardVerifyProgress>();
+        let verify_handle = self.forward(verify_rx, card_verify_event);
+
+        let report = check(verify_tx).await.map_err(|e| self.fail(stage, e))?;
+        let _ = verify_handle.await;
+
+        self.log(&format!(
+            "Checked {} files: {} missing, {} corrupted, {} not part of the release",
+            report.checked,
+            report.missing.len(),
+            report.corrupted.len(),
+            report.extra.len()
+        ));
+        let problems = report
+            .missing
+            .iter()
+            .map(|path| format!("  missing   {}", path))
+            .chain(report.corrupted.iter().map(|path| format!("  corrupted {}", path)))
+            .chain(report.extra.iter().map(|path| format!("  extra     {}", path)));
+        for (i, line) in problems.enumerate() {
+            if i < MAX_REPORTED_ISSUES {
+                self.log(&line);
+            } else {
+                crate::debug::log(&line);
+            }
+        }
+        let listed = report.missing.len() + report.corrupted.len() + report.extra.len();
+        if listed > MAX_REPORTED_ISSUES {
+            self.log(&format!("  ...and {} more (see the debug log)", listed - MAX_REPORTED_ISSUES));
+        }
+        Ok(report)
+    }
+
+    /// Steps 4-6 (image): create and format the image file, extract the release into it
+    /// and compress it. A half-built image is deleted
+    async fn build_image(
+        &self,
+        image: &ImageTarget,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+    ) -> Result<(), PipelineError> {
+        let result = self.write_image(image, archive_path, release).await;
+        if result.is_err() {
+            let _ = std::fs::remove_file(&image.path);
+            return result;
+        }
+
+        if image.compress {
+            let stage = InstallStage::Compressing;
+            self.start_stage(stage);
+            self.log("Compressing image...");
+            self.set_progress(0, 100, "Compressing image...");
+
+            let (gz_tx, gz_rx) = mpsc::unbounded_channel::<CompressProgress>();
+            let gz_handle = self.forward(gz_rx, compress_event);
+            compress_image(&image.path, gz_tx, self.cancel_token.clone())
+                .await
+                .map_err(|e| self.fail(stage, e))?;
+            let _ = gz_handle.await;
+        }
+        self.log(&format!("Image saved to: {}", image.output_path().display()));
+        Ok(())
+    }
+
+    async fn write_image(
+        &self,
+        image: &ImageTarget,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+    ) -> Result<(), PipelineError> {
+        let stage = InstallStage::Formatting;
+        self.start_stage(stage);
+        self.log(&format!(
+            "Creating {} ({} MB)...",
+            image.path.display(),
+            image.size_bytes / 1_048_576
+        ));
+        self.set_progress(0, 100, "Formatting image...");
+
+        let (fmt_tx, fmt_rx) = mpsc::unbounded_channel::<FormatProgress>();
+        let fmt_handle = self.forward(fmt_rx, format_event);
+        let volume = create_image(image, &self.options.volume_label, fmt_tx)
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+        let _ = fmt_handle.await;
+        self.log("Format complete");
+
+        let (_, volume) = self.fill_volume(volume, archive_path, release, "Extracting files into the image...").await?;
+        finish_image(volume).await.map_err(|e| self.fail(InstallStage::Extracting, e))
+    }
+
+    /// Extract the release into the empty `volume` and add the manifest; returns the
+    /// installed files and the volume, still to be finished
+    async fn fill_volume(
+        &self,
+        volume: fat32::Volume<std::fs::File>,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+        message: &str,
+    ) -> Result<(Vec<InstalledFile>, fat32::Volume<std::fs::File>), PipelineError> {
+        let stage = InstallStage::Extracting;
+        self.start_stage(stage);
+        self.log(message);
+        crate::debug::log_section("Extracting Files");
+        self.set_progress(0, 100, "Extracting files...");
+
+        let (ext_tx, ext_rx) = mpsc::unbounded_channel::<ExtractProgress>();
+        let ext_handle = self.forward(ext_rx, extract_event);
+        let options = ExtractOptions {
+            excluded: self.options.excluded_components.clone(),
+            ..Default::default()
+        };
+        let (report, mut volume) =
+            extract_archive_to_volume(archive_path, volume, &options, ext_tx, self.cancel_token.clone())
+                .await
+                .map_err(|e| self.fail(stage, e.to_string()))?;
+        let _ = ext_handle.await;
+        self.log("Extraction complete");
+        self.write_volume_manifest(&mut volume, archive_path, release, &report.files).await?;
+        Ok((report.files, volume))
+    }
+
+    /// Write the manifest into the volume, so cards flashed from an image or written
+    /// without mounting can be verified and repaired like installed ones
+    async fn write_volume_manifest(
+        &self,
+        volume: &mut fat32::Volume<std::fs::File>,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+        files: &[InstalledFile],
+    ) -> Result<(), PipelineError> {
+        match self.build_manifest(archive_path, release, files.to_vec()).await.and_then(|m| m.to_json()) {
+            Ok(json) => volume.write_file(MANIFEST_FILE_NAME, json.as_bytes()).map_err(|e| {
+                self.fail(InstallStage::Extracting, format!("Failed to write {}: {}", MANIFEST_FILE_NAME, e))
+            }),
+            Err(e) => {
+                self.log(&format!("Warning: Could not write install manifest: {}", e));
+                Ok(())
+            }
+        }
+    }
+
+    /// Steps 4-7 (fresh install on Linux): format the card, write the release straight
+    /// into its FAT32 volume and read it back, without ever mounting it
+    #[cfg(target_os = "linux")]
+    async fn install_unmounted(
+        &mut self,
+        archive_path: &Path,
+        release: Option<&SelectedRelease>,
+        temp_dir: &Path,
+    ) -> Result<(), PipelineError> {
+        let stage = InstallStage::Formatting;
+        let drive = self.options.drive.clone();
+        self.start_stage(stage);
+        self.log(&format!("Formatting {}...", drive.name));
+        crate::debug::log_section("Formatting Drive");
+        self.set_progress(0, 100, "Formatting drive...");
+
+        let (fmt_tx, fmt_rx) = mpsc::unbounded_channel::<FormatProgress>();
+        let fmt_handle = self.forward(fmt_rx, format_event);
+        let volume = format_card(&drive.device_path, &self.options.volume_label, fmt_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+        let _ = fmt_handle.await;
+        self.log("Format complete");
+
+        let (files, volume) = if self.options.extract_to_card {
+            self.fill_volume(volume, archive_path, release, "Extracting files to SD card...").await?
+        } else {
+            self.log("Extracting files to local temp folder...");
+            let extract_dir = self.create_extract_dir(&extract_base_dir(temp_dir))?;
+            let report = self.extract(archive_path, &extract_dir, &[]).await?;
+            let mut volume = self.copy_to_volume(&extract_dir, volume).await?;
+            self.write_volume_manifest(&mut volume, archive_path, release, &report.files).await?;
+            (report.files, volume)
+        };
+
+        let (verified, mut volume) = if self.options.verify_after_install {
+            let volume = flush_card(volume).await.map_err(|e| self.fail(InstallStage::Extracting, e))?;
+            (self.verify_unmounted(Path::new(&drive.device_path), &files).await, volume)
+        } else {
+            (Ok(()), volume)
+        };
+        // The log on the card is most useful when the check failed
+        let log_name = crate::debug::CARD_LOG_NAME;
+        match crate::debug::read_log()
+            .and_then(|log| volume.write_file(log_name, &log).map_err(|e| format!("Failed to write {}: {}", log_name, e)))
+        {
+            Ok(()) => self.log(&format!("Debug log saved to: {}", log_name)),
+            Err(e) => self.log(&format!("Warning: Could not copy debug log: {}", e)),
+        }
+        finish_card(volume).await.map_err(|e| self.fail(InstallStage::Extracting, e))?;
+        verified
+    }
+
+    /// Copy the files extracted to the temp folder into the card's volume
+    #[cfg(target_os = "linux")]
+    async fn copy_to_volume(
+        &self,
+        source_dir: &Path,
+        volume: fat32::Volume<std::fs::File>,
+    ) -> Result<fat32::Volume<std::fs::File>, PipelineError> {
+        let stage = InstallStage::Copying;
+        self.start_stage(stage);
+        self.log("Copying files to SD card...");
+        crate::debug::log_section("Copying Files");
+        self.set_progress(0, 100, "Copying files...");
+
+        let (copy_tx, copy_rx) = mpsc::unbounded_channel::<CopyProgress>();
+        let copy_handle = self.forward(copy_rx, copy_event);
+        let volume = copy_directory_to_volume(source_dir, volume, copy_tx, self.cancel_token.clone())
+            .await
+            .map_err(|e| self.fail(stage, e))?;
+        let _ = copy_handle.await;
+        self.log("Copy complete");
+        Ok(volume)
+    }
+
+    /// Copy the debug log to the SD card (failure is only a warning)
+    fn write_debug_log(&self, dest_path: &Path) {
+        self.log("Writing debug log to SD card...");
+        match crate::debug::copy_log_to(dest_path) {
+            Ok(log_path) => self.log(&format!("Debug log saved to: {}", log_path.display())),
+            Err(e) => self.log(&format!("Warning: Could not copy debug log: {}", e)),
+        }
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Step progress -> pipeline events
+// -----------------------------------------------------------------------------
+
+fn format_event(progress: FormatProgress) -> PipelineEvent {
+    let status = |s: &str| PipelineEvent::Status(s.to_string());
+    match progress {
+        FormatProgress::Started => status("Starting format..."),
+        FormatProgress::Unmounting => status("Unmounting drive..."),
+        #[cfg(not(target_os = "macos"))]
+        FormatProgress::CleaningDisk => status("Cleaning disk..."),
+        #[cfg(not(target_os = "macos"))]
+        FormatProgress::CreatingPartition => status("Creating partition..."),
+        FormatProgress::Formatting => status("Formatting to FAT32..."),
+        FormatProgress::Progress { percent } => PipelineEvent::Progress {
+            current: percent as u64,
+            total: 100,
+            message: format!("Formatting... {}%", percent),
+        },
+        FormatProgress::Completed => PipelineEvent::Progress {
+            current: 100,
+            total: 100,
+            message: "Format complete".to_string(),
+        },
+        FormatProgress::Cancelled => status("Format cancelled"),
+        FormatProgress::Error(e) => PipelineEvent::Status(format!("Format error: {}", e)),
+    }
+}
+
+fn capacity_event(progress: CapacityProgress) -> PipelineEvent {
+    match progress {
+        CapacityProgress::Writing { done_blocks, total_blocks } => PipelineEvent::Progress {
+            current: done_blocks,
+            total: total_blocks * 2,
+            message: format!("Writing test blocks... {} / {}", done_blocks, total_blocks),
+        },
+        CapacityProgress::Reading { done_blocks, total_blocks } => PipelineEvent::Progress {
+            current: total_blocks + done_blocks,
+            total: total_blocks * 2,
+            message: format!("Reading test blocks... {} / {}", done_blocks, total_blocks),
+        },
+        CapacityProgress::Completed => PipelineEvent::Status("Capacity check complete".to_string()),
+        CapacityProgress::Cancelled => PipelineEvent::Status("Capacity check cancelled".to_string()),
+    }
+}
+
+fn benchmark_event(progress: BenchmarkProgress) -> PipelineEvent {
+    match progress {
+        BenchmarkProgress::Running { test, done, total } => PipelineEvent::Progress {
+            current: done,
+            total,
+            message: format!("{}... {}%", test, percent(done, total)),
+        },
+        BenchmarkProgress::Completed => PipelineEvent::Status("Benchmark complete".to_string()),
+        BenchmarkProgress::Cancelled => PipelineEvent::Status("Benchmark cancelled".to_string()),
+    }
+}
+
+fn compress_event(progress: CompressProgress) -> PipelineEvent {
+    match progress {
+        CompressProgress::Progress { read_bytes, total_bytes } => PipelineEvent::Progress {
+            current: read_bytes,
+            total: total_bytes,
+            message: format!(
+                "Compressing... {:.1} / {:.1} MB",
+                read_bytes as f64 / 1_048_576.0,
+                total_bytes as f64 / 1_048_576.0
+            ),
+        },
+        CompressProgress::Completed => PipelineEvent::Status("Compression complete".to_string()),
+        CompressProgress::Cancelled => PipelineEvent::Status("Compression cancelled".to_string()),
+    }
+}
+
+fn download_event(progress: DownloadProgress) -> PipelineEvent {
+    match progress {
+        DownloadProgress::Started { total_bytes, resumed_from, attempt } => PipelineEvent::Progress {
+            current: resumed_from,
+            total: total_bytes,
+            message: match (resumed_from, attempt) {
+                (0, 1) => "Downloading...".to_string(),
+                (0, _) => format!("Downloading (attempt {} of {})...", attempt, NETWORK_RETRY_ATTEMPTS),
+                (_, 1) => format!("Resuming download at {:.1} MB...", resumed_from as f64 / 1_048_576.0),
+                (_, _) => format!(
+                    "Resuming download at {:.1} MB (attempt {} of {})...",
+                    resumed_from as f64 / 1_048_576.0,
+                    attempt,
+                    NETWORK_RETRY_ATTEMPTS
+                ),
+            },
+        },
+        DownloadProgress::Retrying(notice) => {
+            PipelineEvent::Status(format!("Download interrupted. {}", notice.status()))
+        }
+        DownloadProgress::Progress { downloaded, total } => PipelineEvent::Progress {
+            current: downloaded,
+            total,
+            message: format!("Downloading... {}%", percent(downloaded, total)),
+        },
+        DownloadProgress::Completed => PipelineEvent::Status("Download complete".to_string()),
+        DownloadProgress::Cancelled => PipelineEvent::Status("Download cancelled".to_string()),
+        DownloadProgress::Error(e) => PipelineEvent::Status(format!("Download error: {}", e)),
+    }
+}
+
+fn verify_event(progress: VerifyProgress) -> PipelineEvent {
+    match progress {
+        VerifyProgress::Started { total_bytes } => PipelineEvent::Progress {
+            current: 0,
+            total: total_bytes,
+            message: "Verifying checksum...".to_string(),
+        },
+        VerifyProgress::Progress { checked, total } => PipelineEvent::Progress {
+            current: checked,
+            total,
+            message: format!("Verifying checksum... {}%", percent(checked, total)),
+        },
+        VerifyProgress::Completed => PipelineEvent::Status("Checksum verified".to_string()),
+        VerifyProgress::Cancelled => PipelineEvent::Status("Verification cancelled".to_string()),
+    }
+}
+
+fn extract_event(progress: ExtractProgress) -> PipelineEvent {
+    match progress {
+        ExtractProgress::Started { total_bytes, total_files } => PipelineEvent::Progress {
+            current: 0,
+            total: total_bytes,
+            message: format!("Extracting {} files...", total_files),
+        },
+        ExtractProgress::Progress { extracted_bytes, total_bytes, files_done, total_files, current_file } => {
+            let pct = percent(extracted_bytes, total_bytes);
+            let message = if current_file.is_empty() {
+                format!("Extracting... {}% ({}/{} files)", pct, files_done, total_files)
+            } else {
+                format!("{}% - {}", pct, truncate_path(&current_file, 40))
+            };
+            PipelineEvent::Progress { current: extracted_bytes, total: total_bytes, message }
+        }
+        ExtractProgress::Completed => PipelineEvent::Progress {
+            current: 100,
+            total: 100,
+            message: "Extraction complete".to_string(),
+        },
+        ExtractProgress::Cancelled => PipelineEvent::Status("Extraction cancelled".to_string()),
+        ExtractProgress::Error(e) => PipelineEvent::Status(format!("Extract error: {}", e)),
+    }
+}
+
+fn card_verify_event(progress: CardVerifyProgress) -> PipelineEvent {
+    match progress {
+        CardVerifyProgress::Started { total_bytes, total_files } => PipelineEvent::Progress {
+            current: 0,
+            total: total_bytes,
+            message: format!("Verifying {} files...", total_files),
+        },
+        CardVerifyProgress::Progress { checked_bytes, total_bytes, current_file } => {
+            let pct = percent(checked_bytes, total_bytes);
+            let message = if current_file.is_empty() {
+                "Looking for extra files...".to_string()
+            } else {
+                format!("{}% - {}", pct, truncate_path(&current_file, 40))
+            };
+            PipelineEvent::Progress { current: checked_bytes, total: total_bytes, message }
+        }
+        CardVerifyProgress::Completed => PipelineEvent::Status("Verification complete".to_string()),
+        CardVerifyProgress::Cancelled => PipelineEvent::Status("Verification cancelled".to_string()),
+    }
+}
+
+fn copy_event(progress: CopyProgress) -> PipelineEvent {
+    match progress {
+        CopyProgress::Counting => PipelineEvent::Status("Counting files...".to_string()),
+        CopyProgress::Started { total_bytes, total_files } => PipelineEvent::Progress {
+            current: 0,
+            total: total_bytes,
+            message: format!("Copying {} files...", total_files),
+        },
+        CopyProgress::Progress { copied_bytes, total_bytes, current_file } => {
+            let pct = percent(copied_bytes, total_bytes);
+            let message = if current_file.is_empty() {
+                format!("Copying... {}%", pct)
+            } else {
+                format!("{}% - {}", pct, truncate_path(&current_file, 40))
+            };
+            PipelineEvent::Progress { current: copied_bytes, total: total_bytes, message }
+        }
+        CopyProgress::Completed => PipelineEvent::Status("Copy complete".to_string()),
+        CopyProgress::Cancelled => PipelineEvent::Status("Copy cancelled".to_string()),
+        CopyProgress::Error(e) => PipelineEvent::Status(format!("Copy error: {}", e)),
+    }
+}
+
+fn percent(current: u64, total: u64) -> u64 {
+    (current.min(total) * 100).checked_div(total).unwrap_or(0)
+}
+
+/// Shorten a path for display, keeping the end (the file name) visible
+fn truncate_path(path: &str, max_chars: usize) -> String {
+    let count = path.chars().count();
+    if count <= max_chars {
+        return path.to_string();
+    }
+    let tail: String = path.chars().skip(count - (max_chars - 3)).collect();
+    format!("...{}", tail)
+}
+
+// -----------------------------------------------------------------------------
+// Paths and disk space
+// -----------------------------------------------------------------------------
+
+/// Where the archive is extracted before copying
+/// On Linux, use the same temp_dir we already determined
+/// On macOS, give cache_dir() another try (original behavior)
+fn extract_base_dir(temp_dir: &Path) -> PathBuf {
+    #[cfg(target_os = "macos")]
+    {
+        dirs::cache_dir().unwrap_or_else(|| temp_dir.to_path_buf())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        temp_dir.to_path_buf()
+    }
+}
+
+/// Get available disk space for a given path (in bytes)
+pub fn get_available_disk_space(path: &Path) -> u64 {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+        let path_wide: Vec<u16> = path.as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+
+        let mut free_bytes = 0u64;
+        unsafe {
+            if GetDiskFreeSpaceExW(
+                windows::core::PCWSTR(path_wide.as_ptr()),
+                None,
+                None,
+                Some(&mut free_bytes),
+            ).is_ok() {
+                return free_bytes;
+            }
+        }
+        crate::debug::log("WARNING: Failed to get disk space on Windows, assuming sufficient space");
+        u64::MAX // Assume sufficient space if we can't check
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let path_cstr = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap_or_default();
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+        unsafe {
+            if libc::statvfs(path_cstr.as_ptr(), &mut stat) == 0 {
+                // Available space = block size * available blocks
+                // Cast both to u64 to handle platforms where they're u32 (macOS, ARM32)
+                return (stat.f_bavail as u64) * (stat.f_bsize as u64);
+            }
+        }
+        crate::debug::log("WARNING: Failed to get disk space on Unix, assuming sufficient space");
+        u64::MAX // Assume sufficient space if we can't check
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+    {
+        crate::debug::log("WARNING: Disk space check not supported on this platform");
+        u64::MAX // Assume sufficient space on unsupported platforms
+    }
+}
+
+// -----------------------------------------------------------------------------
+// Mounting after format (Linux writes the card without mounting it)
+// -----------------------------------------------------------------------------
+
+/// Get the mount path after formatting, handling platform differences
+#[cfg(target_os = "windows")]
+async fn get_mount_path_after_format(drive: &DriveInfo, _volume_label: &str) -> Result<PathBuf, String> {
+    // On Windows, the drive letter remains the same after formatting
+    // The mount_path should be set (e.g., "E:\")
+    drive.mount_path.clone().ok_or_else(|| {
+        format!("No mount path available for drive {}", drive.name)
+    })
+}
+
+#[cfg(target_os = "macos")]
+async fn get_mount_path_after_format(_drive: &DriveInfo, volume_label: &str) -> Result<PathBuf, String> {
+    // macOS automatically mounts at /Volumes/LABEL after diskutil eraseDisk
+    // Wait a moment for the mount to complete
+    tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+
+    let mount_path = PathBuf::from(format!("/Volumes/{}", volume_label));
+
+    // Wait for the mount point to appear (up to 10 seconds)
+    for _ in 0..20 {
+        if mount_path.exists() {
+            return Ok(mount_path);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    }
+
+    Err(format!("Mount point {} did not appear after formatting", mount_path.display()))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
+async fn get_mount_path_after_format(_drive: &DriveInfo, _volume_label: &str) -> Result<PathBuf, String> {
+    Err("Mounting not supported on this platform".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_zip::{release_zip, RELEASE_FILES};
+
+    fn test_pipeline() -> (InstallPipeline, mpsc::UnboundedReceiver<PipelineEvent>, CancellationToken) {
+        let drive = DriveInfo {
+            name: "test".to_string(),
+            device_path: "/dev/null".to_string(),
+            mount_path: None,
+            label: String::new(),
+            size_bytes: 0,
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        let cancel_token = CancellationToken::new();
+        let pipeline = InstallPipeline::new(
+            InstallOptions::new(drive, "Test", "owner/repo"),
+            tx,
+            cancel_token.clone(),
+        );
+        (pipeline, rx, cancel_token)
+    }
+
+    fn drain(rx: &mut mpsc::UnboundedReceiver<PipelineEvent>) -> Vec<PipelineEvent> {
+        let mut events = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_copy_stage() {
+        let source = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(source.path().join(".system")).unwrap();
+        std::fs::write(source.path().join(".system/version.txt"), b"v1").unwrap();
+
+        let (pipeline, mut rx, _) = test_pipeline();
+        pipeline.copy_files(source.path(), dest.path()).await.unwrap();
+
+        assert_eq!(std::fs::read(dest.path().join(".system/version.txt")).unwrap(), b"v1");
+        let events = drain(&mut rx);
+        assert_eq!(events.first(), Some(&PipelineEvent::StageStarted(InstallStage::Copying)));
+        assert!(events.contains(&PipelineEvent::Status("Copy complete".to_string())));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_stage_reports_cancelled() {
+        let source = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("file.txt"), b"data").unwrap();
+
+        let (pipeline, _rx, cancel_token) = test_pipeline();
+        cancel_token.cancel();
+        let result = pipeline.copy_files(source.path(), dest.path()).await;
+
+        assert_eq!(result, Err(PipelineError::Cancelled));
+        assert!(!dest.path().join("file.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn test_extract_stage_writes_to_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = release_zip(dir.path(), &[(".system/version.txt", b"v1")]);
+        let card = dir.path().join("card");
+        std::fs::create_dir_all(&card).unwrap();
+
+        let (pipeline, mut rx, _) = test_pipeline();
+        let report = pipeline.extract(&archive, &card, &[]).await.unwrap();
+
+        assert_eq!(std::fs::read(card.join(".system/version.txt")).unwrap(), b"v1");
+        assert!(pipeline.workspace.extract_dir.is_none());
+        let events = drain(&mut rx);
+        assert_eq!(events.first(), Some(&PipelineEvent::StageStarted(InstallStage::Extracting)));
+
+        // A local archive has no published digest, so the manifest records its own hash
+        pipeline.write_manifest(&card, &archive, None, report.files).await;
+        let json = std::fs::read_to_string(card.join(crate::manifest::MANIFEST_FILE_NAME)).unwrap();
+        let manifest: Manifest = serde_json::from_str(&json).unwrap();
+        let (hash_tx, _hash_rx) = mpsc::unbounded_channel();
+        assert_eq!(manifest.asset, "release.zip");
+        assert_eq!(manifest.asset_sha256, hash_file(&archive, hash_tx, CancellationToken::new()).await.unwrap());
+        assert_eq!(manifest.tag, None);
+        assert_eq!(manifest.files.len(), 1);
+        assert_eq!((manifest.files[0].path.as_str(), manifest.files[0].size), (".system/version.txt", 2));
+
+        pipeline.verify_card(&card, &manifest.files).await.unwrap();
+        std::fs::write(card.join(".system/version.txt"), b"v2").unwrap();
+        let result = pipeline.verify_card(&card, &manifest.files).await;
+        assert!(matches!(result, Err(PipelineError::Failed { stage: InstallStage::VerifyingCard, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_lists_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut pipeline, mut rx, _) = test_pipeline();
+        pipeline.options.local_archive = Some(release_zip(dir.path(), RELEASE_FILES));
+        pipeline.options.mode = InstallMode::Download;
+        pipeline.run().await.unwrap();
+
+        let listed = drain(&mut rx).into_iter().find_map(|event| match event {
+            PipelineEvent::Components(components) => Some(components),
+            _ => None,
+        });
+        let names: Vec<_> = listed.unwrap().into_iter().map(|c| c.item.name).collect();
+        assert_eq!(names, [".system/", "MinUI.zip", "Tools/"]);
+    }
+
+    #[tokio::test]
+    async fn test_repair_restores_damaged_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = release_zip(dir.path(), RELEASE_FILES);
+        let card = dir.path().join("card");
+        let repair_pipeline = || {
+            let (mut pipeline, rx, _) = test_pipeline();
+            pipeline.options.mode = InstallMode::Repair;
+            pipeline.options.local_archive = Some(archive.clone());
+            pipeline.options.drive.mount_path = Some(card.clone());
+            (pipeline, rx)
+        };
+
+        let (pipeline, _rx) = repair_pipeline();
+        let report = pipeline.extract(&archive, &card, &[]).await.unwrap();
+        pipeline.write_manifest(&card, &archive, None, report.files).await;
+        std::fs::write(card.join(".system/version.txt"), b"v0.99").unwrap();
+        std::fs::remove_file(card.join("MinUI.zip")).unwrap();
+        std::fs::write(card.join("Tools/notes.txt"), b"mine").unwrap();
+
+        pipeline.run().await.unwrap();
+        assert_eq!(std::fs::read(card.join(".system/version.txt")).unwrap(), b"v1.00");
+        assert_eq!(std::fs::read(card.join("MinUI.zip")).unwrap(), b"minui");
+        assert_eq!(std::fs::read(card.join("Tools/notes.txt")).unwrap(), b"mine");
+
+        // Nothing is downloaded or written once the card matches its manifest again
+        let (pipeline, mut rx) = repair_pipeline();
+        pipeline.run().await.unwrap();
+        let events = drain(&mut rx);
+        assert!(events.contains(&PipelineEvent::Log("Nothing to repair: every installed file is intact".to_string())));
+        assert!(!events.contains(&PipelineEvent::StageStarted(InstallStage::Extracting)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_install_without_mounting() {
+        use std::io::Read;
+        let dir = tempfile::tempdir().unwrap();
+        let card_bytes = 64 * 1024 * 1024;
+        // A plain file stands in for the card's device
+        let device = dir.path().join("card.img");
+        std::fs::File::create(&device).unwrap().set_len(card_bytes).unwrap();
+
+        let archive = release_zip(dir.path(), RELEASE_FILES);
+
+        // Straight from the archive, and through the temp folder
+        let mut manifest = None;
+        for extract_to_card in [true, false] {
+            let (mut pipeline, mut rx, _) = test_pipeline();
+            pipeline.options.drive.device_path = device.display().to_string();
+            pipeline.options.drive.size_bytes = card_bytes;
+            pipeline.options.local_archive = Some(archive.clone());
+            pipeline.options.extract_to_card = extract_to_card;
+            pipeline.run().await.unwrap();
+            let events = drain(&mut rx);
+            assert!(events.contains(&PipelineEvent::StageStarted(InstallStage::VerifyingCard)));
+            assert_eq!(events.contains(&PipelineEvent::StageStarted(InstallStage::Copying)), !extract_to_card);
+
+            let disk = std::fs::File::open(&device).unwrap();
+            let mut reader = fat32::VolumeReader::open(disk, fat32::PARTITION_OFFSET).unwrap();
+            let mut read = |path: &str| {
+                let mut data = Vec::new();
+                reader.open_file(path).unwrap().unwrap().read_to_end(&mut data).unwrap();
+                data
+            };
+            assert_eq!(read(".system/version.txt"), b"v1.00");
+            // The log on the card is written after the check, so it has the result
+            let log = String::from_utf8_lossy(&read(crate::debug::CARD_LOG_NAME)).to_string();
+            assert!(log.contains("Checked 3 files: 0 missing, 0 corrupted"), "{}", log);
+            let read_manifest: Manifest = serde_json::from_slice(&read(MANIFEST_FILE_NAME)).unwrap();
+            assert_eq!(read_manifest.files.len(), 3);
+            manifest = Some(read_manifest);
+        }
+        let manifest = manifest.unwrap();
+
+        // Data the card lost fails the check
+        let mut image = std::fs::read(&device).unwrap();
+        let at = image.windows(5).position(|data| data == b"v1.00").unwrap();
+        image[at] = b'V';
+        std::fs::write(&device, image).unwrap();
+        let (pipeline, _rx, _) = test_pipeline();
+        let result = pipeline.verify_unmounted(&device, &manifest.files).await;
+        assert!(matches!(result, Err(PipelineError::Failed { stage: InstallStage::VerifyingCard, .. })));
+    }
+
+    #[tokio::test]
+    async fn test_build_image() {
+        use std::io::Read;
+        let dir = tempfile::tempdir().unwrap();
+        let archive = release_zip(dir.path(), RELEASE_FILES);
+        let build = |image: &ImageTarget| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            let mut options = InstallOptions::for_image(image.clone(), "Test", "owner/repo");
+            options.local_archive = Some(archive.clone());
+            (InstallPipeline::new(options, tx, CancellationToken::new()), rx)
+        };
+
+        let image = ImageTarget::new(dir.path().join("nextui.img"), 64 * 1024 * 1024, true);
+        let (pipeline, mut rx) = build(&image);
+        pipeline.run().await.unwrap();
+        assert!(drain(&mut rx).contains(&PipelineEvent::StageStarted(InstallStage::Compressing)));
+        assert!(!image.path.exists());
+
+        let mut data = Vec::new();
+        let gz = std::fs::File::open(image.output_path()).unwrap();
+        flate2::read::GzDecoder::new(gz).read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 64 * 1024 * 1024);
+        let volume = std::io::Cursor::new(data.split_off(fat32::PARTITION_OFFSET as usize));
+        let fs = fatfs::FileSystem::new(volume, fatfs::FsOptions::new()).unwrap();
+        let read = |path: &str| {
+            let mut contents = String::new();
+            fs.root_dir().open_file(path).unwrap().read_to_string(&mut contents).unwrap();
+            contents
+        };
+        assert_eq!(read(".system/version.txt"), "v1.00");
+        let manifest: Manifest = serde_json::from_str(&read(MANIFEST_FILE_NAME)).unwrap();
+        assert_eq!(manifest.asset, "release.zip");
+        assert_eq!(manifest.files.len(), 3);
+
+        // A build that fails leaves no image behind
+        let small = ImageTarget::new(dir.path().join("small.img"), 16 * 1024 * 1024, false);
+        let (pipeline, _rx) = build(&small);
+        let result = pipeline.run().await;
+        assert!(matches!(result, Err(PipelineError::Failed { stage: InstallStage::Formatting, .. })));
+        assert!(!small.path.exists());
+    }
+
+    #[test]
+    fn test_check_archive_refuses_small_card() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = release_zip(dir.path(), &[("MinUI.zip", b"minui")]);
+
+        let (mut pipeline, mut rx, _) = test_pipeline();
+        pipeline.check_archive(&archive, dir.path(), None).unwrap();
+        assert!(drain(&mut rx).contains(&PipelineEvent::Log("  MinUI.zip (1 files, 0.0 MB)".to_string())));
+
+        // Smaller than the partition alignment alone
+        pipeline.options.drive.size_bytes = 512 * 1024;
+        let result = pipeline.check_archive(&archive, dir.path(), None);
+        assert!(matches!(result, Err(PipelineError::Failed { stage: InstallStage::CheckingArchive, .. })));
+    }
+
+    #[test]
+    fn test_workspace_cleanup() {
+        let dir = tempfile::tempdir().unwrap();
+        let download = dir.path().join("release.zip");
+        let extract = dir.path().join("extract");
+        std::fs::write(&download, b"zip").unwrap();
+        std::fs::create_dir_all(extract.join("nested")).unwrap();
+
+        let mut workspace = Workspace {
+            download_path: Some(download.clone()),
+            extract_dir: Some(extract.clone()),
+        };
+        workspace.cleanup();
+
+        assert!(!download.exists());
+        assert!(!extract.exists());
+    }
+
+    #[test]
+    fn test_truncate_path() {
+        assert_eq!(truncate_path("short.txt", 40), "short.txt");
+        let long = format!("{}/file.txt", "a".repeat(50));
+        let truncated = truncate_path(&long, 40);
+        assert_eq!(truncated.chars().count(), 40);
+        assert!(truncated.starts_with("...") && truncated.ends_with("file.txt"));
+    }
+}
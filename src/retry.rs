@@ -0,0 +1,194 @@
+// Retrying transient network failures
+// Timeouts, dropped connections and 5xx answers are retried with exponential backoff
+// and jitter; anything else (404, bad token, full disk) fails straight away.
+
+use crate::config::NETWORK_RETRY_ATTEMPTS;
+use std::future::Future;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+/// Shorter waits under test so retry tests stay fast
+const fn delay_ms(ms: u64) -> Duration {
+    if cfg!(test) {
+        Duration::from_millis(ms / 1000)
+    } else {
+        Duration::from_millis(ms)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one
+    pub max_attempts: u32,
+    /// Wait before the second attempt; doubles for every attempt after that
+    pub base_delay: Duration,
+    /// Upper bound for a single wait
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Release metadata (small requests, fail fast)
+    pub const API: RetryPolicy = RetryPolicy {
+        max_attempts: NETWORK_RETRY_ATTEMPTS,
+        base_delay: delay_ms(1000),
+        max_delay: delay_ms(10_000),
+    };
+
+    /// Release downloads (each retry resumes where the last attempt stopped)
+    pub const DOWNLOAD: RetryPolicy = RetryPolicy {
+        max_attempts: NETWORK_RETRY_ATTEMPTS,
+        base_delay: delay_ms(2000),
+        max_delay: delay_ms(30_000),
+    };
+
+    /// Wait after failed attempt `attempt` (1-based): base * 2^(attempt-1), capped,
+    /// then a random amount between half and all of it so clients don't retry in lockstep
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let capped = exponential.min(self.max_delay);
+        let half = capped / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// How an attempt failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// Worth another try (timeout, dropped connection, 5xx)
+    Transient(String),
+    /// Retrying won't help
+    Permanent(String),
+}
+
+/// Sent before waiting for the next attempt
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryNotice {
+    /// The attempt about to start (2 for the first retry)
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub delay: Duration,
+    /// Why the previous attempt failed
+    pub error: String,
+}
+
+impl RetryNotice {
+    /// e.g. "Retrying in 4s (attempt 2 of 5)..."
+    pub fn status(&self) -> String {
+        format!(
+            "Retrying in {}s (attempt {} of {})...",
+            self.delay.as_secs_f64().ceil() as u64,
+            self.attempt,
+            self.max_attempts
+        )
+    }
+}
+
+/// Run `attempt` (called with the 1-based attempt number) until it succeeds, fails
+/// permanently or runs out of attempts. `on_retry` hears about each retry before the
+/// wait; cancelling `cancel_token` ends the wait early.
+pub async fn retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    cancel_token: &CancellationToken,
+    mut on_retry: impl FnMut(&RetryNotice),
+    mut attempt: F,
+) -> Result<T, String>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    let mut number = 1;
+    loop {
+        let error = match attempt(number).await {
+            Ok(value) => return Ok(value),
+            Err(Failure::Permanent(e)) => return Err(e),
+            Err(Failure::Transient(e)) if number >= policy.max_attempts || cancel_token.is_cancelled() => {
+                return Err(e)
+            }
+            Err(Failure::Transient(e)) => e,
+        };
+
+        let notice = RetryNotice {
+            attempt: number + 1,
+            max_attempts: policy.max_attempts,
+            delay: policy.delay(number),
+            error,
+        };
+        crate::debug::log(&format!(
+            "Attempt {} of {} failed: {}. Retrying in {:?}",
+            number, policy.max_attempts, notice.error, notice.delay
+        ));
+        on_retry(&notice);
+
+        tokio::select! {
+            _ = cancel_token.cancelled() => return Err(notice.error),
+            _ = tokio::time::sleep(notice.delay) => {}
+        }
+        number += 1;
+    }
+}
+
+/// Classify a reqwest error: network trouble is transient, everything else is not
+pub fn classify_request_error(e: &reqwest::Error, message: String) -> Failure {
+    if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode() {
+        Failure::Transient(message)
+    } else {
+        Failure::Permanent(message)
+    }
+}
+
+/// Server errors and "slow down" answers are worth retrying
+pub fn is_transient_status(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_backs_off_with_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+        };
+        for _ in 0..50 {
+            let first = policy.delay(1);
+            assert!(first >= Duration::from_secs(1) && first <= Duration::from_secs(2));
+            let third = policy.delay(3);
+            assert!(third >= Duration::from_secs(4) && third <= Duration::from_secs(8));
+            assert!(policy.delay(30) <= Duration::from_secs(10));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_permanent_failure_and_gives_up() {
+        let token = CancellationToken::new();
+        let mut notices = Vec::new();
+
+        let result = retry(&RetryPolicy::API, &token, |n| notices.push(n.attempt), |attempt| async move {
+            match attempt {
+                1 | 2 => Err(Failure::Transient(format!("timeout {}", attempt))),
+                _ => Ok(attempt),
+            }
+        })
+        .await;
+        assert_eq!(result, Ok(3));
+        assert_eq!(notices, vec![2, 3]);
+
+        let result: Result<(), String> =
+            retry(&RetryPolicy::API, &token, |_| {}, |_| async { Err(Failure::Permanent("404".to_string())) }).await;
+        assert_eq!(result, Err("404".to_string()));
+
+        let mut attempts = 0;
+        let result: Result<(), String> = retry(&RetryPolicy::API, &token, |_| {}, |_| {
+            attempts += 1;
+            async { Err(Failure::Transient("503".to_string())) }
+        })
+        .await;
+        assert_eq!(result, Err("503".to_string()));
+        assert_eq!(attempts, NETWORK_RETRY_ATTEMPTS);
+    }
+}
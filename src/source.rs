@@ -0,0 +1,338 @@
+// Release sources
+// The repository strings in config::REPO_OPTIONS pick where releases come from:
+//
+//   "owner/repo" or "https://github.com/owner/repo"     GitHub
+//   "github:https://ghe.example.com/api/v3/owner/repo"  GitHub Enterprise (API base + owner/repo)
+//   "gitea:https://git.example.com/owner/repo"          Gitea or Forgejo ("forgejo:" works too)
+//   "index:https://mirror.example.com/releases.json"    Static JSON index over plain HTTP
+//
+// Gitea/Forgejo serve the same release JSON shape as GitHub. The static index is
+// {"releases": [<GitHub-style release>, ...]}, newest first; asset URLs may be
+// relative to the index.
+
+use crate::config::RELEASE_LIST_LIMIT;
+use crate::github::{api_token, fetch_json, find_release_asset, parse_github_url, Release};
+use crate::retry::RetryNotice;
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+/// API base of github.com, the only host the GitHub token is sent to
+const GITHUB_API_BASE: &str = "https://api.github.com";
+
+/// Told about each retry of a failed request (see retry::retry)
+pub type OnRetry<'a> = &'a (dyn Fn(&RetryNotice) + Send + Sync);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReleaseSource {
+    GitHub { api_base: String, owner: String, repo: String },
+    Gitea { api_base: String, owner: String, repo: String },
+    Index { url: String },
+}
+
+#[derive(Debug, Deserialize)]
+struct ReleaseIndex {
+    releases: Vec<Release>,
+}
+
+impl ReleaseSource {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let spec = spec.trim();
+
+        if let Some(url) = spec.strip_prefix("index:") {
+            reqwest::Url::parse(url).map_err(|e| format!("Invalid release index URL '{}': {}", url, e))?;
+            return Ok(ReleaseSource::Index { url: url.to_string() });
+        }
+
+        if let Some(url) = spec.strip_prefix("gitea:").or_else(|| spec.strip_prefix("forgejo:")) {
+            let (base, owner, repo) = split_repo_url(url)?;
+            return Ok(ReleaseSource::Gitea {
+                api_base: format!("{}/api/v1", base),
+                owner,
+                repo,
+            });
+        }
+
+        if let Some(url) = spec.strip_prefix("github:") {
+            let (api_base, owner, repo) = split_repo_url(url)?;
+            return Ok(ReleaseSource::GitHub { api_base, owner, repo });
+        }
+
+        let (owner, repo) = parse_github_url(spec)?;
+        Ok(ReleaseSource::GitHub {
+            api_base: GITHUB_API_BASE.to_string(),
+            owner,
+            repo,
+        })
+    }
+
+    /// Name of the host for messages ("GitHub", "Gitea", "release index")
+    pub fn service_name(&self) -> &'static str {
+        match self {
+            ReleaseSource::GitHub { .. } => "GitHub",
+            ReleaseSource::Gitea { .. } => "Gitea",
+            ReleaseSource::Index { .. } => "release index",
+        }
+    }
+
+    /// The newest release that isn't a pre-release
+    pub async fn latest_release(&self, cancel_token: &CancellationToken, on_retry: OnRetry<'_>) -> Result<Release, String> {
+        match self {
+            ReleaseSource::GitHub { .. } | ReleaseSource::Gitea { .. } => {
+                self.fetch_api(&self.api_url("releases/latest"), "No published release found", cancel_token, on_retry).await
+            }
+            ReleaseSource::Index { .. } => {
+                let releases = self.fetch_index(cancel_token, on_retry).await?;
+                // An index of nothing but pre-releases (nightlies) still has a latest
+                releases
+                    .iter()
+                    .find(|r| !r.prerelease)
+                    .or(releases.first())
+                    .cloned()
+                    .ok_or_else(|| "The release index is empty".to_string())
+            }
+        }
+    }
+
+    /// A single release by its tag (e.g. "v6.3.0")
+    pub async fn release_by_tag(
+        &self,
+        tag: &str,
+        cancel_token: &CancellationToken,
+        on_retry: OnRetry<'_>,
+    ) -> Result<Release, String> {
+        let not_found = format!("Release '{}' not found in {}", tag, self);
+        match self {
+            ReleaseSource::GitHub { .. } | ReleaseSource::Gitea { .. } => {
+                self.fetch_api(&self.tag_url(tag)?, &not_found, cancel_token, on_retry).await
+            }
+            ReleaseSource::Index { .. } => self
+                .fetch_index(cancel_token, on_retry)
+                .await?
+                .into_iter()
+                .find(|r| r.tag_name == tag)
+                .ok_or(not_found),
+        }
+    }
+
+    /// Recent releases, newest first, including pre-releases
+    /// Releases without an installable asset are left out
+    pub async fn list_releases(&self, cancel_token: &CancellationToken, on_retry: OnRetry<'_>) -> Result<Vec<Release>, String> {
+        let releases: Vec<Release> = match self {
+            ReleaseSource::GitHub { .. } => {
+                let url = self.api_url(&format!("releases?per_page={}", RELEASE_LIST_LIMIT));
+                self.fetch_api(&url, "Repository not found", cancel_token, on_retry).await?
+            }
+            ReleaseSource::Gitea { .. } => {
+                let url = self.api_url(&format!("releases?limit={}", RELEASE_LIST_LIMIT));
+                self.fetch_api(&url, "Repository not found", cancel_token, on_retry).await?
+            }
+            ReleaseSource::Index { .. } => self.fetch_index(cancel_token, on_retry).await?,
+        };
+
+        Ok(releases
+            .into_iter()
+            .filter(|release| find_release_asset(release).is_some())
+            .take(RELEASE_LIST_LIMIT)
+            .collect())
+    }
+
+    /// API URL for `path` under /repos/{owner}/{repo}/ (GitHub and Gitea share the layout)
+    fn api_url(&self, path: &str) -> String {
+        match self {
+            ReleaseSource::GitHub { api_base, owner, repo } | ReleaseSource::Gitea { api_base, owner, repo } => {
+                format!("{}/repos/{}/{}/{}", api_base, owner, repo, path)
+            }
+            ReleaseSource::Index { url } => url.clone(),
+        }
+    }
+
+    /// API URL of the release tagged `tag`. The tag is escaped as a single path
+    /// segment, since tags may contain '/', '#' or '?'
+    fn tag_url(&self, tag: &str) -> Result<String, String> {
+        let invalid = |e: String| format!("Invalid API URL for {}: {}", self, e);
+        let mut url = reqwest::Url::parse(&self.api_url("releases/tags")).map_err(|e| invalid(e.to_string()))?;
+        url.path_segments_mut()
+            .map_err(|_| invalid("not a hierarchical URL".to_string()))?
+            .push(tag);
+        Ok(url.to_string())
+    }
+
+    /// GET JSON from this source. The GitHub token is only sent to api.github.com; a
+    /// custom `github:` base (GitHub Enterprise) is another host and gets no token
+    async fn fetch_api<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        not_found: &str,
+        cancel_token: &CancellationToken,
+        on_retry: OnRetry<'_>,
+    ) -> Result<T, String> {
+        let token = match self {
+            ReleaseSource::GitHub { api_base, .. } if api_base == GITHUB_API_BASE => api_token(),
+            _ => None,
+        };
+        fetch_json(url, not_found, self.service_name(), token.as_deref(), cancel_token, on_retry).await
+    }
+
+    /// Fetch the static index and make relative asset URLs absolute
+    async fn fetch_index(&self, cancel_token: &CancellationToken, on_retry: OnRetry<'_>) -> Result<Vec<Release>, String> {
+        let ReleaseSource::Index { url } = self else {
+            return Err("Not a release index".to_string());
+        };
+        let mut index: ReleaseIndex = self.fetch_api(url, "Release index not found", cancel_token, on_retry).await?;
+
+        let base = reqwest::Url::parse(url).map_err(|e| format!("Invalid release index URL: {}", e))?;
+        for asset in index.releases.iter_mut().flat_map(|r| r.assets.iter_mut()) {
+            if let Ok(absolute) = base.join(&asset.browser_download_url) {
+                asset.browser_download_url = absolute.to_string();
+            }
+        }
+        Ok(index.releases)
+    }
+}
+
+impl std::fmt::Display for ReleaseSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReleaseSource::GitHub { owner, repo, .. } | ReleaseSource::Gitea { owner, repo, .. } => {
+                write!(f, "{} ({}/{})", self.service_name(), owner, repo)
+            }
+            ReleaseSource::Index { url } => write!(f, "release index ({})", url),
+        }
+    }
+}
+
+/// "https://host/some/base/owner/repo" -> ("https://host/some/base", "owner", "repo")
+fn split_repo_url(url: &str) -> Result<(String, String, String), String> {
+    let trimmed = url.trim().trim_end_matches('/');
+    let trimmed = trimmed.strip_suffix(".git").unwrap_or(trimmed);
+    let invalid = || format!("Invalid repository URL '{}'. Use https://host/owner/repo", url);
+
+    if !trimmed.starts_with("http://") && !trimmed.starts_with("https://") {
+        return Err(invalid());
+    }
+    let (rest, repo) = trimmed.rsplit_once('/').ok_or_else(invalid)?;
+    let (base, owner) = rest.rsplit_once('/').ok_or_else(invalid)?;
+    if base.ends_with('/') || base.ends_with(':') || owner.is_empty() || repo.is_empty() {
+        return Err(invalid());
+    }
+    Ok((base.to_string(), owner.to_string(), repo.to_string()))
+}
+
+/// Releases for a REPO_OPTIONS repository string (retries only go to the debug log)
+pub async fn list_releases(spec: &str) -> Result<Vec<Release>, String> {
+    ReleaseSource::parse(spec)?.list_releases(&CancellationToken::new(), &|_| {}).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{Response, TestServer};
+
+    fn release_json(tag: &str, prerelease: bool, asset_url: &str) -> String {
+        format!(
+            r#"{{"tag_name": "{}", "name": null, "prerelease": {}, "published_at": "2025-02-01T10:00:00Z",
+                "assets": [{{"name": "NextUI-{}.zip", "size": 1024, "browser_download_url": "{}"}}]}}"#,
+            tag, prerelease, tag, asset_url
+        )
+    }
+
+    #[test]
+    fn test_parse_sources() {
+        assert_eq!(
+            ReleaseSource::parse("LoveRetro/NextUI").unwrap(),
+            ReleaseSource::GitHub {
+                api_base: "https://api.github.com".to_string(),
+                owner: "LoveRetro".to_string(),
+                repo: "NextUI".to_string(),
+            }
+        );
+        assert_eq!(
+            ReleaseSource::parse("github:https://ghe.example.com/api/v3/team/os").unwrap(),
+            ReleaseSource::GitHub {
+                api_base: "https://ghe.example.com/api/v3".to_string(),
+                owner: "team".to_string(),
+                repo: "os".to_string(),
+            }
+        );
+        assert_eq!(
+            ReleaseSource::parse("forgejo:https://codeberg.org/owner/repo.git").unwrap(),
+            ReleaseSource::Gitea {
+                api_base: "https://codeberg.org/api/v1".to_string(),
+                owner: "owner".to_string(),
+                repo: "repo".to_string(),
+            }
+        );
+        assert!(ReleaseSource::parse("gitea:https://git.example.com").is_err());
+        assert!(ReleaseSource::parse("index:not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_github_and_gitea_against_mock_server() {
+        let latest = release_json("v2", false, "https://example.com/v2.zip");
+        let list = format!("[{}, {}]", release_json("v3", true, "https://example.com/v3.zip"), latest);
+        let server = TestServer::start(move |request| match request.path.as_str() {
+            "/api/v3/repos/team/os/releases/latest" | "/api/v1/repos/team/os/releases/latest" => Response::json(&latest),
+            "/api/v3/repos/team/os/releases/tags/nightly%2F2025%3F%231" | "/api/v1/repos/team/os/releases/tags/nightly%2F2025%3F%231" => {
+                Response::json(&latest)
+            }
+            "/api/v3/repos/team/os/releases?per_page=30" | "/api/v1/repos/team/os/releases?limit=30" => Response::json(&list),
+            _ => Response::new(404, "not found"),
+        })
+        .await;
+
+        for spec in [
+            format!("github:{}/api/v3/team/os", server.url),
+            format!("gitea:{}/team/os", server.url),
+        ] {
+            let source = ReleaseSource::parse(&spec).unwrap();
+            assert_eq!(source.latest_release(&CancellationToken::new(), &|_| {}).await.unwrap().tag_name, "v2");
+
+            let tags: Vec<String> = source.list_releases(&CancellationToken::new(), &|_| {}).await.unwrap().into_iter().map(|r| r.tag_name).collect();
+            assert_eq!(tags, vec!["v3", "v2"]);
+
+            let err = source.release_by_tag("v0", &CancellationToken::new(), &|_| {}).await.unwrap_err();
+            assert!(err.contains("'v0' not found"), "{}", err);
+
+            // The tag is one path segment, whatever it contains
+            assert!(source.release_by_tag("nightly/2025?#1", &CancellationToken::new(), &|_| {}).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_github_base_gets_no_token() {
+        let latest = release_json("v1", false, "https://example.com/v1.zip");
+        let server = TestServer::start(move |_| Response::json(&latest)).await;
+
+        crate::github::set_api_token(Some("secret".to_string()));
+        let source = ReleaseSource::parse(&format!("github:{}/api/v3/team/os", server.url)).unwrap();
+        let result = source.latest_release(&CancellationToken::new(), &|_| {}).await;
+        crate::github::set_api_token(None);
+
+        assert_eq!(result.unwrap().tag_name, "v1");
+        assert_eq!(server.requests()[0].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn test_static_index() {
+        let index = format!(
+            r#"{{"releases": [{}, {}]}}"#,
+            release_json("nightly-2", true, "files/nightly-2.zip"),
+            release_json("v1", false, "https://cdn.example.com/v1.zip")
+        );
+        let server = TestServer::start(move |request| match request.path.as_str() {
+            "/mirror/releases.json" => Response::json(&index),
+            _ => Response::new(404, "not found"),
+        })
+        .await;
+
+        let source = ReleaseSource::parse(&format!("index:{}/mirror/releases.json", server.url)).unwrap();
+        assert_eq!(source.latest_release(&CancellationToken::new(), &|_| {}).await.unwrap().tag_name, "v1");
+
+        let nightly = source.release_by_tag("nightly-2", &CancellationToken::new(), &|_| {}).await.unwrap();
+        assert_eq!(
+            nightly.assets[0].browser_download_url,
+            format!("{}/mirror/files/nightly-2.zip", server.url)
+        );
+        assert_eq!(source.list_releases(&CancellationToken::new(), &|_| {}).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,171 @@
+// Minimal HTTP/1.1 stand-in for tests
+// Serves one response per connection from a handler closure and records every
+// request, so download and API code can be exercised without network access
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(&name.to_ascii_lowercase()).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Close the connection after this many body bytes (simulates a dropped download)
+    pub cut_after: Option<usize>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            cut_after: None,
+        }
+    }
+
+    pub fn json(body: &str) -> Self {
+        Self::new(200, body).header("Content-Type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+pub struct TestServer {
+    /// Base URL without a trailing slash, e.g. "http://127.0.0.1:41234"
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl TestServer {
+    pub async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler = Arc::new(handler);
+
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                tokio::spawn(async move {
+                    let Some(request) = read_request(&mut stream).await else {
+                        return;
+                    };
+                    recorded.lock().unwrap().push(request.clone());
+                    let response = handler(&request);
+
+                    let mut head = format!("HTTP/1.1 {} {}\r\n", response.status, reason(response.status));
+                    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n", response.body.len()));
+                    for (name, value) in &response.headers {
+                        head.push_str(&format!("{}: {}\r\n", name, value));
+                    }
+                    head.push_str("\r\n");
+
+                    let body_len = response.cut_after.unwrap_or(response.body.len()).min(response.body.len());
+                    let _ = stream.write_all(head.as_bytes()).await;
+                    if request.method != "HEAD" {
+                        let _ = stream.write_all(&response.body[..body_len]).await;
+                    }
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// Serve `body` like a static file host: honours Range/If-Range against `etag`
+/// unless `honor_ranges` is false, in which case ranges are ignored (200 + full body)
+pub fn file_response(request: &Request, body: &[u8], etag: &str, honor_ranges: bool) -> Response {
+    let full = Response::new(200, body).header("ETag", etag).header("Accept-Ranges", "bytes");
+
+    let Some(range) = request.header("range") else {
+        return full;
+    };
+    if !honor_ranges || request.header("if-range").is_some_and(|v| v != etag) {
+        return full;
+    }
+
+    let start: usize = range
+        .strip_prefix("bytes=")
+        .and_then(|r| r.strip_suffix('-'))
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(0);
+    if start >= body.len() {
+        return Response::new(416, Vec::new()).header("Content-Range", &format!("bytes */{}", body.len()));
+    }
+
+    Response::new(206, &body[start..])
+        .header("ETag", etag)
+        .header("Content-Range", &format!("bytes {}-{}/{}", start, body.len() - 1, body.len()))
+}
+
+async fn read_request(stream: &mut tokio::net::TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buffer.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+
+    let text = String::from_utf8_lossy(&buffer);
+    let mut lines = text.split("\r\n");
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+        .collect();
+
+    Some(Request { method, path, headers })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
@@ -0,0 +1,33 @@
+// Zip release archives for tests
+// One builder for every module's tests, so they all see archives written the same
+// way (deflated entries, explicit directory entries where asked for)
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The files of the small release most pipeline tests install
+pub const RELEASE_FILES: &[(&str, &[u8])] =
+    &[("MinUI.zip", b"minui"), (".system/version.txt", b"v1.00"), ("Tools/tool.elf", b"tools")];
+
+/// Write a zip with `files` to `path`. Names ending in '/' become directory entries
+/// (their data is ignored)
+pub fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, data) in files {
+        if let Some(dir) = name.strip_suffix('/') {
+            zip.add_directory(dir, options).unwrap();
+        } else {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+    }
+    zip.finish().unwrap();
+}
+
+/// A release archive with `files` in `dir`
+pub fn release_zip(dir: &Path, files: &[(&str, &[u8])]) -> PathBuf {
+    let archive = dir.join("release.zip");
+    write_zip(&archive, files);
+    archive
+}
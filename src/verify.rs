@@ -0,0 +1,344 @@
+// Card verification
+// Reads every installed file back from the card and compares its size and SHA-256
+// with what was recorded while installing (see manifest.rs). Cheap and counterfeit
+// cards can silently lose data, so a file that was written without an error may
+// still read back wrong. A card that was written without mounting it is read the
+// same way, through its FAT32 volume.
+
+use crate::archive::is_excluded;
+use crate::benchmark::BENCHMARK_FILE_NAME;
+use crate::checksum::to_hex;
+use crate::debug::CARD_LOG_NAME;
+use crate::fat32::VolumeReader;
+use crate::manifest::{InstalledFile, MANIFEST_FILE_NAME};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Files the installer itself leaves in the root of the card (the benchmark file only
+/// if a benchmark was interrupted)
+const INSTALLER_FILES: &[&str] = &[MANIFEST_FILE_NAME, CARD_LOG_NAME, "install_log.txt", BENCHMARK_FILE_NAME];
+
+/// Folders and files operating systems create on removable drives
+const SYSTEM_ENTRIES: &[&str] = &[
+    "System Volume Information",
+    "$RECYCLE.BIN",
+    ".Spotlight-V100",
+    ".fseventsd",
+    ".Trashes",
+    ".TemporaryItems",
+    ".DS_Store",
+];
+
+#[derive(Debug, Clone)]
+pub enum CardVerifyProgress {
+    Started { total_bytes: u64, total_files: u64 },
+    Progress { checked_bytes: u64, total_bytes: u64, current_file: String },
+    Completed,
+    Cancelled,
+}
+
+/// Result of comparing the card with the installed file list
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    /// Files that were read back
+    pub checked: usize,
+    pub missing: Vec<String>,
+    /// Files with a different size or contents, or that could not be read
+    pub corrupted: Vec<String>,
+    /// Files on the card that are not part of the install (user folders and files the
+    /// OS or the installer creates are not counted)
+    pub extra: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Extra files are reported but don't fail the check; they are usually leftovers
+    /// of an older release or the user's own
+    pub fn passed(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+/// Read every file in `files` back from `card_dir` and look for files that don't
+/// belong there; anything under `keep` (user data) is not reported as extra
+pub async fn verify_card(
+    card_dir: &Path,
+    files: &[InstalledFile],
+    keep: &[String],
+    progress_tx: mpsc::UnboundedSender<CardVerifyProgress>,
+    cancel_token: CancellationToken,
+) -> Result<VerifyReport, String> {
+    crate::debug::log_section("Verify Card");
+    crate::debug::log(&format!("Card: {:?}", card_dir));
+    crate::debug::log(&format!("Checking {} files", files.len()));
+
+    let (card, files, keep) = (card_dir.to_path_buf(), files.to_vec(), keep.to_vec());
+    let (tx, token) = (progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        let mut hash = |path: &str, buffer: &mut [u8]| hash_uncached(&card.join(path), buffer, &token);
+        let mut report = verify_blocking(&files, &mut hash, &tx, &token)?;
+
+        let installed: std::collections::HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        find_extra(&card, "", &installed, &keep, &mut report.extra)
+            .map_err(|e| format!("Failed to list {}: {}", card.display(), e))?;
+        report.extra.sort();
+        for path in &report.extra {
+            crate::debug::log(&format!("Extra: {}", path));
+        }
+        Ok(report)
+    })
+    .await
+    .map_err(|e| format!("Verification task failed: {}", e))?;
+    finish(result, &progress_tx, &cancel_token)
+}
+
+/// Read every file in `files` back from the FAT32 volume at `offset` of `device`,
+/// without mounting it (see fat32::VolumeReader)
+/// Only meant right after installing to a freshly formatted card, so files that
+/// don't belong there aren't looked for
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub async fn verify_volume(
+    device: &Path,
+    offset: u64,
+    files: &[InstalledFile],
+    progress_tx: mpsc::UnboundedSender<CardVerifyProgress>,
+    cancel_token: CancellationToken,
+) -> Result<VerifyReport, String> {
+    crate::debug::log_section("Verify Card");
+    crate::debug::log(&format!("Device: {:?}", device));
+    crate::debug::log(&format!("Checking {} files", files.len()));
+
+    let (device, files) = (device.to_path_buf(), files.to_vec());
+    let (tx, token) = (progress_tx.clone(), cancel_token.clone());
+    let result = tokio::task::spawn_blocking(move || {
+        let disk = open_uncached(&device).map_err(|e| format!("Failed to open {}: {}", device.display(), e))?;
+        let mut reader = VolumeReader::open(disk, offset)?;
+        let mut hash = |path: &str, buffer: &mut [u8]| match reader.open_file(path)? {
+            Some(mut file) => hash_reader(&mut file, buffer, &token).map(Some),
+            None => Ok(None),
+        };
+        verify_blocking(&files, &mut hash, &tx, &token)
+    })
+    .await
+    .map_err(|e| format!("Verification task failed: {}", e))?;
+    finish(result, &progress_tx, &cancel_token)
+}
+
+/// Log the outcome and tell the UI
+fn finish(
+    result: Result<VerifyReport, String>,
+    progress_tx: &mpsc::UnboundedSender<CardVerifyProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<VerifyReport, String> {
+    match &result {
+        Ok(report) => {
+            crate::debug::log(&format!(
+                "Checked {} files: {} missing, {} corrupted, {} extra",
+                report.checked,
+                report.missing.len(),
+                report.corrupted.len(),
+                report.extra.len()
+            ));
+            let _ = progress_tx.send(CardVerifyProgress::Completed);
+        }
+        Err(_) if cancel_token.is_cancelled() => {
+            let _ = progress_tx.send(CardVerifyProgress::Cancelled);
+        }
+        Err(_) => {}
+    }
+    result
+}
+
+/// Size and SHA-256 of a file as read back, None if it doesn't exist
+type HashFn<'a> = dyn FnMut(&str, &mut [u8]) -> std::io::Result<Option<(u64, String)>> + 'a;
+
+/// Check every file in `files` with `hash`
+fn verify_blocking(
+    files: &[InstalledFile],
+    hash: &mut HashFn,
+    progress_tx: &mpsc::UnboundedSender<CardVerifyProgress>,
+    cancel_token: &CancellationToken,
+) -> Result<VerifyReport, String> {
+    let total_bytes = files.iter().map(|f| f.size).sum();
+    let _ = progress_tx.send(CardVerifyProgress::Started { total_bytes, total_files: files.len() as u64 });
+
+    let mut report = VerifyReport::default();
+    let mut checked_bytes = 0u64;
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    for file in files {
+        if cancel_token.is_cancelled() {
+            return Err("Verification cancelled".to_string());
+        }
+        let _ = progress_tx.send(CardVerifyProgress::Progress {
+            checked_bytes,
+            total_bytes,
+            current_file: file.path.clone(),
+        });
+
+        match hash(&file.path, &mut buffer) {
+            Ok(None) => {
+                crate::debug::log(&format!("Missing: {}", file.path));
+                report.missing.push(file.path.clone());
+            }
+            Ok(Some((size, sha256))) => {
+                if size != file.size {
+                    crate::debug::log(&format!("Corrupted: {} ({} bytes, expected {})", file.path, size, file.size));
+                    report.corrupted.push(file.path.clone());
+                } else if sha256 != file.sha256 {
+                    crate::debug::log(&format!("Corrupted: {} (SHA-256 {}, expected {})", file.path, sha256, file.sha256));
+                    report.corrupted.push(file.path.clone());
+                }
+            }
+            Err(_) if cancel_token.is_cancelled() => return Err("Verification cancelled".to_string()),
+            Err(e) => {
+                crate::debug::log(&format!("Corrupted: {} (read error: {})", file.path, e));
+                report.corrupted.push(file.path.clone());
+            }
+        }
+        report.checked += 1;
+        checked_bytes += file.size;
+    }
+    let _ = progress_tx.send(CardVerifyProgress::Progress { checked_bytes, total_bytes, current_file: String::new() });
+    Ok(report)
+}
+
+/// Size and SHA-256 of a file as it is stored on the card, None if it doesn't exist
+fn hash_uncached(
+    path: &Path,
+    buffer: &mut [u8],
+    cancel_token: &CancellationToken,
+) -> std::io::Result<Option<(u64, String)>> {
+    let mut file = match open_uncached(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !file.metadata()?.is_file() {
+        return Ok(None);
+    }
+    hash_reader(&mut file, buffer, cancel_token).map(Some)
+}
+
+fn hash_reader(
+    reader: &mut dyn Read,
+    buffer: &mut [u8],
+    cancel_token: &CancellationToken,
+) -> std::io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    loop {
+        if cancel_token.is_cancelled() {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"));
+        }
+        let read = reader.read(buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, to_hex(&hasher.finalize())))
+}
+
+/// Open a file for reading so that the data comes from the card, not from what the
+/// OS still has cached from writing it
+/// Windows has no per-file equivalent that works with unaligned reads, so there a
+/// check right after installing may be served partly from memory
+fn open_uncached(path: &Path) -> std::io::Result<std::fs::File> {
+    let file = std::fs::File::open(path)?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        // Cached pages can only be dropped once they are on the card
+        unsafe {
+            libc::fdatasync(file.as_raw_fd());
+            libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::unix::io::AsRawFd;
+        unsafe {
+            libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1);
+        }
+    }
+    Ok(file)
+}
+
+/// Collect files below `card_dir/relative` that are not in `installed`
+fn find_extra(
+    card_dir: &Path,
+    relative: &str,
+    installed: &std::collections::HashSet<&str>,
+    keep: &[String],
+    extra: &mut Vec<String>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(card_dir.join(relative))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{}{}", relative, name);
+
+        let ignored = SYSTEM_ENTRIES.contains(&name.as_str())
+            || name.starts_with("._")
+            || (relative.is_empty() && INSTALLER_FILES.iter().any(|f| name.starts_with(f)))
+            || is_excluded(&path, keep);
+        if ignored {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            find_extra(card_dir, &format!("{}/", path), installed, keep, extra)?;
+        } else if !installed.contains(path.as_str()) {
+            extra.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_verify_card() {
+        let card = tempfile::tempdir().unwrap();
+        let file = |path: &str, data: &[u8]| {
+            let full = card.path().join(path);
+            std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+            std::fs::write(&full, data).unwrap();
+            InstalledFile { path: path.to_string(), size: data.len() as u64, sha256: to_hex(&Sha256::digest(data)) }
+        };
+        let files = vec![
+            file("MinUI.zip", b"minui"),
+            file(".system/bin/tool", b"tool"),
+            file(".system/res/font.ttf", b"font"),
+            file("Tools/readme.txt", b"tools"),
+        ];
+        file("Roms/GB/game.gb", b"rom");
+        file(".system/old.bin", b"left over");
+        file(MANIFEST_FILE_NAME, b"{}");
+        file("._MinUI.zip", b"apple double");
+        let keep = vec!["Roms/".to_string()];
+
+        let (tx, _rx) = mpsc::unbounded_channel();
+        let report = verify_card(card.path(), &files, &keep, tx.clone(), CancellationToken::new()).await.unwrap();
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(report.checked, 4);
+        assert_eq!(report.extra, vec![".system/old.bin"]);
+
+        // Same size, different contents; a shorter file; and a deleted one
+        std::fs::write(card.path().join(".system/bin/tool"), b"TOOL").unwrap();
+        std::fs::write(card.path().join(".system/res/font.ttf"), b"fo").unwrap();
+        std::fs::remove_file(card.path().join("Tools/readme.txt")).unwrap();
+        let report = verify_card(card.path(), &files, &keep, tx.clone(), CancellationToken::new()).await.unwrap();
+        assert!(!report.passed());
+        assert_eq!(report.corrupted, vec![".system/bin/tool", ".system/res/font.ttf"]);
+        assert_eq!(report.missing, vec!["Tools/readme.txt"]);
+
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(verify_card(card.path(), &files, &keep, tx, token).await.is_err());
+    }
+}